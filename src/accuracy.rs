@@ -0,0 +1,40 @@
+/// Accuracy preset, toggling groups of accuracy features consistently.
+///
+/// Options given explicitly (e.g. `--no-sprite-limit`) take precedence over
+/// the preset.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Accuracy {
+    /// Favor speed and convenience over accuracy
+    Fast,
+    /// Accurate enough for commercial games (default)
+    Balanced,
+    /// Closest to hardware, for test ROMs
+    Cycle,
+}
+
+impl Accuracy {
+    /// Returns a preset by its name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "fast" => Some(Accuracy::Fast),
+            "balanced" => Some(Accuracy::Balanced),
+            "cycle" => Some(Accuracy::Cycle),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the 10 sprites per scanline limit is enforced.
+    pub fn sprite_limit(self) -> bool {
+        self != Accuracy::Fast
+    }
+
+    /// Returns the clocks between a device requesting an interrupt and the
+    /// CPU seeing it.
+    pub fn irq_latency(self) -> u16 {
+        match self {
+            // Requests become visible in the next M-cycle
+            Accuracy::Cycle => 4,
+            _ => 0,
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use ppu::Layer;
+
+/// Emulator action triggered by a hotkey, independent of the frontend and
+/// the key, button or combo bound to it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Action {
+    /// Show the next page of the key binding help screen or hide it
+    ToggleHelp,
+    /// Show or hide the current palettes
+    TogglePalettes,
+    /// Show or hide the timeline of PPU modes and interrupts
+    ToggleTimeline,
+    /// Show or hide the per-frame counters of the core
+    ToggleTelemetry,
+    /// Show or hide the keys held on the joypad
+    ToggleInputDisplay,
+    /// Show or hide a PPU layer
+    ToggleLayer(Layer),
+    /// Show or hide where sprites collide with BG
+    ToggleCollisions,
+    /// Mute or unmute an audio channel (1-4)
+    MuteChannel(u8),
+    /// Raise the audio volume
+    VolumeUp,
+    /// Lower the audio volume
+    VolumeDown,
+    /// Save the frame being rendered
+    DumpPartialFrame,
+    /// Save the last complete frame
+    Screenshot,
+    /// Start or stop recording audio to a WAV file
+    ToggleAudioRecording,
+    /// Step back in time while held
+    Rewind,
+    /// Save a snapshot to a slot
+    SaveState(u8),
+    /// Restore the snapshot in a slot
+    LoadState(u8),
+    /// Return to the state before the last load
+    UndoLoadState,
+    /// Enable or disable all cheat codes
+    ToggleCheats,
+    /// Stop or continue emulation
+    TogglePause,
+    /// Run as fast as possible instead of at 60 frames per second
+    ToggleFastForward,
+    /// Fast forward until the `--turbo-until` condition becomes true
+    TurboUntil,
+    /// Restart the game, keeping the catridge RAM
+    Reset,
+    /// Exit the emulator
+    Quit,
+}
+
+impl Action {
+    /// Returns the description shown on the help screen.
+    pub fn description(self) -> String {
+        match self {
+            Action::ToggleHelp => String::from("Help"),
+            Action::TogglePalettes => String::from("Palettes"),
+            Action::ToggleTimeline => String::from("Timeline"),
+            Action::ToggleTelemetry => String::from("Telemetry"),
+            Action::ToggleInputDisplay => String::from("Input display"),
+            Action::ToggleLayer(Layer::Background) => String::from("Toggle BG"),
+            Action::ToggleLayer(Layer::Window) => String::from("Toggle window"),
+            Action::ToggleLayer(Layer::Sprites) => String::from("Toggle sprites"),
+            Action::ToggleCollisions => String::from("Collisions"),
+            Action::MuteChannel(channel) => format!("Mute channel {}", channel),
+            Action::VolumeUp => String::from("Volume up"),
+            Action::VolumeDown => String::from("Volume down"),
+            Action::DumpPartialFrame => String::from("Dump partial frame"),
+            Action::Screenshot => String::from("Screenshot"),
+            Action::ToggleAudioRecording => String::from("Record audio"),
+            Action::Rewind => String::from("Rewind"),
+            Action::SaveState(slot) => format!("Save state {}", slot),
+            Action::LoadState(slot) => format!("Load state {}", slot),
+            Action::UndoLoadState => String::from("Undo load state"),
+            Action::ToggleCheats => String::from("Cheats"),
+            Action::TogglePause => String::from("Pause"),
+            Action::ToggleFastForward => String::from("Fast forward"),
+            Action::TurboUntil => String::from("Fast forward until"),
+            Action::Reset => String::from("Reset"),
+            Action::Quit => String::from("Quit"),
+        }
+    }
+
+    /// Returns true if the action lasts while its key is held, instead of
+    /// being triggered once per press.
+    pub fn is_held(self) -> bool {
+        self == Action::Rewind
+    }
+}
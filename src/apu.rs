@@ -0,0 +1,981 @@
+use std::mem;
+
+use blip::Blip;
+use io_device::IODevice;
+use savestate::{Savestate, StateBuffer};
+
+/// Number of clocks per second.
+const CLOCKS_PER_SEC: u32 = 4_194_304;
+/// Default output sample rate in Hz.
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+/// Lowest supported output sample rate in Hz.
+pub const MIN_SAMPLE_RATE: u32 = 8_000;
+/// Highest supported output sample rate in Hz.
+pub const MAX_SAMPLE_RATE: u32 = 192_000;
+
+/// Waveforms of the duty cycles (12.5%, 25%, 50% and 75%).
+const DUTY_WAVEFORMS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+/// Base divisors of the noise channel, selected by NR43 bits 0-2.
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+/// Frequency sweep of channel 1.
+#[derive(Clone)]
+struct Sweep {
+    /// Sweep period
+    period: u8,
+    /// Decrease frequency instead of increasing
+    negate: bool,
+    /// Shift of the frequency change
+    shift: u8,
+    /// Steps until the next sweep
+    timer: u8,
+    /// Frequency the sweep is calculated from
+    shadow: u16,
+    /// Sweep is running
+    enabled: bool,
+}
+
+impl Sweep {
+    /// Creates a new `Sweep`.
+    fn new() -> Self {
+        Sweep {
+            period: 0,
+            negate: false,
+            shift: 0,
+            timer: 0,
+            shadow: 0,
+            enabled: false,
+        }
+    }
+
+    /// Reloads the sweep timer. A period of 0 is treated as 8.
+    fn reload(&mut self) {
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+
+    /// Returns the next frequency.
+    fn next_freq(&self) -> u16 {
+        let delta = self.shadow >> self.shift;
+
+        if self.negate {
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+}
+
+/// Volume envelope.
+#[derive(Clone)]
+struct Envelope {
+    /// Initial volume (NRx2)
+    initial: u8,
+    /// Increase volume instead of decreasing
+    increase: bool,
+    /// Envelope period
+    period: u8,
+    /// Current volume
+    volume: u8,
+    /// Steps until the next volume change
+    timer: u8,
+}
+
+impl Envelope {
+    /// Creates a new `Envelope`.
+    fn new() -> Self {
+        Envelope {
+            initial: 0,
+            increase: false,
+            period: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    /// Reads the NRx2 register.
+    fn read(&self) -> u8 {
+        self.initial << 4 | (self.increase as u8) << 3 | self.period
+    }
+
+    /// Writes the NRx2 register.
+    fn write(&mut self, val: u8) {
+        self.initial = val >> 4;
+        self.increase = val & 0x08 > 0;
+        self.period = val & 0x07;
+    }
+
+    /// Restarts the envelope on trigger.
+    fn trigger(&mut self) {
+        self.volume = self.initial;
+        self.timer = self.period;
+    }
+
+    /// Advances the envelope by one step (64 Hz).
+    fn step(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+
+        if self.timer > 1 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period;
+
+        if self.increase && self.volume < 15 {
+            self.volume += 1;
+        } else if !self.increase && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
+
+/// Square wave channel with optional frequency sweep.
+#[derive(Clone)]
+struct Square {
+    /// Frequency sweep (channel 1 only)
+    sweep: Option<Sweep>,
+    /// Duty cycle
+    duty: u8,
+    /// Remaining length
+    length: u8,
+    /// Stop the channel when the length expires
+    length_enable: bool,
+    /// Volume envelope
+    envelope: Envelope,
+    /// Frequency (11 bits)
+    freq: u16,
+    /// Clocks until the next duty step
+    timer: u32,
+    /// Position within the duty waveform
+    duty_pos: u8,
+    /// Channel is playing
+    enabled: bool,
+}
+
+impl Square {
+    /// Creates a new `Square`.
+    fn new(sweep: bool) -> Self {
+        Square {
+            sweep: if sweep { Some(Sweep::new()) } else { None },
+            duty: 0,
+            length: 0,
+            length_enable: false,
+            envelope: Envelope::new(),
+            freq: 0,
+            timer: 0,
+            duty_pos: 0,
+            enabled: false,
+        }
+    }
+
+    /// Returns true if the DAC is powered.
+    fn dac_enabled(&self) -> bool {
+        self.envelope.read() & 0xf8 > 0
+    }
+
+    /// Returns the clocks per duty step.
+    fn period(&self) -> u32 {
+        (2048 - self.freq as u32) * 4
+    }
+
+    /// Reads a register (0 for NRx0 to 4 for NRx4).
+    fn read(&self, reg: u16) -> u8 {
+        match reg {
+            0 => match self.sweep {
+                Some(ref s) => 0x80 | s.period << 4 | (s.negate as u8) << 3 | s.shift,
+                None => 0xff,
+            },
+            1 => self.duty << 6 | 0x3f,
+            2 => self.envelope.read(),
+            4 => (self.length_enable as u8) << 6 | 0xbf,
+            _ => 0xff,
+        }
+    }
+
+    /// Writes a register (0 for NRx0 to 4 for NRx4).
+    fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                if let Some(ref mut s) = self.sweep {
+                    s.period = (val >> 4) & 0x07;
+                    s.negate = val & 0x08 > 0;
+                    s.shift = val & 0x07;
+                }
+            }
+            1 => {
+                self.duty = val >> 6;
+                self.length = 64 - (val & 0x3f);
+            }
+            2 => {
+                self.envelope.write(val);
+                if !self.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.freq = (self.freq & 0x700) | val as u16,
+            4 => {
+                self.freq = (self.freq & 0xff) | (val as u16 & 0x07) << 8;
+                self.length_enable = val & 0x40 > 0;
+
+                if val & 0x80 > 0 {
+                    self.trigger();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Restarts the channel.
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled();
+
+        if self.length == 0 {
+            self.length = 64;
+        }
+
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        let freq = self.freq;
+        let overflow = match self.sweep {
+            Some(ref mut s) => {
+                s.shadow = freq;
+                s.reload();
+                s.enabled = s.period != 0 || s.shift != 0;
+                s.shift != 0 && s.next_freq() > 2047
+            }
+            None => false,
+        };
+
+        if overflow {
+            self.enabled = false;
+        }
+    }
+
+    /// Advances the length counter by one step (256 Hz).
+    fn step_length(&mut self) {
+        if self.length_enable && self.length > 0 {
+            self.length -= 1;
+            if self.length == 0 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// Advances the frequency sweep by one step (128 Hz).
+    fn step_sweep(&mut self) {
+        let s = match self.sweep {
+            Some(ref mut s) => s,
+            None => return,
+        };
+
+        if s.timer > 1 {
+            s.timer -= 1;
+            return;
+        }
+        s.reload();
+
+        if !s.enabled || s.period == 0 {
+            return;
+        }
+
+        let freq = s.next_freq();
+        if freq > 2047 {
+            self.enabled = false;
+            return;
+        }
+
+        if s.shift != 0 {
+            s.shadow = freq;
+            self.freq = freq;
+
+            // The new frequency is checked for overflow once more
+            if s.next_freq() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// Progresses the duty timer for a given number of ticks.
+    fn update(&mut self, tick: u8) {
+        let mut tick = tick as u32;
+
+        while tick >= self.timer {
+            tick -= self.timer;
+            self.timer = self.period();
+            self.duty_pos = (self.duty_pos + 1) % 8;
+        }
+
+        self.timer -= tick;
+    }
+
+    /// Returns the current output level (0-15).
+    fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let high = DUTY_WAVEFORMS[self.duty as usize] >> (7 - self.duty_pos) & 1;
+        high * self.envelope.volume
+    }
+}
+
+/// Wave channel playing 4-bit samples from wave RAM.
+#[derive(Clone)]
+struct Wave {
+    /// DAC is powered (NR30 bit 7)
+    dac_enable: bool,
+    /// Remaining length
+    length: u16,
+    /// Stop the channel when the length expires
+    length_enable: bool,
+    /// Volume code (0: mute, 1: 100%, 2: 50%, 3: 25%)
+    volume: u8,
+    /// Frequency (11 bits)
+    freq: u16,
+    /// Clocks until the next sample
+    timer: u32,
+    /// Position of the current sample within wave RAM (0-31)
+    position: u8,
+    /// 32 4-bit samples, upper nibble first
+    wave_ram: [u8; 16],
+    /// Channel is playing
+    enabled: bool,
+}
+
+impl Wave {
+    /// Creates a new `Wave`.
+    fn new() -> Self {
+        Wave {
+            dac_enable: false,
+            length: 0,
+            length_enable: false,
+            volume: 0,
+            freq: 0,
+            timer: 0,
+            position: 0,
+            wave_ram: [0; 16],
+            enabled: false,
+        }
+    }
+
+    /// Returns the clocks per sample.
+    fn period(&self) -> u32 {
+        (2048 - self.freq as u32) * 2
+    }
+
+    /// Reads a register (0 for NR30 to 4 for NR34).
+    fn read(&self, reg: u16) -> u8 {
+        match reg {
+            0 => (self.dac_enable as u8) << 7 | 0x7f,
+            2 => self.volume << 5 | 0x9f,
+            4 => (self.length_enable as u8) << 6 | 0xbf,
+            _ => 0xff,
+        }
+    }
+
+    /// Writes a register (0 for NR30 to 4 for NR34).
+    fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                self.dac_enable = val & 0x80 > 0;
+                if !self.dac_enable {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length = 256 - val as u16,
+            2 => self.volume = (val >> 5) & 0x03,
+            3 => self.freq = (self.freq & 0x700) | val as u16,
+            4 => {
+                self.freq = (self.freq & 0xff) | (val as u16 & 0x07) << 8;
+                self.length_enable = val & 0x40 > 0;
+
+                if val & 0x80 > 0 {
+                    self.trigger();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Restarts the channel.
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enable;
+
+        if self.length == 0 {
+            self.length = 256;
+        }
+
+        self.timer = self.period();
+        self.position = 0;
+    }
+
+    /// Advances the length counter by one step (256 Hz).
+    fn step_length(&mut self) {
+        if self.length_enable && self.length > 0 {
+            self.length -= 1;
+            if self.length == 0 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// Progresses the sample timer for a given number of ticks.
+    fn update(&mut self, tick: u8) {
+        let mut tick = tick as u32;
+
+        while tick >= self.timer {
+            tick -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) % 32;
+        }
+
+        self.timer -= tick;
+    }
+
+    /// Returns the current output level (0-15).
+    fn output(&self) -> u8 {
+        if !self.enabled || self.volume == 0 {
+            return 0;
+        }
+
+        let byte = self.wave_ram[self.position as usize / 2];
+        // Upper nibble first
+        let shift = (1 - self.position % 2) * 4;
+        let sample = (byte >> shift) & 0x0f;
+
+        sample >> (self.volume - 1)
+    }
+}
+
+/// Noise channel driven by a linear feedback shift register.
+#[derive(Clone)]
+struct Noise {
+    /// Remaining length
+    length: u8,
+    /// Stop the channel when the length expires
+    length_enable: bool,
+    /// Volume envelope
+    envelope: Envelope,
+    /// Clock shift (NR43 bits 4-7)
+    shift: u8,
+    /// Use a 7-bit LFSR instead of 15 bits
+    short_mode: bool,
+    /// Divisor code (NR43 bits 0-2)
+    divisor: u8,
+    /// Linear feedback shift register
+    lfsr: u16,
+    /// Clocks until the next LFSR shift
+    timer: u32,
+    /// Channel is playing
+    enabled: bool,
+}
+
+impl Noise {
+    /// Creates a new `Noise`.
+    fn new() -> Self {
+        Noise {
+            length: 0,
+            length_enable: false,
+            envelope: Envelope::new(),
+            shift: 0,
+            short_mode: false,
+            divisor: 0,
+            lfsr: 0x7fff,
+            timer: 0,
+            enabled: false,
+        }
+    }
+
+    /// Returns true if the DAC is powered.
+    fn dac_enabled(&self) -> bool {
+        self.envelope.read() & 0xf8 > 0
+    }
+
+    /// Returns the clocks per LFSR shift.
+    fn period(&self) -> u32 {
+        NOISE_DIVISORS[self.divisor as usize] << self.shift
+    }
+
+    /// Reads a register (0 for NR40 to 4 for NR44).
+    fn read(&self, reg: u16) -> u8 {
+        match reg {
+            2 => self.envelope.read(),
+            3 => self.shift << 4 | (self.short_mode as u8) << 3 | self.divisor,
+            4 => (self.length_enable as u8) << 6 | 0xbf,
+            _ => 0xff,
+        }
+    }
+
+    /// Writes a register (0 for NR40 to 4 for NR44).
+    fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            1 => self.length = 64 - (val & 0x3f),
+            2 => {
+                self.envelope.write(val);
+                if !self.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => {
+                self.shift = val >> 4;
+                self.short_mode = val & 0x08 > 0;
+                self.divisor = val & 0x07;
+            }
+            4 => {
+                self.length_enable = val & 0x40 > 0;
+
+                if val & 0x80 > 0 {
+                    self.trigger();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Restarts the channel.
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled();
+
+        if self.length == 0 {
+            self.length = 64;
+        }
+
+        self.timer = self.period();
+        self.envelope.trigger();
+        self.lfsr = 0x7fff;
+    }
+
+    /// Advances the length counter by one step (256 Hz).
+    fn step_length(&mut self) {
+        if self.length_enable && self.length > 0 {
+            self.length -= 1;
+            if self.length == 0 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// Shifts the LFSR, feeding back the XOR of its two lowest bits.
+    fn shift_lfsr(&mut self) {
+        let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+
+        self.lfsr = (self.lfsr >> 1) | bit << 14;
+        if self.short_mode {
+            self.lfsr = (self.lfsr & !0x40) | bit << 6;
+        }
+    }
+
+    /// Progresses the LFSR timer for a given number of ticks.
+    fn update(&mut self, tick: u8) {
+        let mut tick = tick as u32;
+
+        while tick >= self.timer {
+            tick -= self.timer;
+            self.timer = self.period();
+            self.shift_lfsr();
+        }
+
+        self.timer -= tick;
+    }
+
+    /// Returns the current output level (0-15).
+    fn output(&self) -> u8 {
+        if !self.enabled || self.lfsr & 1 > 0 {
+            return 0;
+        }
+
+        self.envelope.volume
+    }
+}
+
+/// Listening settings. They only affect the output, not the state visible to
+/// the game, so powering off the APU leaves them alone.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputSettings {
+    /// Output sample rate in Hz
+    sample_rate: u32,
+    /// Channels 1 to 4 are left out of the mix
+    muted: [bool; 4],
+    /// Output volume (0.0-1.0) applied after NR50
+    volume: f32,
+    /// Both outputs carry the average of left and right
+    mono: bool,
+}
+
+/// Audio Processing Unit.
+#[derive(Clone)]
+pub struct APU {
+    /// Channel 1 (square with sweep)
+    ch1: Square,
+    /// Channel 2 (square)
+    ch2: Square,
+    /// Channel 3 (wave)
+    ch3: Wave,
+    /// Channel 4 (noise)
+    ch4: Noise,
+    /// Master volume (NR50)
+    nr50: u8,
+    /// Channel routing (NR51)
+    nr51: u8,
+    /// APU is powered (NR52 bit 7)
+    power: bool,
+    /// Frame sequencer step (0-7)
+    step: u8,
+    /// Output samples scaled by `CLOCKS_PER_SEC`
+    sample_counter: u32,
+    /// Band-limited synthesis of the left output
+    left: Blip,
+    /// Band-limited synthesis of the right output
+    right: Blip,
+    /// Samples not taken yet
+    samples: Vec<f32>,
+    /// Listening settings
+    settings: OutputSettings,
+}
+
+impl APU {
+    /// Creates a new `APU`.
+    pub fn new() -> Self {
+        APU {
+            ch1: Square::new(true),
+            ch2: Square::new(false),
+            ch3: Wave::new(),
+            ch4: Noise::new(),
+            nr50: 0,
+            nr51: 0,
+            power: false,
+            step: 0,
+            sample_counter: 0,
+            left: Blip::new(),
+            right: Blip::new(),
+            samples: Vec::new(),
+            settings: OutputSettings {
+                sample_rate: DEFAULT_SAMPLE_RATE,
+                muted: [false; 4],
+                volume: 1.0,
+                mono: false,
+            },
+        }
+    }
+
+    /// Returns the listening settings, e.g. to keep them across a restore.
+    pub fn settings(&self) -> &OutputSettings {
+        &self.settings
+    }
+
+    /// Replaces the listening settings.
+    pub fn set_settings(&mut self, settings: OutputSettings) {
+        self.settings = settings;
+    }
+
+    /// Sets the output sample rate. The channels run at the native clock
+    /// rate and level changes are resampled to the output rate by the
+    /// band-limited synthesis buffers, so any rate can be requested.
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        if (MIN_SAMPLE_RATE..=MAX_SAMPLE_RATE).contains(&rate) {
+            self.settings.sample_rate = rate;
+        } else {
+            warn!("Unsupported sample rate {} Hz", rate);
+        }
+    }
+
+    /// Returns the output sample rate in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        self.settings.sample_rate
+    }
+
+    /// Returns the number of stereo samples generated but not taken yet.
+    pub fn pending_samples(&self) -> usize {
+        self.samples.len() / 2
+    }
+
+    /// Returns the samples generated since the last call, interleaved
+    /// stereo (left first) at the output sample rate.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        mem::take(&mut self.samples)
+    }
+
+    /// Advances the frame sequencer, which clocks the length counters,
+    /// sweep and envelopes. Called on a falling edge of DIV bit 4 (512 Hz),
+    /// so writing DIV affects the APU timing as on hardware.
+    pub fn step_frame_sequencer(&mut self) {
+        if !self.power {
+            return;
+        }
+
+        if self.step & 1 == 0 {
+            self.ch1.step_length();
+            self.ch2.step_length();
+            self.ch3.step_length();
+            self.ch4.step_length();
+        }
+        if self.step == 2 || self.step == 6 {
+            self.ch1.step_sweep();
+        }
+        if self.step == 7 {
+            self.ch1.envelope.step();
+            self.ch2.envelope.step();
+            self.ch4.envelope.step();
+        }
+
+        self.step = (self.step + 1) % 8;
+    }
+
+    /// Mutes or unmutes a channel (1-4) and returns whether it is now muted. Muting
+    /// only affects the output, not the channel state visible to the game.
+    pub fn toggle_muted(&mut self, channel: usize) -> bool {
+        let muted = &mut self.settings.muted[channel - 1];
+        *muted = !*muted;
+
+        *muted
+    }
+
+    /// Sets the output volume (0.0-1.0). Like muting, this only affects the
+    /// output.
+    pub fn set_volume(&mut self, volume: f32) {
+        if (0.0..=1.0).contains(&volume) {
+            self.settings.volume = volume;
+        } else {
+            warn!("Unsupported volume {}", volume);
+        }
+    }
+
+    /// Returns the output volume (0.0-1.0).
+    pub fn volume(&self) -> f32 {
+        self.settings.volume
+    }
+
+    /// Downmixes the output to mono for single-speaker setups, so that
+    /// channels routed to one side only are not lost. Like muting, this only
+    /// affects the output.
+    pub fn set_mono(&mut self, mono: bool) {
+        self.settings.mono = mono;
+    }
+
+    /// Mixes the channels into a left and right sample in the range of
+    /// -1.0 to 1.0, routed by NR51 and scaled by the master volume of NR50.
+    fn mix(&self) -> (f32, f32) {
+        if !self.power {
+            return (0.0, 0.0);
+        }
+
+        let dac = |level: u8| level as f32 / 7.5 - 1.0;
+
+        // DAC outputs of channel 1 to 4, or None if the DAC is off
+        let outputs = [
+            Some(self.ch1.output()).filter(|_| self.ch1.dac_enabled()),
+            Some(self.ch2.output()).filter(|_| self.ch2.dac_enabled()),
+            Some(self.ch3.output()).filter(|_| self.ch3.dac_enable),
+            Some(self.ch4.output()).filter(|_| self.ch4.dac_enabled()),
+        ];
+
+        let (mut left, mut right) = (0.0, 0.0);
+
+        for (i, output) in outputs.iter().enumerate() {
+            if self.settings.muted[i] {
+                continue;
+            }
+
+            if let Some(level) = *output {
+                if self.nr51 & (0x10 << i) > 0 {
+                    left += dac(level) * 0.25;
+                }
+                if self.nr51 & (0x01 << i) > 0 {
+                    right += dac(level) * 0.25;
+                }
+            }
+        }
+
+        let left_volume = ((self.nr50 >> 4) & 0x07) + 1;
+        let right_volume = (self.nr50 & 0x07) + 1;
+
+        let left = left * left_volume as f32 / 8.0 * self.settings.volume;
+        let right = right * right_volume as f32 / 8.0 * self.settings.volume;
+
+        if self.settings.mono {
+            let mid = (left + right) / 2.0;
+            (mid, mid)
+        } else {
+            (left, right)
+        }
+    }
+}
+
+impl IODevice for APU {
+    fn write(&mut self, addr: u16, val: u8) {
+        // Wave RAM is not affected by power
+        if let 0xff30..=0xff3f = addr {
+            self.ch3.wave_ram[(addr - 0xff30) as usize] = val;
+            return;
+        }
+
+        // Only NR52 is writable while powered off
+        if !self.power && addr != 0xff26 {
+            return;
+        }
+
+        match addr {
+            0xff10..=0xff14 => self.ch1.write(addr - 0xff10, val),
+            0xff15..=0xff19 => self.ch2.write(addr - 0xff15, val),
+            0xff1a..=0xff1e => self.ch3.write(addr - 0xff1a, val),
+            0xff1f..=0xff23 => self.ch4.write(addr - 0xff1f, val),
+            0xff24 => self.nr50 = val,
+            0xff25 => self.nr51 = val,
+            0xff26 => {
+                let power = val & 0x80 > 0;
+
+                // Powering off clears all registers but wave RAM
+                if self.power && !power {
+                    let wave_ram = self.ch3.wave_ram;
+                    self.ch1 = Square::new(true);
+                    self.ch2 = Square::new(false);
+                    self.ch3 = Wave::new();
+                    self.ch3.wave_ram = wave_ram;
+                    self.ch4 = Noise::new();
+                    self.nr50 = 0;
+                    self.nr51 = 0;
+                } else if !self.power && power {
+                    self.step = 0;
+                }
+
+                self.power = power;
+            }
+            _ => (),
+        }
+    }
+
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xff10..=0xff14 => self.ch1.read(addr - 0xff10),
+            0xff15..=0xff19 => self.ch2.read(addr - 0xff15),
+            0xff1a..=0xff1e => self.ch3.read(addr - 0xff1a),
+            0xff1f..=0xff23 => self.ch4.read(addr - 0xff1f),
+            0xff30..=0xff3f => self.ch3.wave_ram[(addr - 0xff30) as usize],
+            0xff24 => self.nr50,
+            0xff25 => self.nr51,
+            0xff26 => {
+                (self.power as u8) << 7
+                    | 0x70
+                    | (self.ch4.enabled as u8) << 3
+                    | (self.ch3.enabled as u8) << 2
+                    | (self.ch2.enabled as u8) << 1
+                    | self.ch1.enabled as u8
+            }
+            _ => 0xff,
+        }
+    }
+
+    fn update(&mut self, tick: u8) {
+        if self.power {
+            self.ch1.update(tick);
+            self.ch2.update(tick);
+            self.ch3.update(tick);
+            self.ch4.update(tick);
+        }
+
+        // Level changes are placed at their time within the output sample
+        // to avoid aliasing
+        let frac = self.sample_counter as f32 / CLOCKS_PER_SEC as f32;
+        let (left, right) = self.mix();
+        self.left.set_level(frac, left);
+        self.right.set_level(frac, right);
+
+        self.sample_counter += self.settings.sample_rate * tick as u32;
+        while self.sample_counter >= CLOCKS_PER_SEC {
+            self.sample_counter -= CLOCKS_PER_SEC;
+
+            let (left, right) = (self.left.next_sample(), self.right.next_sample());
+            // Up to one second of stereo is buffered until taken
+            if self.samples.len() < self.settings.sample_rate as usize * 2 {
+                self.samples.push(left);
+                self.samples.push(right);
+            }
+        }
+    }
+}
+
+impl Savestate for Sweep {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.u8(&mut self.period);
+        buf.bool(&mut self.negate);
+        buf.u8(&mut self.shift);
+        buf.u8(&mut self.timer);
+        buf.u16(&mut self.shadow);
+        buf.bool(&mut self.enabled);
+    }
+}
+
+impl Savestate for Envelope {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.u8(&mut self.initial);
+        buf.bool(&mut self.increase);
+        buf.u8(&mut self.period);
+        buf.u8(&mut self.volume);
+        buf.u8(&mut self.timer);
+    }
+}
+
+impl Savestate for Square {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        if let Some(ref mut sweep) = self.sweep {
+            sweep.savestate(buf);
+        }
+        buf.u8(&mut self.duty);
+        buf.u8(&mut self.length);
+        buf.bool(&mut self.length_enable);
+        self.envelope.savestate(buf);
+        buf.u16(&mut self.freq);
+        buf.u32(&mut self.timer);
+        buf.u8(&mut self.duty_pos);
+        buf.bool(&mut self.enabled);
+    }
+}
+
+impl Savestate for Wave {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.bool(&mut self.dac_enable);
+        buf.u16(&mut self.length);
+        buf.bool(&mut self.length_enable);
+        buf.u8(&mut self.volume);
+        buf.u16(&mut self.freq);
+        buf.u32(&mut self.timer);
+        buf.u8(&mut self.position);
+        buf.bytes(&mut self.wave_ram);
+        buf.bool(&mut self.enabled);
+    }
+}
+
+impl Savestate for Noise {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.u8(&mut self.length);
+        buf.bool(&mut self.length_enable);
+        self.envelope.savestate(buf);
+        buf.u8(&mut self.shift);
+        buf.bool(&mut self.short_mode);
+        buf.u8(&mut self.divisor);
+        buf.u16(&mut self.lfsr);
+        buf.u32(&mut self.timer);
+        buf.bool(&mut self.enabled);
+    }
+}
+
+impl Savestate for APU {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        self.ch1.savestate(buf);
+        self.ch2.savestate(buf);
+        self.ch3.savestate(buf);
+        self.ch4.savestate(buf);
+        buf.u8(&mut self.nr50);
+        buf.u8(&mut self.nr51);
+        buf.bool(&mut self.power);
+        buf.u8(&mut self.step);
+        buf.u32(&mut self.sample_counter);
+
+        if buf.is_loading() {
+            self.samples.clear();
+        }
+    }
+}
@@ -0,0 +1,85 @@
+/// Length of a fade in seconds.
+const FADE_SECS: f32 = 0.01;
+
+/// Fades the audio out when the emulation pauses and back in when it
+/// resumes. Without it, the audio queue runs dry in the middle of a
+/// waveform, which clicks, and restarts just as abruptly.
+pub struct AudioFade {
+    /// Frames per fade
+    frames: usize,
+    /// Last stereo frame queued
+    last: [f32; 2],
+    /// Frames of the fade-in left
+    fade_in: usize,
+}
+
+impl AudioFade {
+    /// Creates a new `AudioFade` for a sample rate in Hz.
+    pub fn new(sample_rate: u32) -> Self {
+        AudioFade {
+            frames: (sample_rate as f32 * FADE_SECS) as usize,
+            last: [0.0; 2],
+            fade_in: 0,
+        }
+    }
+
+    /// Fades in interleaved stereo samples about to be queued if the
+    /// emulation has just resumed.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_mut(2) {
+            if self.fade_in > 0 {
+                let gain = 1.0 - self.fade_in as f32 / self.frames as f32;
+                for sample in frame.iter_mut() {
+                    *sample *= gain;
+                }
+                self.fade_in -= 1;
+            }
+        }
+
+        if samples.len() >= 2 {
+            self.last = [samples[samples.len() - 2], samples[samples.len() - 1]];
+        }
+    }
+
+    /// Returns samples ramping from the last frame queued down to silence,
+    /// to queue when the emulation pauses. The samples after the pause fade
+    /// in.
+    pub fn fade_out(&mut self) -> Vec<f32> {
+        let mut samples = Vec::with_capacity(self.frames * 2);
+
+        for i in 0..self.frames {
+            let gain = 1.0 - (i + 1) as f32 / self.frames as f32;
+            samples.push(self.last[0] * gain);
+            samples.push(self.last[1] * gain);
+        }
+
+        self.last = [0.0; 2];
+        self.fade_in = self.frames;
+
+        samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fade() {
+        let mut fade = AudioFade::new(48_000);
+        let mut samples = vec![0.5; 960];
+        fade.process(&mut samples);
+        assert!(samples.iter().all(|&s| s == 0.5));
+
+        let out = fade.fade_out();
+        assert_eq!(out.len(), 960);
+        assert!(out[0] < 0.5 && out[0] > 0.49);
+        assert_eq!(out[out.len() - 1], 0.0);
+
+        let mut samples = vec![0.5; 1920];
+        fade.process(&mut samples);
+        assert_eq!(samples[0], 0.0);
+        assert!(samples[..960].windows(2).all(|w| w[0] <= w[1]));
+        assert!(samples[960..].iter().all(|&s| s == 0.5));
+    }
+}
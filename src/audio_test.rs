@@ -0,0 +1,207 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+
+use apu::{self, APU};
+use io_device::IODevice;
+
+/// Clocks between frame sequencer steps (DIV bit 4 falling edges).
+const CLOCKS_PER_STEP: u32 = 8192;
+/// Default maximum difference of a sample from the reference.
+const DEFAULT_TOLERANCE: f32 = 1e-3;
+
+/// Command of an audio test script.
+enum Command {
+    /// Write a value to an APU register
+    Write(u16, u8),
+    /// Run for a number of clocks
+    Run(u32),
+}
+
+/// Test of the APU output for a register script, compared to a reference
+/// trace kept next to the script with extension `ref`:
+///
+/// ```text
+/// rate 8000               # Output sample rate (default 48000)
+/// tolerance 0.001         # Maximum difference of a sample
+/// write ff26 80           # Write a value to an APU register (hex)
+/// run 70224               # Run for a number of clocks
+/// ```
+struct AudioTest {
+    /// Reference trace filename
+    ref_fname: PathBuf,
+    /// Output sample rate
+    rate: u32,
+    /// Maximum difference of a sample from the reference
+    tolerance: f32,
+    /// Register writes and runs
+    commands: Vec<Command>,
+}
+
+/// Parses a hex value of an APU register write.
+fn parse_hex(s: Option<&str>) -> Option<u32> {
+    s.and_then(|s| u32::from_str_radix(s, 16).ok())
+}
+
+impl AudioTest {
+    /// Reads a test script.
+    fn new(fname: &str) -> Result<Self, String> {
+        let file = File::open(fname).map_err(|e| e.to_string())?;
+
+        let mut rate = apu::DEFAULT_SAMPLE_RATE;
+        let mut tolerance = DEFAULT_TOLERANCE;
+        let mut commands = Vec::new();
+
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            let line = match line.find('#') {
+                Some(pos) => &line[..pos],
+                None => &line,
+            };
+            let mut fields = line.split_whitespace();
+            let at_line = |e: &str| format!("{} at line {}", e, i + 1);
+
+            match fields.next() {
+                None => continue,
+                Some("rate") => {
+                    rate = fields
+                        .next()
+                        .and_then(|r| r.parse().ok())
+                        .ok_or_else(|| at_line("Invalid rate"))?
+                }
+                Some("tolerance") => {
+                    tolerance = fields
+                        .next()
+                        .and_then(|t| t.parse().ok())
+                        .ok_or_else(|| at_line("Invalid tolerance"))?
+                }
+                Some("write") => {
+                    let addr = parse_hex(fields.next()).filter(|a| (0xff10..=0xff3f).contains(a));
+                    let val = parse_hex(fields.next()).filter(|&v| v <= 0xff);
+
+                    match (addr, val) {
+                        (Some(addr), Some(val)) => {
+                            commands.push(Command::Write(addr as u16, val as u8))
+                        }
+                        _ => return Err(at_line("Invalid register write")),
+                    }
+                }
+                Some("run") => match fields.next().and_then(|c| c.parse().ok()) {
+                    Some(clocks) => commands.push(Command::Run(clocks)),
+                    None => return Err(at_line("Invalid number of clocks")),
+                },
+                Some(cmd) => return Err(at_line(&format!("Unknown command {}", cmd))),
+            }
+        }
+
+        Ok(AudioTest {
+            ref_fname: PathBuf::from(fname).with_extension("ref"),
+            rate,
+            tolerance,
+            commands,
+        })
+    }
+
+    /// Runs the script and returns the interleaved stereo samples.
+    fn samples(&self) -> Vec<f32> {
+        let mut apu = APU::new();
+        apu.set_sample_rate(self.rate);
+
+        let mut step_counter = 0;
+        let mut samples = Vec::new();
+
+        for command in self.commands.iter() {
+            match *command {
+                Command::Write(addr, val) => apu.write(addr, val),
+                Command::Run(clocks) => {
+                    for _ in 0..clocks / 4 {
+                        apu.update(4);
+
+                        // Stands in for the timer's DIV
+                        step_counter += 4;
+                        if step_counter >= CLOCKS_PER_STEP {
+                            step_counter -= CLOCKS_PER_STEP;
+                            apu.step_frame_sequencer();
+                        }
+                    }
+
+                    samples.extend(apu.take_samples());
+                }
+            }
+        }
+
+        samples
+    }
+
+    /// Writes the samples as the new reference trace.
+    fn update(&self) -> Result<(), String> {
+        let lines: Vec<String> = self
+            .samples()
+            .chunks(2)
+            .map(|s| format!("{:.4} {:.4}", s[0], s[1]))
+            .collect();
+
+        fs::write(&self.ref_fname, lines.join("\n") + "\n").map_err(|e| e.to_string())
+    }
+
+    /// Compares the samples to the reference trace.
+    fn run(&self) -> Result<(), String> {
+        let reference = fs::read_to_string(&self.ref_fname)
+            .map_err(|e| format!("{}: {}", self.ref_fname.display(), e))?;
+        let samples = self.samples();
+
+        let expected: Vec<f32> = reference
+            .split_whitespace()
+            .map(|s| s.parse().map_err(|_| format!("Invalid sample {}", s)))
+            .collect::<Result<_, _>>()?;
+
+        if samples.len() != expected.len() {
+            return Err(format!(
+                "expected {} samples, got {}",
+                expected.len() / 2,
+                samples.len() / 2
+            ));
+        }
+
+        for (i, (&actual, &expected)) in samples.iter().zip(expected.iter()).enumerate() {
+            if (actual - expected).abs() > self.tolerance {
+                return Err(format!(
+                    "sample {} ({}): expected {:.4}, got {:.4}",
+                    i / 2,
+                    if i % 2 == 0 { "left" } else { "right" },
+                    expected,
+                    actual
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs audio test scripts (`[--update] <script>...`) and returns the exit
+/// code. With `--update`, the reference traces are rewritten instead.
+pub fn main(args: &[String]) -> i32 {
+    let update = args.iter().any(|a| a == "--update");
+    let mut failed = 0;
+
+    for fname in args.iter().filter(|a| *a != "--update") {
+        let result =
+            AudioTest::new(fname).and_then(|test| if update { test.update() } else { test.run() });
+
+        match result {
+            Ok(()) if update => println!("{} ... updated", fname),
+            Ok(()) => println!("{} ... ok", fname),
+            Err(reason) => {
+                println!("{} ... FAILED ({})", fname, reason);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        1
+    } else {
+        0
+    }
+}
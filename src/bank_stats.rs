@@ -0,0 +1,74 @@
+use std::fmt;
+
+use mmu::ram_region;
+
+/// Time spent executing from each ROM bank and number of ROM bank switches.
+#[derive(Clone)]
+pub struct BankStats {
+    /// Clocks spent executing from each ROM bank
+    clocks: Vec<u64>,
+    /// Clocks spent executing from each RAM region, in order of first use
+    ram_clocks: Vec<(&'static str, u64)>,
+    /// Number of times the switchable ROM bank changed
+    switches: u64,
+    /// Switchable ROM bank when the last instruction was executed
+    last_bank: usize,
+}
+
+impl BankStats {
+    /// Creates a new `BankStats` for a catridge with `num_banks` ROM banks.
+    pub fn new(num_banks: usize) -> Self {
+        BankStats {
+            clocks: vec![0; num_banks],
+            ram_clocks: Vec::new(),
+            switches: 0,
+            last_bank: 1,
+        }
+    }
+
+    /// Records an instruction at `pc` taking `clocks` clocks, with `bank`
+    /// mapped to 0x4000-0x7fff.
+    pub fn record(&mut self, pc: u16, bank: usize, clocks: u8) {
+        if bank != self.last_bank {
+            self.switches += 1;
+            self.last_bank = bank;
+        }
+
+        // A larger catridge may have been swapped in
+        if bank >= self.clocks.len() {
+            self.clocks.resize(bank + 1, 0);
+        }
+
+        match ram_region(pc) {
+            None if pc < 0x4000 => self.clocks[0] += clocks as u64,
+            None => self.clocks[bank] += clocks as u64,
+            Some(region) => match self.ram_clocks.iter_mut().find(|r| r.0 == region) {
+                Some(r) => r.1 += clocks as u64,
+                None => self.ram_clocks.push((region, clocks as u64)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for BankStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total =
+            self.clocks.iter().sum::<u64>() + self.ram_clocks.iter().map(|r| r.1).sum::<u64>();
+        let percent = |clocks: u64| 100.0 * clocks as f64 / total.max(1) as f64;
+
+        writeln!(f, "Bank  Clocks          Time")?;
+
+        for (bank, &clocks) in self.clocks.iter().enumerate() {
+            // Unused banks are omitted to keep the report short
+            if clocks > 0 {
+                writeln!(f, "{:03x}   {:<14}  {:5.1}%", bank, clocks, percent(clocks))?;
+            }
+        }
+
+        for &(region, clocks) in self.ram_clocks.iter() {
+            writeln!(f, "{:<4}  {:<14}  {:5.1}%", region, clocks, percent(clocks))?;
+        }
+
+        write!(f, "ROM bank switches: {}", self.switches)
+    }
+}
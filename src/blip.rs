@@ -0,0 +1,92 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+/// Number of sub-sample positions a level change is snapped to.
+const PHASES: usize = 32;
+/// Length of the band-limited impulse in output samples.
+const TAPS: usize = 16;
+/// Cutoff frequency relative to the sample rate, just below Nyquist.
+const CUTOFF: f64 = 0.45;
+
+/// Band-limited synthesis buffer (in the style of blip_buf).
+///
+/// Instead of sampling a signal at each output sample, which aliases the
+/// sharp edges of square waves, level changes are recorded as deltas at
+/// their exact time and spread over a few samples with a band-limited
+/// impulse. Summing the deltas gives the band-limited signal.
+#[derive(Clone)]
+pub struct Blip {
+    /// Band-limited impulse for each sub-sample phase
+    kernel: Vec<[f32; TAPS]>,
+    /// Deltas of the current and upcoming samples
+    deltas: VecDeque<f32>,
+    /// Current level of the input signal
+    level: f32,
+    /// Sum of the deltas output so far
+    sum: f32,
+}
+
+impl Blip {
+    /// Creates a new `Blip`.
+    pub fn new() -> Self {
+        let mut kernel = Vec::with_capacity(PHASES);
+
+        for phase in 0..PHASES {
+            let mut taps = [0.0; TAPS];
+
+            for (i, tap) in taps.iter_mut().enumerate() {
+                // Distance from the center of the impulse in samples
+                let x = i as f64 - (TAPS / 2) as f64 + 1.0 - phase as f64 / PHASES as f64;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (PI * 2.0 * CUTOFF * x).sin() / (PI * 2.0 * CUTOFF * x)
+                };
+                // Blackman window over the taps
+                let w = (x + (TAPS / 2) as f64) / TAPS as f64;
+                let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+
+                *tap = (sinc * window) as f32;
+            }
+
+            // Normalize so that a delta adds up to exactly its size
+            let total: f32 = taps.iter().sum();
+            for tap in taps.iter_mut() {
+                *tap /= total;
+            }
+
+            kernel.push(taps);
+        }
+
+        Blip {
+            kernel,
+            deltas: (0..TAPS).map(|_| 0.0).collect(),
+            level: 0.0,
+            sum: 0.0,
+        }
+    }
+
+    /// Changes the level of the signal at `frac` (0.0-1.0) within the
+    /// current output sample.
+    pub fn set_level(&mut self, frac: f32, level: f32) {
+        let delta = level - self.level;
+        if delta == 0.0 {
+            return;
+        }
+        self.level = level;
+
+        let phase = ((frac * PHASES as f32) as usize).min(PHASES - 1);
+
+        for (d, &tap) in self.deltas.iter_mut().zip(self.kernel[phase].iter()) {
+            *d += delta * tap;
+        }
+    }
+
+    /// Completes the current output sample and returns it.
+    pub fn next_sample(&mut self) -> f32 {
+        self.sum += self.deltas.pop_front().unwrap_or(0.0);
+        self.deltas.push_back(0.0);
+
+        self.sum
+    }
+}
@@ -0,0 +1,234 @@
+use std::sync::Arc;
+
+use mbc::{Mbc, Memory};
+use savestate::{Savestate, StateBuffer};
+
+/// Width of the image captured by the sensor.
+pub const WIDTH: usize = 128;
+/// Height of the image captured by the sensor.
+pub const HEIGHT: usize = 112;
+
+/// Number of registers (0xa000-0xa035): control, sensor settings and a 4x4
+/// matrix of three dithering thresholds each.
+const NUM_REGS: usize = 0x36;
+/// Offset of the captured image, as 16x14 tiles, in RAM bank 0.
+const IMAGE_OFFSET: usize = 0x0100;
+/// Exposure at which the source image is captured as is. Longer exposures
+/// brighten it and shorter ones darken it.
+const NEUTRAL_EXPOSURE: u32 = 0x0800;
+
+/// Mapper of the Game Boy Camera. The registers of its M64282FP image sensor
+/// and capture logic are mapped to 0xa000-0xbfff while bit 4 of the RAM bank
+/// is set.
+///
+/// Instead of a live sensor, a grayscale image (0 is black, 255 is white)
+/// is captured, scaled to 128x112. Brightness follows the exposure time and
+/// the result is dithered to 2bpp with the thresholds set by the game. Edge
+/// enhancement and gain are not emulated.
+#[derive(Clone)]
+pub struct Camera {
+    /// RAM write enable
+    ram_enable: bool,
+    /// ROM bank number
+    rom_bank_no: u8,
+    /// RAM bank number or camera register select
+    ram_bank_no: u8,
+    /// Registers as written by the game
+    regs: [u8; NUM_REGS],
+    /// Clocks until the capture in progress completes
+    countdown: u32,
+    /// Image seen by the sensor, shared between snapshots
+    image: Arc<Vec<u8>>,
+}
+
+impl Camera {
+    /// Creates a new `Camera` seeing a gradient, brighter to the right.
+    pub fn new() -> Self {
+        let image = (0..WIDTH * HEIGHT)
+            .map(|i| (i % WIDTH * 255 / (WIDTH - 1)) as u8)
+            .collect();
+
+        Camera {
+            ram_enable: false,
+            rom_bank_no: 0,
+            ram_bank_no: 0,
+            regs: [0; NUM_REGS],
+            countdown: 0,
+            image: Arc::new(image),
+        }
+    }
+
+    /// Returns true while a capture is in progress.
+    fn busy(&self) -> bool {
+        self.countdown > 0
+    }
+
+    /// Returns true if the camera registers are mapped to 0xa000-0xbfff.
+    fn regs_mapped(&self) -> bool {
+        self.ram_bank_no & 0x10 > 0
+    }
+
+    /// Handles a write to the camera registers.
+    fn write_regs(&mut self, addr: u16, val: u8) {
+        let reg = (addr & 0x7f) as usize;
+
+        if reg >= NUM_REGS {
+            return;
+        }
+
+        if reg == 0 {
+            // Writing 0 to bit 0 aborts the capture
+            if val & 0x01 == 0 {
+                self.countdown = 0;
+            } else if !self.busy() {
+                self.countdown = self.capture_clocks();
+            }
+        }
+
+        self.regs[reg] = val;
+    }
+
+    /// Handles a read from the camera registers. Only the control register
+    /// is readable, with bit 0 telling if a capture is in progress.
+    fn read_regs(&self, addr: u16) -> u8 {
+        match addr & 0x7f {
+            0x00 => (self.regs[0] & 0x06) | self.busy() as u8,
+            _ => 0x00,
+        }
+    }
+
+    /// Returns the exposure time (0xa002-0xa003).
+    fn exposure(&self) -> u32 {
+        u32::from(self.regs[2]) << 8 | u32::from(self.regs[3])
+    }
+
+    /// Returns the system clocks a capture takes with the current settings.
+    fn capture_clocks(&self) -> u32 {
+        let n = if self.regs[1] & 0x80 > 0 { 0 } else { 512 };
+
+        4 * (32446 + n + 16 * self.exposure())
+    }
+
+    /// Captures the image, dithers it and stores it as tiles in `ram`.
+    fn capture(&self, ram: &mut [u8]) {
+        if ram.len() < IMAGE_OFFSET + WIDTH * HEIGHT / 4 {
+            warn!(target: "gbr::mbc", "Camera RAM too small, discarding image");
+            return;
+        }
+
+        let exposure = self.exposure();
+        let mut tiles = vec![0; WIDTH * HEIGHT / 4];
+
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let brightness = u32::from(self.image[y * WIDTH + x]) * exposure / NEUTRAL_EXPOSURE;
+                let brightness = brightness.min(0xff) as u8;
+
+                let base = 0x06 + ((y & 3) * 4 + (x & 3)) * 3;
+                let color = match brightness {
+                    b if b < self.regs[base] => 3,
+                    b if b < self.regs[base + 1] => 2,
+                    b if b < self.regs[base + 2] => 1,
+                    _ => 0,
+                };
+
+                let offset = ((y / 8) * (WIDTH / 8) + x / 8) * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                tiles[offset] |= (color & 1) << bit;
+                tiles[offset + 1] |= (color >> 1) << bit;
+            }
+        }
+
+        ram[IMAGE_OFFSET..IMAGE_OFFSET + WIDTH * HEIGHT / 4].copy_from_slice(&tiles);
+    }
+}
+
+impl Mbc for Camera {
+    fn write(&mut self, mem: &mut Memory, addr: u16, val: u8) {
+        match addr {
+            // RAM write enable
+            0x0000..=0x1fff => self.ram_enable = val & 0x0f == 0x0a,
+            // ROM bank number
+            0x2000..=0x3fff => self.rom_bank_no = val & 0x3f,
+            // RAM bank number or camera register select
+            0x4000..=0x5fff => self.ram_bank_no = val,
+            0x6000..=0x7fff => (),
+            // RAM bank 00-0f or camera registers
+            0xa000..=0xbfff if self.regs_mapped() => self.write_regs(addr, val),
+            0xa000..=0xbfff => {
+                if self.ram_enable {
+                    mem.write_ram((self.ram_bank_no & 0x0f) as usize, addr, val);
+                }
+            }
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn read(&self, mem: &Memory, addr: u16) -> Option<u8> {
+        match addr {
+            // ROM bank 00
+            0x0000..=0x3fff => Some(mem.read_rom(0, addr)),
+            // ROM bank 00-3f
+            0x4000..=0x7fff => Some(mem.read_rom(self.rom_bank_no(), addr)),
+            // RAM bank 00-0f, readable even while writes are disabled, or
+            // camera registers
+            0xa000..=0xbfff if self.regs_mapped() => Some(self.read_regs(addr)),
+            0xa000..=0xbfff => Some(mem.read_ram((self.ram_bank_no & 0x0f) as usize, addr)),
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn rom_bank_no(&self) -> usize {
+        self.rom_bank_no as usize
+    }
+
+    /// Resets the registers, aborting any capture. The image is kept.
+    fn reset(&mut self) {
+        let image = self.image.clone();
+
+        *self = Camera::new();
+        self.image = image;
+    }
+
+    /// Progresses a capture. The image is written to RAM when the capture
+    /// completes.
+    fn update(&mut self, mem: &mut Memory, tick: u8) {
+        if !self.busy() {
+            return;
+        }
+
+        self.countdown = self.countdown.saturating_sub(tick as u32);
+
+        if !self.busy() {
+            self.regs[0] &= !0x01;
+            self.capture(&mut mem.ram);
+        }
+    }
+
+    /// Sets the image seen by the sensor from grayscale pixels, scaling it to
+    /// 128x112.
+    fn set_camera_image(&mut self, pixels: &[u8], width: usize, height: usize) {
+        let image = (0..WIDTH * HEIGHT)
+            .map(|i| {
+                let (x, y) = (i % WIDTH * width / WIDTH, i / WIDTH * height / HEIGHT);
+                pixels[y * width + x]
+            })
+            .collect();
+
+        self.image = Arc::new(image);
+    }
+
+    fn box_clone(&self) -> Box<dyn Mbc> {
+        Box::new(self.clone())
+    }
+}
+
+impl Savestate for Camera {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.bool(&mut self.ram_enable);
+        buf.u8(&mut self.rom_bank_no);
+        buf.u8(&mut self.ram_bank_no);
+        buf.bytes(&mut self.regs);
+        buf.u32(&mut self.countdown);
+    }
+}
@@ -0,0 +1,80 @@
+/// Features implemented by this build of the emulator, so that frontends
+/// can disable unsupported features up front.
+pub struct Capabilities {
+    /// Emulator version
+    pub version: &'static str,
+    /// Supported catridge types (values of 0x0147) and their names
+    pub mappers: &'static [(u8, &'static str)],
+    /// Emulated hardware models
+    pub models: &'static [&'static str],
+    /// Optional behaviors that trade accuracy for convenience
+    pub accuracy_options: &'static [&'static str],
+}
+
+/// Catridge types handled by `Catridge`.
+const MAPPERS: [(u8, &str); 25] = [
+    (0x00, "ROM ONLY"),
+    (0x01, "MBC1"),
+    (0x02, "MBC1+RAM"),
+    (0x03, "MBC1+RAM+BATTERY"),
+    (0x05, "MBC2"),
+    (0x06, "MBC2+BATTERY"),
+    (0x08, "ROM+RAM"),
+    (0x09, "ROM+RAM+BATTERY"),
+    (0x0b, "MMM01"),
+    (0x0c, "MMM01+RAM"),
+    (0x0d, "MMM01+RAM+BATTERY"),
+    (0x0f, "MBC3+TIMER+BATTERY"),
+    (0x10, "MBC3+TIMER+RAM+BATTERY"),
+    (0x11, "MBC3"),
+    (0x12, "MBC3+RAM"),
+    (0x13, "MBC3+RAM+BATTERY"),
+    (0x19, "MBC5"),
+    (0x1a, "MBC5+RAM"),
+    (0x1b, "MBC5+RAM+BATTERY"),
+    (0x1c, "MBC5+RUMBLE"),
+    (0x1d, "MBC5+RUMBLE+RAM"),
+    (0x1e, "MBC5+RUMBLE+RAM+BATTERY"),
+    (0x22, "MBC7+SENSOR+RUMBLE+RAM+BATTERY"),
+    (0xfc, "POCKET CAMERA"),
+    (0xfe, "HuC3"),
+];
+
+/// Returns true if a catridge type is supported.
+pub fn supports_mapper(mbc_type: u8) -> bool {
+    MAPPERS.iter().any(|&(t, _)| t == mbc_type)
+}
+
+/// Returns the capabilities of this build.
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        mappers: &MAPPERS,
+        models: &["DMG"],
+        accuracy_options: &[
+            "no-sprite-limit",
+            "overclock",
+            "irq-latency",
+            "blend-frames",
+            "accuracy",
+        ],
+    }
+}
+
+/// Prints the capabilities as `key: value` lines. Returns the exit code.
+pub fn main(_args: &[String]) -> i32 {
+    let caps = capabilities();
+
+    println!("version: {}", caps.version);
+    for &(mbc_type, name) in caps.mappers.iter() {
+        println!("mapper: 0x{:02x} {}", mbc_type, name);
+    }
+    for model in caps.models {
+        println!("model: {}", model);
+    }
+    for option in caps.accuracy_options {
+        println!("accuracy-option: {}", option);
+    }
+
+    0
+}
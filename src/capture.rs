@@ -0,0 +1,49 @@
+/// Capture taken the first time an address is executed, for building test
+/// fixtures and bug reports at an exact program point.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Capture {
+    /// Address of the instruction to capture before
+    pub pc: u16,
+    /// Keep a save state of the CPU
+    pub state: bool,
+    /// Save the frame being rendered as a PNG file
+    pub frame: bool,
+    /// Exit after capturing
+    pub exit: bool,
+}
+
+impl Capture {
+    /// Parses a capture given as `pc=<addr>:<what>`, where `<what>` is a
+    /// comma-separated list of `state`, `frame` and `exit`, e.g.
+    /// `pc=0x1234:state,frame`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let pos = spec
+            .find(':')
+            .ok_or_else(|| String::from("Expected pc=<addr>:<what>"))?;
+
+        let addr = &spec[..pos];
+        if !addr.starts_with("pc=") {
+            return Err(format!("Expected pc=<addr>: {}", addr));
+        }
+        let addr = addr[3..].trim_start_matches("0x");
+        let pc = u16::from_str_radix(addr, 16).map_err(|_| format!("Invalid address: {}", addr))?;
+
+        let mut capture = Capture {
+            pc,
+            state: false,
+            frame: false,
+            exit: false,
+        };
+
+        for what in spec[pos + 1..].split(',') {
+            match what {
+                "state" => capture.state = true,
+                "frame" => capture.frame = true,
+                "exit" => capture.exit = true,
+                _ => return Err(format!("Unknown capture: {}", what)),
+            }
+        }
+
+        Ok(capture)
+    }
+}
@@ -1,190 +1,379 @@
-use std::fs::File;
-use std::io::{Read, Write};
+use std::cell::Cell;
+use std::io;
+use std::sync::Arc;
 
+use capabilities;
+use catridge_error::CatridgeError;
+use catridge_info::CatridgeInfo;
 use io_device::IODevice;
+use mbc::{self, Mbc, Memory};
+use rng::Rng;
+use rtc::Rtc;
+use save_file;
+use savestate::{Savestate, StateBuffer};
+use storage::StorageBackend;
+use unlicensed;
 
+/// Value read from catridge RAM while it is disabled.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DisabledRam {
+    /// A fixed value (most carts pull the data bus up to 0xff)
+    Value(u8),
+    /// The last value on the data bus
+    OpenBus,
+    /// A pseudo-random value on every read
+    Random,
+}
+
+impl DisabledRam {
+    /// Parses a policy: `open-bus`, `random` or a hex value (e.g. `ff`).
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "open-bus" => Some(DisabledRam::OpenBus),
+            "random" => Some(DisabledRam::Random),
+            _ => u8::from_str_radix(name, 16).ok().map(DisabledRam::Value),
+        }
+    }
+
+    /// Returns the policy of a catridge type.
+    fn of_mapper(mbc_type: u8) -> Self {
+        match mbc_type {
+            // MBC1 and MBC3 pull the data bus up
+            0x01..=0x03 | 0x0f..=0x13 => DisabledRam::Value(0xff),
+            // Nothing drives the data bus on other carts
+            _ => DisabledRam::OpenBus,
+        }
+    }
+}
+
+/// Contents of catridge RAM at power-on, before any save is loaded.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RamInit {
+    /// Every byte set to a value
+    Value(u8),
+    /// Pseudo-random bytes derived from a seed
+    Random(u64),
+}
+
+impl RamInit {
+    /// Parses a pattern: a hex value (e.g. `ff`) or `random[:<seed>]`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "random" => Some(RamInit::Random(0)),
+            _ if name.starts_with("random:") => name[7..].parse().ok().map(RamInit::Random),
+            _ => u8::from_str_radix(name, 16).ok().map(RamInit::Value),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Catridge {
-    rom: Vec<u8>,
-    ram: Vec<u8>,
-    #[allow(dead_code)]
-    mbc_type: u8,
-    ram_enable: bool,
-    bank_no_upper: u8,
-    bank_no_lower: u8,
-    num_rom_banks: u8,
-    mode: bool,
+    /// ROM and RAM
+    mem: Memory,
+    /// Mapper of the catridge type
+    mbc: Box<dyn Mbc>,
+    /// Advance the RTC only by emulated clocks, even across sessions
+    emulated_rtc: bool,
+    /// Metadata parsed from the header
+    info: CatridgeInfo,
+    /// Value read from RAM while it is disabled
+    disabled_ram: DisabledRam,
+    /// Last value on the data bus
+    open_bus: Cell<u8>,
+    /// Source of random values read from disabled RAM
+    noise: Cell<Rng>,
 }
 
 impl Catridge {
-    pub fn new(fname: &str) -> Self {
-        let mut rom = Vec::new();
-        let mut file = File::open(fname).unwrap();
-        file.read_to_end(&mut rom).unwrap();
-
-        let rom_size: usize = match rom[0x0148] {
-            0 => 32 * 1024,
-            n => 32 * 1024 << (n as usize),
-        };
-
-        let num_rom_banks = 2 << rom[0x0148];
+    /// Loads a catridge from a ROM image.
+    pub fn from_bytes(rom: Vec<u8>) -> Result<Self, CatridgeError> {
+        let info = CatridgeInfo::from_rom(&rom)?;
 
-        let ram_size: usize = match rom[0x0149] {
-            0 => 0,
-            1 => 2 * 1024,
-            2 => 8 * 1024,
-            3 => 32 * 1024,
-            4 => 128 * 1024,
-            5 => 64 * 1024,
-            _ => panic!("RAM size invalid"),
+        let rom = if info.rom_size != rom.len() {
+            warn!(
+                target: "gbr::mbc",
+                "ROM size {}KB does not match the header ({}KB), mirroring",
+                rom.len() / 1024,
+                info.rom_size / 1024
+            );
+            Self::mirror(rom, info.rom_size)
+        } else {
+            rom
         };
 
-        let mbc_type = rom[0x0147];
-
-        let mbc_name = match mbc_type {
-            0x00 => "ROM ONLY",
-            0x01 => "MBC1",
-            0x02 => "MBC1+RAM",
-            0x03 => "MBC1+RAM+BATTERY",
-            0x05 => "MBC2",
-            0x06 => "MBC2+BATTERY",
-            0x08 => "ROM+RAM",
-            0x09 => "ROM+RAM+BATTERY",
-            0x0b => "MMM01",
-            0x0c => "MMM01+RAM",
-            0x0d => "MMM01+RAM+BATTERY",
-            0x0f => "MBC3+TIMER+BATTERY",
-            0x10 => "MBC3+TIMER+RAM+BATTERY",
-            0x11 => "MBC3",
-            0x12 => "MBC3+RAM",
-            0x13 => "MBC3+RAM+BATTERY",
-            0x19 => "MBC5",
-            0x1a => "MBC5+RAM",
-            0x1b => "MBC5+RAM+BATTERY",
-            0x1c => "MBC5+RUMBLE",
-            0x1d => "MBC5+RUMBLE+RAM",
-            0x1e => "MBC5+RUMBLE+RAM+BATTERY",
-            0x20 => "MBC6",
-            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
-            0xfc => "POCKET CAMERA",
-            0xfd => "BANDAI TAMA5",
-            0xfe => "HuC3",
-            0xff => "HuC1+RAM+BATTERY",
-            _ => "Unknown",
-        };
+        // Homebrew and test ROMs often leave the header checksum unfixed
+        if !info.header_checksum_valid {
+            warn!(target: "gbr::mbc", "ROM header checksum is incorrect");
+        }
+
+        info!(target: "gbr::mbc", "ROM size {}KB", info.rom_size / 1024);
+        info!(target: "gbr::mbc", "RAM size {}KB", info.ram_size / 1024);
+        info!(target: "gbr::mbc", "Title {}", info.title);
+        info!(target: "gbr::mbc", "MBC type {}", info.mapper);
+        info!(target: "gbr::mbc", "CGB {}, SGB {}", info.cgb, info.sgb);
 
-        let mut chksum: u8 = 0;
-        for i in 0x0134..0x014d {
-            chksum = chksum.wrapping_sub(rom[i]).wrapping_sub(1);
+        if let Some(mapper) = unlicensed::detect(&rom, &info) {
+            info!(target: "gbr::mbc", "Unlicensed mapper {:?}", mapper);
+        } else if !capabilities::supports_mapper(info.mbc_type) {
+            return Err(CatridgeError::UnsupportedMapper(info.mbc_type, info.mapper));
         }
 
-        if rom_size != rom.len() {
-            panic!("ROM file invalid");
+        if info.cgb_only {
+            warn!(target: "gbr::mbc", "CGB only catridges are not supported");
         }
 
-        if chksum != rom[0x014d] {
-            panic!("ROM header checksum is incorrect");
+        // The boot ROM does not verify the global checksum
+        if !info.global_checksum_valid {
+            warn!(target: "gbr::mbc", "ROM global checksum is incorrect");
         }
 
-        info!("ROM size {}KB", rom_size / 1024);
-        info!("RAM size {}KB", ram_size / 1024);
-        info!("MBC type {}", mbc_name);
+        let mbc = mbc::from_rom(&rom, &info);
+
+        Ok(Catridge {
+            mem: Memory {
+                rom: Arc::new(rom),
+                ram: vec![0; info.ram_size],
+            },
+            mbc,
+            emulated_rtc: false,
+            disabled_ram: DisabledRam::of_mapper(info.mbc_type),
+            open_bus: Cell::new(0xff),
+            noise: Cell::new(Rng::new(0)),
+            info,
+        })
+    }
 
-        Catridge {
-            rom: rom,
-            ram: vec![0; ram_size],
-            mbc_type: mbc_type,
-            ram_enable: false,
-            bank_no_upper: 0,
-            bank_no_lower: 0,
-            num_rom_banks: num_rom_banks,
-            mode: false,
+    /// Pads a ROM that is not a power of two in size (e.g. homebrew or
+    /// trimmed ROMs) to at least `size` bytes by repeating it, like the
+    /// unconnected address lines of a smaller ROM chip do.
+    fn mirror(rom: Vec<u8>, size: usize) -> Vec<u8> {
+        let size = size.max(rom.len().next_power_of_two());
+
+        (0..size).map(|i| rom[i % rom.len()]).collect()
+    }
+
+    /// Returns the metadata parsed from the catridge header.
+    pub fn info(&self) -> &CatridgeInfo {
+        &self.info
+    }
+
+    /// Returns true if the catridge has an RTC.
+    fn has_rtc(&self) -> bool {
+        self.info.has_rtc
+    }
+
+    /// Returns true if the catridge has a battery backing its RAM or RTC.
+    fn has_battery(&self) -> bool {
+        self.info.has_battery
+    }
+
+    /// Returns true if the catridge has any state to persist in a save file.
+    fn has_save_data(&self) -> bool {
+        self.has_battery() && (!self.mem.ram.is_empty() || self.has_rtc())
+    }
+
+    /// Makes the RTC advance only by emulated clocks. Host time elapsed
+    /// between sessions is ignored, keeping the clock deterministic.
+    pub fn set_emulated_rtc(&mut self, enable: bool) {
+        self.emulated_rtc = enable;
+    }
+
+    /// Resets the mapper registers. RAM and the RTC are kept.
+    pub fn reset(&mut self) {
+        self.mbc.reset();
+    }
+
+    /// Sets the tilt sensed by the accelerometer of MBC7 catridges in g
+    /// (-1.0 to 1.0 on each axis), positive to the right and towards the
+    /// player.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mbc.set_tilt(x, y);
+    }
+
+    /// Sets the image seen by the Game Boy Camera from grayscale pixels (0 is
+    /// black, 255 is white).
+    pub fn set_camera_image(&mut self, pixels: &[u8], width: usize, height: usize) {
+        self.mbc.set_camera_image(pixels, width, height);
+    }
+
+    /// Fills RAM with random data, like uninitialized SRAM. Also seeds the
+    /// values read from disabled RAM.
+    pub fn randomize_ram(&mut self, rng: &mut Rng) {
+        rng.fill(&mut self.mem.ram);
+        self.noise.set(Rng::new(rng.next_u64()));
+    }
+
+    /// Fills RAM with a pattern, like the garbage in SRAM that has never
+    /// been backed up by a battery.
+    pub fn init_ram(&mut self, pattern: RamInit) {
+        match pattern {
+            RamInit::Value(val) => {
+                for b in self.mem.ram.iter_mut() {
+                    *b = val;
+                }
+            }
+            RamInit::Random(seed) => Rng::new(seed).fill(&mut self.mem.ram),
         }
     }
 
-    fn rom_bank_no(&self) -> u8 {
-        let bank_no = if self.mode {
-            self.bank_no_lower
-        } else {
-            self.bank_no_upper << 5 | self.bank_no_lower
-        };
+    /// Returns the RAM.
+    pub fn ram(&self) -> &[u8] {
+        &self.mem.ram
+    }
 
-        let bank_no = match bank_no {
-            0 | 0x20 | 0x40 | 0x60 => bank_no + 1,
-            _ => bank_no,
-        };
+    /// Overwrites the RAM. RAM of another size (e.g. from a different
+    /// catridge) is ignored.
+    pub fn load_ram(&mut self, ram: &[u8]) {
+        if ram.len() != self.mem.ram.len() {
+            warn!(target: "gbr::mbc", "Ignoring RAM of {} bytes", ram.len());
+            return;
+        }
 
-        bank_no & (self.num_rom_banks - 1)
+        self.mem.ram.copy_from_slice(ram);
     }
 
-    fn ram_bank_no(&self) -> u8 {
-        if self.mode {
-            self.bank_no_upper
-        } else {
-            0
+    /// Overrides the value read from RAM while it is disabled.
+    pub fn set_disabled_ram(&mut self, policy: DisabledRam) {
+        self.disabled_ram = policy;
+    }
+
+    /// Records the last value on the data bus, read back as open bus.
+    pub fn set_open_bus(&self, val: u8) {
+        self.open_bus.set(val);
+    }
+
+    /// Returns the value read from RAM while it is disabled.
+    fn disabled_ram_read(&self) -> u8 {
+        match self.disabled_ram {
+            DisabledRam::Value(val) => val,
+            DisabledRam::OpenBus => self.open_bus.get(),
+            DisabledRam::Random => {
+                let mut noise = self.noise.get();
+                let val = noise.next_u64() as u8;
+                self.noise.set(noise);
+                val
+            }
         }
     }
 
-    pub fn read_save_file(&mut self, fname: &str) {
-        info!("Reading save file from: {}", fname);
+    /// Reads a byte like `read`, but without advancing the random values read
+    /// from disabled RAM.
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.mbc
+            .read(&self.mem, addr)
+            .unwrap_or_else(|| match self.disabled_ram {
+                DisabledRam::Random => self.noise.get().next_u64() as u8,
+                _ => self.disabled_ram_read(),
+            })
+    }
+
+    /// Returns the number of ROM banks.
+    pub fn num_rom_banks(&self) -> usize {
+        self.mem.num_rom_banks()
+    }
 
-        if let Ok(mut file) = File::open(fname) {
-            self.ram = Vec::new();
-            file.read_to_end(&mut self.ram).unwrap();
+    /// Returns the ROM bank mapped to 0x4000-0x7fff.
+    pub fn rom_bank_no(&self) -> usize {
+        self.mbc.rom_bank_no() & (self.num_rom_banks() - 1)
+    }
+
+    /// Loads the battery save stored under a key, if the catridge has one.
+    pub fn load_save(&mut self, storage: &dyn StorageBackend, key: &str) {
+        if !self.has_save_data() {
+            return;
+        }
+
+        info!(target: "gbr::mbc", "Reading save file from: {}", key);
+
+        let data = match storage.load(key) {
+            Ok(data) => data,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return,
+            Err(e) => {
+                warn!(target: "gbr::mbc", "Failed to read save file {}: {}", key, e);
+                return;
+            }
+        };
+
+        let mbc2 = self.info.mbc_type == 0x05 || self.info.mbc_type == 0x06;
+        let save = save_file::normalize(data, self.info.ram_size, self.has_rtc(), mbc2);
+
+        self.mem.ram = save.ram;
+        if let (Some(footer), Some(rtc)) = (save.rtc, self.mbc.rtc()) {
+            rtc.load(&footer, !self.emulated_rtc);
         }
     }
 
-    pub fn write_save_file(&mut self, fname: &str) {
-        info!("Writing save file to: {}", fname);
+    /// Stores the battery save under a key, if the catridge has one.
+    pub fn store_save(&mut self, storage: &mut dyn StorageBackend, key: &str) {
+        if !self.has_save_data() {
+            return;
+        }
+
+        info!(target: "gbr::mbc", "Writing save file to: {}", key);
+
+        let mut data = self.mem.ram.clone();
+        if self.has_rtc() {
+            if let Some(rtc) = self.mbc.rtc() {
+                data.extend_from_slice(&rtc.save());
+            }
+        }
 
-        if let Ok(mut file) = File::create(fname) {
-            file.write_all(&mut self.ram).unwrap();
+        if let Err(e) = storage.store(key, &data) {
+            warn!(target: "gbr::mbc", "Failed to write save file {}: {}", key, e);
         }
     }
 }
 
 impl IODevice for Catridge {
     fn write(&mut self, addr: u16, val: u8) {
-        match addr {
-            // RAM enable
-            0x0000..=0x1fff => self.ram_enable = val & 0x0f == 0x0a,
-            // ROM bank number (lower 5 bits)
-            0x2000..=0x3fff => self.bank_no_lower = val & 0x1f,
-            // RAM bank number or ROM bank number (upper 2 bits)
-            0x4000..=0x5fff => self.bank_no_upper = val & 0x03,
-            // ROM/RAM mode select
-            0x6000..=0x7fff => self.mode = val & 0x01 > 0,
-            // RAM bank 00-03
-            0xa000..=0xbfff => {
-                if !self.ram_enable {
-                    return;
-                }
-                let offset = (8 * 1024) * self.ram_bank_no() as usize;
-                self.ram[(addr & 0x1fff) as usize + offset] = val
-            }
-            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
-        }
+        self.mbc.write(&mut self.mem, addr, val)
     }
 
     fn read(&self, addr: u16) -> u8 {
-        match addr {
-            // ROM bank 00
-            0x0000..=0x3fff => self.rom[addr as usize],
-            // ROM bank 01-7f
-            0x4000..=0x7fff => {
-                let offset = (16 * 1024) * self.rom_bank_no() as usize;
-                self.rom[(addr & 0x3fff) as usize + offset]
-            }
-            // RAM bank 00-03
-            0xa000..=0xbfff => {
-                if !self.ram_enable {
-                    return 0xff;
-                }
-                let offset = (8 * 1024) * self.ram_bank_no() as usize;
-                self.ram[(addr & 0x1fff) as usize + offset]
+        self.mbc
+            .read(&self.mem, addr)
+            .unwrap_or_else(|| self.disabled_ram_read())
+    }
+
+    fn update(&mut self, tick: u8) {
+        self.mbc.update(&mut self.mem, tick);
+    }
+}
+
+impl Savestate for Catridge {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        // Header and global checksums tell ROMs apart
+        let header = CatridgeInfo::header(&self.mem.rom);
+        let expected = [header[0x014d], header[0x014e], header[0x014f]];
+        let mut checksums = expected;
+        buf.bytes(&mut checksums);
+        if checksums != expected {
+            buf.fail("resume state is for another ROM");
+        }
+
+        let ram_size = self.mem.ram.len();
+        buf.vec(&mut self.mem.ram);
+        if self.mem.ram.len() != ram_size {
+            buf.fail("resume state is for another ROM");
+        }
+        self.mbc.savestate(buf);
+
+        // Like the save file footer, the state keeps the host time so that the
+        // clock catches up with the time spent suspended
+        let emulated_rtc = self.emulated_rtc;
+        if let Some(rtc) = self.mbc.rtc() {
+            let mut timestamp = Rtc::now();
+            buf.u64(&mut timestamp);
+            if buf.is_loading() && !emulated_rtc {
+                rtc.advance_secs(Rtc::now().saturating_sub(timestamp));
             }
-            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
         }
-    }
 
-    fn update(&mut self, _tick: u8) {}
+        let mut open_bus = self.open_bus.get();
+        let mut noise = self.noise.get();
+        buf.u8(&mut open_bus);
+        noise.savestate(buf);
+        self.open_bus.set(open_bus);
+        self.noise.set(noise);
+    }
 }
@@ -0,0 +1,66 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Reason a ROM image cannot be loaded as a catridge.
+#[derive(Debug)]
+pub enum CatridgeError {
+    /// The ROM image could not be read
+    Read(io::Error),
+    /// The ROM image could not be downloaded
+    Download(String),
+    /// The patch could not be read or applied to the ROM image
+    Patch(String),
+    /// The ROM image is too small to hold a header
+    Truncated(usize),
+    /// The ROM size in the header (0x0148) is invalid
+    InvalidRomSize(u8),
+    /// The RAM size in the header (0x0149) is invalid
+    InvalidRamSize(u8),
+    /// The catridge type (0x0147) is not supported
+    UnsupportedMapper(u8, &'static str),
+    /// The boot ROM could not be read
+    BootRomRead(io::Error),
+    /// The boot ROM is not 256 bytes
+    InvalidBootRom(usize),
+    /// The boot ROM did not finish, e.g. on a bad logo or header checksum
+    BootRomLockup,
+}
+
+impl fmt::Display for CatridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CatridgeError::Read(ref e) => write!(f, "{}", e),
+            CatridgeError::Download(ref reason) => write!(f, "Download failed ({})", reason),
+            CatridgeError::Patch(ref reason) => write!(f, "Patching failed ({})", reason),
+            CatridgeError::Truncated(len) => {
+                write!(f, "ROM is only {} bytes, too small to hold a header", len)
+            }
+            CatridgeError::InvalidRomSize(val) => {
+                write!(f, "Invalid ROM size 0x{:02x} in the header", val)
+            }
+            CatridgeError::InvalidRamSize(val) => {
+                write!(f, "Invalid RAM size 0x{:02x} in the header", val)
+            }
+            CatridgeError::UnsupportedMapper(mbc_type, name) => {
+                write!(f, "{} (0x{:02x}) is not supported", name, mbc_type)
+            }
+            CatridgeError::BootRomRead(ref e) => write!(f, "Failed to read the boot ROM ({})", e),
+            CatridgeError::InvalidBootRom(len) => {
+                write!(f, "Boot ROM is {} bytes instead of 256", len)
+            }
+            CatridgeError::BootRomLockup => write!(
+                f,
+                "The boot ROM locked up, as it does on a bad logo or header checksum"
+            ),
+        }
+    }
+}
+
+impl error::Error for CatridgeError {}
+
+impl From<io::Error> for CatridgeError {
+    fn from(e: io::Error) -> Self {
+        CatridgeError::Read(e)
+    }
+}
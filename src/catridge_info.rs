@@ -0,0 +1,169 @@
+use catridge_error::CatridgeError;
+use mbc7::EEPROM_SIZE;
+use save_file::MBC2_RAM_SIZE;
+
+/// Metadata parsed from the catridge header.
+#[derive(Clone)]
+pub struct CatridgeInfo {
+    /// Game title
+    pub title: String,
+    /// Catridge type (0x0147)
+    pub mbc_type: u8,
+    /// Name of the catridge type (e.g. "MBC1+RAM+BATTERY")
+    pub mapper: &'static str,
+    /// ROM size in bytes
+    pub rom_size: usize,
+    /// RAM size in bytes
+    pub ram_size: usize,
+    /// Supports CGB functions
+    pub cgb: bool,
+    /// Works on CGB only
+    pub cgb_only: bool,
+    /// Supports SGB functions
+    pub sgb: bool,
+    /// Header checksum (0x014d) matches the header
+    pub header_checksum_valid: bool,
+    /// Global checksum (0x014e-0x014f) matches the ROM
+    pub global_checksum_valid: bool,
+    /// Has a battery backing its RAM or RTC
+    pub has_battery: bool,
+    /// Has an RTC
+    pub has_rtc: bool,
+}
+
+/// Returns the name of a catridge type.
+fn mapper_name(mbc_type: u8) -> &'static str {
+    match mbc_type {
+        0x00 => "ROM ONLY",
+        0x01 => "MBC1",
+        0x02 => "MBC1+RAM",
+        0x03 => "MBC1+RAM+BATTERY",
+        0x05 => "MBC2",
+        0x06 => "MBC2+BATTERY",
+        0x08 => "ROM+RAM",
+        0x09 => "ROM+RAM+BATTERY",
+        0x0b => "MMM01",
+        0x0c => "MMM01+RAM",
+        0x0d => "MMM01+RAM+BATTERY",
+        0x0f => "MBC3+TIMER+BATTERY",
+        0x10 => "MBC3+TIMER+RAM+BATTERY",
+        0x11 => "MBC3",
+        0x12 => "MBC3+RAM",
+        0x13 => "MBC3+RAM+BATTERY",
+        0x19 => "MBC5",
+        0x1a => "MBC5+RAM",
+        0x1b => "MBC5+RAM+BATTERY",
+        0x1c => "MBC5+RUMBLE",
+        0x1d => "MBC5+RUMBLE+RAM",
+        0x1e => "MBC5+RUMBLE+RAM+BATTERY",
+        0x20 => "MBC6",
+        0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+        0xfc => "POCKET CAMERA",
+        0xfd => "BANDAI TAMA5",
+        0xfe => "HuC3",
+        0xff => "HuC1+RAM+BATTERY",
+        _ => "Unknown",
+    }
+}
+
+impl CatridgeInfo {
+    /// Returns the part of a ROM image starting with the header of the whole
+    /// catridge. MMM01 multicarts boot the menu in the last 32KB, so the
+    /// header at the start is that of the first game.
+    pub fn header(rom: &[u8]) -> &[u8] {
+        if rom.len() > 0x8000 {
+            let menu = &rom[rom.len() - 0x8000..];
+            let chksum = menu[0x0134..0x014d]
+                .iter()
+                .fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1));
+
+            if (0x0b..=0x0d).contains(&menu[0x0147]) && chksum == menu[0x014d] {
+                return menu;
+            }
+        }
+
+        rom
+    }
+
+    /// Parses the header of a ROM image.
+    pub fn from_rom(rom: &[u8]) -> Result<Self, CatridgeError> {
+        if rom.len() < 0x0150 {
+            return Err(CatridgeError::Truncated(rom.len()));
+        }
+
+        let header = Self::header(rom);
+        let cgb_flag = header[0x0143];
+
+        // The last byte of the title is the CGB flag on CGB-aware catridges
+        let title_end = if cgb_flag & 0x80 > 0 { 0x0143 } else { 0x0144 };
+        let title = header[0x0134..title_end]
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| if c.is_ascii_graphic() { c as char } else { ' ' })
+            .collect::<String>()
+            .trim_end()
+            .to_string();
+
+        let rom_size = match header[0x0148] {
+            n @ 0x00..=0x08 => (32 * 1024) << (n as usize),
+            n => return Err(CatridgeError::InvalidRomSize(n)),
+        };
+
+        let mbc_type = header[0x0147];
+
+        let ram_size = match header[0x0149] {
+            // MBC2 has RAM built in and always reports none
+            _ if mbc_type == 0x05 || mbc_type == 0x06 => MBC2_RAM_SIZE,
+            // MBC7 saves to an EEPROM and reports no RAM
+            _ if mbc_type == 0x22 => EEPROM_SIZE,
+            0 => 0,
+            1 => 2 * 1024,
+            2 => 8 * 1024,
+            3 => 32 * 1024,
+            4 => 128 * 1024,
+            5 => 64 * 1024,
+            n => return Err(CatridgeError::InvalidRamSize(n)),
+        };
+
+        let mut chksum: u8 = 0;
+        for &byte in &header[0x0134..0x014d] {
+            chksum = chksum.wrapping_sub(byte).wrapping_sub(1);
+        }
+
+        let global_chksum = rom
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != 0x014e && i != 0x014f)
+            .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16));
+
+        Ok(CatridgeInfo {
+            title,
+            mbc_type,
+            mapper: mapper_name(mbc_type),
+            rom_size,
+            ram_size,
+            cgb: cgb_flag & 0x80 > 0,
+            cgb_only: cgb_flag == 0xc0,
+            sgb: header[0x0146] == 0x03,
+            header_checksum_valid: chksum == header[0x014d],
+            global_checksum_valid: global_chksum
+                == (header[0x014e] as u16) << 8 | header[0x014f] as u16,
+            has_battery: matches!(
+                mbc_type,
+                0x03 | 0x06
+                    | 0x09
+                    | 0x0d
+                    | 0x0f
+                    | 0x10
+                    | 0x13
+                    | 0x1b
+                    | 0x1e
+                    | 0x22
+                    | 0xfc
+                    | 0xfe
+                    | 0xff
+            ),
+            has_rtc: matches!(mbc_type, 0x0f | 0x10 | 0xfe),
+        })
+    }
+}
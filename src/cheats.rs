@@ -0,0 +1,171 @@
+/// Effect of a cheat code.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CheatKind {
+    /// Game Genie: replaces a ROM byte when it is read, optionally only if
+    /// the original byte matches (so that it applies to one bank only)
+    RomPatch {
+        addr: u16,
+        value: u8,
+        compare: Option<u8>,
+    },
+    /// GameShark: writes a byte to RAM every frame
+    RamWrite { addr: u16, value: u8 },
+}
+
+/// Cheat code.
+#[derive(Clone, Debug)]
+pub struct Cheat {
+    /// Code as entered by the user
+    pub code: String,
+    /// Effect
+    pub kind: CheatKind,
+    /// Cheat is applied
+    pub enabled: bool,
+}
+
+/// Parses a hex digit string.
+fn parse_hex(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s, 16).map_err(|_| format!("Invalid cheat code: {}", s))
+}
+
+impl Cheat {
+    /// Parses a Game Genie (`ABC-DEF` or `ABC-DEF-GHI`) or GameShark
+    /// (`ABCDEFGH`) code.
+    pub fn parse(code: &str) -> Result<Self, String> {
+        let code = code.trim();
+        let digits: String = code.chars().filter(|&c| c != '-').collect();
+
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("Invalid cheat code: {}", code));
+        }
+
+        let kind = match (digits.len(), code.contains('-')) {
+            // Game Genie: AB is the new value, FCDE the address XOR 0xf000 and
+            // GI the original value XOR 0xba rotated left by 2 (H is unused)
+            (6, true) | (9, true) => {
+                let value = parse_hex(&digits[0..2])? as u8;
+                let addr = parse_hex(&format!("{}{}", &digits[5..6], &digits[2..5]))? as u16;
+                let compare = if digits.len() == 9 {
+                    let gi = parse_hex(&format!("{}{}", &digits[6..7], &digits[8..9]))? as u8;
+                    Some(gi.rotate_right(2) ^ 0xba)
+                } else {
+                    None
+                };
+
+                CheatKind::RomPatch {
+                    addr: addr ^ 0xf000,
+                    value,
+                    compare,
+                }
+            }
+            // GameShark: AB is the RAM bank, CD the value and GHEF the address
+            (8, false) => {
+                let value = parse_hex(&digits[2..4])? as u8;
+                let addr = parse_hex(&format!("{}{}", &digits[6..8], &digits[4..6]))? as u16;
+
+                CheatKind::RamWrite { addr, value }
+            }
+            _ => return Err(format!("Invalid cheat code: {}", code)),
+        };
+
+        match kind {
+            CheatKind::RomPatch { addr, .. } if addr >= 0x8000 => {
+                Err(format!("Game Genie code outside ROM: {}", code))
+            }
+            CheatKind::RamWrite { addr, .. } if addr < 0x8000 => {
+                Err(format!("GameShark code outside RAM: {}", code))
+            }
+            kind => Ok(Cheat {
+                code: code.to_string(),
+                kind,
+                enabled: true,
+            }),
+        }
+    }
+}
+
+/// Cheat codes applied by the core. Frontends (CLI, OSD, ...) share this
+/// list to add, enable and disable cheats.
+#[derive(Clone)]
+pub struct Cheats {
+    /// Cheats in order of addition
+    cheats: Vec<Cheat>,
+    /// At least one enabled ROM patch exists, to keep ROM reads fast
+    has_rom_patches: bool,
+}
+
+impl Cheats {
+    /// Creates an empty cheat list.
+    pub fn new() -> Self {
+        Cheats {
+            cheats: Vec::new(),
+            has_rom_patches: false,
+        }
+    }
+
+    /// Parses and adds a cheat code. Returns its index.
+    pub fn add(&mut self, code: &str) -> Result<usize, String> {
+        let cheat = Cheat::parse(code)?;
+
+        info!("Cheat added: {} ({:?})", cheat.code, cheat.kind);
+
+        self.cheats.push(cheat);
+        self.update_flags();
+
+        Ok(self.cheats.len() - 1)
+    }
+
+    /// Enables or disables a cheat.
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        if let Some(cheat) = self.cheats.get_mut(index) {
+            cheat.enabled = enabled;
+        }
+        self.update_flags();
+    }
+
+    /// Returns all cheats.
+    pub fn list(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    fn update_flags(&mut self) {
+        self.has_rom_patches = self.cheats.iter().any(|c| match c.kind {
+            CheatKind::RomPatch { .. } => c.enabled,
+            _ => false,
+        });
+    }
+
+    /// Applies ROM patches to a byte read from ROM.
+    pub fn patch_rom(&self, addr: u16, val: u8) -> u8 {
+        if !self.has_rom_patches {
+            return val;
+        }
+
+        for cheat in self.cheats.iter().filter(|c| c.enabled) {
+            if let CheatKind::RomPatch {
+                addr: a,
+                value,
+                compare,
+            } = cheat.kind
+            {
+                if a == addr && compare.map(|c| c == val).unwrap_or(true) {
+                    return value;
+                }
+            }
+        }
+
+        val
+    }
+
+    /// Returns the RAM writes to apply at the start of a frame.
+    pub fn ram_writes(&self) -> Vec<(u16, u8)> {
+        self.cheats
+            .iter()
+            .filter(|c| c.enabled)
+            .filter_map(|c| match c.kind {
+                CheatKind::RamWrite { addr, value } => Some((addr, value)),
+                _ => None,
+            })
+            .collect()
+    }
+}
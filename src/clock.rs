@@ -0,0 +1,19 @@
+/// Number of system clocks per second.
+pub const CLOCKS_PER_SEC: u64 = 4_194_304;
+
+/// Emulated time at the start of a V-Blank, sent to external tools that lock
+/// to the emulated frame rate (about 59.73 Hz) instead of the host clock.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct VBlank {
+    /// Number of V-Blanks since power-on, starting from 1
+    pub frame: u64,
+    /// System clocks elapsed since power-on
+    pub clocks: u64,
+}
+
+impl VBlank {
+    /// Returns the emulated seconds elapsed since power-on.
+    pub fn secs(&self) -> f64 {
+        self.clocks as f64 / CLOCKS_PER_SEC as f64
+    }
+}
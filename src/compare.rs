@@ -0,0 +1,313 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::process::Command;
+
+use catridge_error::CatridgeError;
+use cpu::CYCLES_PER_FRAME;
+use emulator::EmulatorBuilder;
+use input_script::InputScript;
+use input_source::InputSource;
+
+/// Prints usage of `gbr trace` and `gbr compare`.
+fn usage() -> i32 {
+    eprintln!("Usage: gbr trace <rom> [options] [--detail <frame>]");
+    eprintln!("       gbr compare <gbr|trace> <rom> [options]");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("    --frames <n>    Number of frames to run (default: 600)");
+    eprintln!("    --input <file>  Replay joypad input from a script");
+    eprintln!("    --seed <n>      Seed power-on RAM");
+    1
+}
+
+/// Options shared by `gbr trace` and `gbr compare`.
+struct TraceOptions {
+    /// ROM filename
+    rom_fname: String,
+    /// Number of frames to run
+    frames: u64,
+    /// Input script filename
+    input_fname: Option<String>,
+    /// Seed for power-on RAM contents
+    seed: Option<u64>,
+    /// Frame to trace instruction by instruction (1-based)
+    detail: Option<u64>,
+    /// Other gbr binary or recorded trace (`gbr compare` only)
+    reference: Option<String>,
+}
+
+impl TraceOptions {
+    /// Parses options. `positional` is the number of positional arguments.
+    fn parse(args: &[String], positional: usize) -> Option<Self> {
+        let mut positionals = Vec::new();
+        let mut frames = 600;
+        let mut input_fname = None;
+        let mut seed = None;
+        let mut detail = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--frames" => frames = args.next()?.parse().ok()?,
+                "--input" => input_fname = Some(args.next()?.clone()),
+                "--seed" => seed = Some(args.next()?.parse().ok()?),
+                "--detail" => detail = Some(args.next()?.parse().ok()?),
+                _ if arg.starts_with("--") => return None,
+                _ => positionals.push(arg.clone()),
+            }
+        }
+
+        if positionals.len() != positional {
+            return None;
+        }
+
+        let rom_fname = positionals.pop()?;
+
+        Some(TraceOptions {
+            rom_fname,
+            frames,
+            input_fname,
+            seed,
+            detail,
+            reference: positionals.pop(),
+        })
+    }
+
+    /// Returns the arguments that make `gbr trace` reproduce this run.
+    fn trace_args(&self, detail: Option<u64>) -> Vec<String> {
+        let mut args = vec![
+            String::from("trace"),
+            self.rom_fname.clone(),
+            String::from("--frames"),
+            self.frames.to_string(),
+        ];
+
+        if let Some(ref fname) = self.input_fname {
+            args.push(String::from("--input"));
+            args.push(fname.clone());
+        }
+        if let Some(seed) = self.seed {
+            args.push(String::from("--seed"));
+            args.push(seed.to_string());
+        }
+        if let Some(frame) = detail {
+            args.push(String::from("--detail"));
+            args.push(frame.to_string());
+        }
+
+        args
+    }
+}
+
+/// Runs a ROM and returns its trace: a `frame <n> <hash>` line per frame,
+/// preceded by a `step <registers>` line per instruction of the detail frame.
+fn trace(opts: &TraceOptions, detail: Option<u64>) -> Result<Vec<String>, String> {
+    let cpu = fs::read(&opts.rom_fname)
+        .map_err(CatridgeError::Read)
+        .and_then(|rom| {
+            let mut builder = EmulatorBuilder::new(rom);
+            if let Some(seed) = opts.seed {
+                builder = builder.seed(seed);
+            }
+            builder.build()
+        });
+    let mut cpu = cpu.map_err(|e| format!("Failed to load {}: {}", opts.rom_fname, e))?;
+
+    let mut input = match opts.input_fname {
+        Some(ref fname) => {
+            InputScript::new(fname).map_err(|e| format!("Failed to load {}: {}", fname, e))?
+        }
+        None => InputScript::empty(),
+    };
+    let mut lines = Vec::new();
+
+    for frame in 0..opts.frames {
+        cpu.mmu.joypad.set_input(&input.poll(frame));
+
+        if detail == Some(frame + 1) {
+            cpu.run_for_cycles_traced(CYCLES_PER_FRAME, |cpu| {
+                lines.push(format!("step {}", cpu.state_line()))
+            });
+        } else {
+            cpu.run_for_cycles(CYCLES_PER_FRAME);
+        }
+
+        lines.push(format!(
+            "frame {} {:016x}",
+            frame + 1,
+            cpu.mmu.ppu.frame_hash()
+        ));
+    }
+
+    Ok(lines)
+}
+
+/// Prints the trace of a ROM. Returns the exit code.
+pub fn trace_main(args: &[String]) -> i32 {
+    let opts = match TraceOptions::parse(args, 1) {
+        Some(opts) => opts,
+        None => return usage(),
+    };
+
+    match trace(&opts, opts.detail) {
+        Ok(lines) => {
+            for line in lines {
+                println!("{}", line);
+            }
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+/// Source of the trace compared against.
+enum Reference {
+    /// Another gbr binary, run with `gbr trace`
+    Binary(String),
+    /// Recorded output of `gbr trace`
+    Recorded(Vec<String>),
+}
+
+impl Reference {
+    /// Opens a reference. Files that start like a trace are read as one,
+    /// anything else is run as a gbr binary.
+    fn new(fname: &str) -> Result<Self, String> {
+        let mut content = Vec::new();
+        File::open(fname)
+            .and_then(|mut file| file.read_to_end(&mut content))
+            .map_err(|e| format!("Failed to read {}: {}", fname, e))?;
+
+        if content.starts_with(b"frame ") || content.starts_with(b"step ") {
+            let text = String::from_utf8_lossy(&content);
+            Ok(Reference::Recorded(
+                text.lines().map(String::from).collect(),
+            ))
+        } else {
+            Ok(Reference::Binary(fname.to_string()))
+        }
+    }
+
+    /// Returns the trace of the reference.
+    fn trace(&self, opts: &TraceOptions, detail: Option<u64>) -> Result<Vec<String>, String> {
+        match *self {
+            Reference::Recorded(ref lines) => Ok(lines.clone()),
+            Reference::Binary(ref bin) => {
+                let output = Command::new(bin)
+                    .args(opts.trace_args(detail))
+                    .output()
+                    .map_err(|e| format!("Failed to run {}: {}", bin, e))?;
+
+                if !output.status.success() {
+                    return Err(format!("{} exited with {}", bin, output.status));
+                }
+
+                let text = String::from_utf8_lossy(&output.stdout);
+                Ok(text.lines().map(String::from).collect())
+            }
+        }
+    }
+}
+
+/// Returns the lines of a trace starting with `prefix`.
+fn filter<'a>(lines: &'a [String], prefix: &'a str) -> Vec<&'a String> {
+    lines.iter().filter(|l| l.starts_with(prefix)).collect()
+}
+
+/// Runs a ROM under this build and a reference, and reports the first frame
+/// whose hash differs and the first differing instruction within it.
+/// Returns the exit code (0 if the traces match).
+pub fn compare_main(args: &[String]) -> i32 {
+    let opts = match TraceOptions::parse(args, 2) {
+        Some(opts) => opts,
+        None => return usage(),
+    };
+
+    let reference = match Reference::new(opts.reference.as_ref().unwrap()) {
+        Ok(reference) => reference,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+
+    let (ours, theirs) = match reference
+        .trace(&opts, None)
+        .and_then(|theirs| Ok((trace(&opts, None)?, theirs)))
+    {
+        Ok(traces) => traces,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+
+    let (our_frames, their_frames) = (filter(&ours, "frame "), filter(&theirs, "frame "));
+    let diverged = our_frames
+        .iter()
+        .zip(their_frames.iter())
+        .position(|(a, b)| a != b);
+
+    let frame = match diverged {
+        Some(i) => i as u64 + 1,
+        None if our_frames.len() != their_frames.len() => {
+            println!(
+                "Traces match but have {} and {} frames",
+                our_frames.len(),
+                their_frames.len()
+            );
+            return 1;
+        }
+        None => {
+            println!("Traces match ({} frames)", our_frames.len());
+            return 0;
+        }
+    };
+
+    println!("First divergent frame: {}", frame);
+    println!("  this build: {}", our_frames[frame as usize - 1]);
+    println!("  reference:  {}", their_frames[frame as usize - 1]);
+
+    // Trace the divergent frame instruction by instruction
+    let detail = Some(frame);
+    let (ours, theirs) = match reference
+        .trace(&opts, detail)
+        .and_then(|theirs| Ok((trace(&opts, detail)?, theirs)))
+    {
+        Ok(traces) => traces,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let (our_steps, their_steps) = (filter(&ours, "step "), filter(&theirs, "step "));
+
+    if their_steps.is_empty() {
+        println!("The reference has no instruction trace of frame {}", frame);
+        return 1;
+    }
+
+    match our_steps
+        .iter()
+        .zip(their_steps.iter())
+        .position(|(a, b)| a != b)
+    {
+        Some(i) => {
+            println!("First divergent instruction: #{} of frame {}", i + 1, frame);
+            if i > 0 {
+                println!("  previous:   {}", &our_steps[i - 1][5..]);
+            }
+            println!("  this build: {}", &our_steps[i][5..]);
+            println!("  reference:  {}", &their_steps[i][5..]);
+        }
+        None => println!(
+            "Instructions match ({} and {}); the difference is in the PPU output",
+            our_steps.len(),
+            their_steps.len()
+        ),
+    }
+
+    1
+}
@@ -1,5 +1,25 @@
-use mmu::MMU;
-
+use std::mem;
+
+use bank_stats::BankStats;
+use catridge::Catridge;
+use catridge_error::CatridgeError;
+use clock::CLOCKS_PER_SEC;
+use interrupt::Interrupt;
+use mmu::{self, MMU};
+use rng::Rng;
+use savestate::{Savestate, StateBuffer};
+use timeline::Event;
+use tracepoint::Tracepoint;
+
+/// Number of clocks in a frame (154 scanlines of 456 clocks).
+pub const CYCLES_PER_FRAME: u32 = 456 * 154;
+/// Maximum CPU clock multiplier.
+pub const MAX_OVERCLOCK: u8 = 8;
+/// Clocks within which the boot ROM must unmap itself. It takes a few
+/// seconds, but loops forever on a bad logo or header checksum.
+const BOOT_ROM_TIMEOUT: u64 = 10 * CLOCKS_PER_SEC;
+
+#[derive(Clone)]
 pub struct CPU {
     pub mmu: MMU,
     pc: u16,
@@ -15,13 +35,29 @@ pub struct CPU {
     ime: bool,
     tick: u8, // This is T-cycle (4.194304 MHz), not M-cycle
     halted: bool,
+    /// Clocks executed beyond the budget of the last `run_for_cycles` call
+    overshoot: u32,
+    /// CPU clock multiplier relative to the rest of the system
+    overclock: u8,
+    /// CPU clocks not yet passed on to the rest of the system
+    overclock_remainder: u8,
+    /// ROM bank usage statistics, if enabled
+    bank_stats: Option<BankStats>,
+    /// Addresses that log a message when executed
+    tracepoints: Vec<Tracepoint>,
+    /// Addresses and messages of hit tracepoints
+    trace_log: Vec<(u16, String)>,
+    /// Log when execution enters RAM
+    trace_ram_exec: bool,
+    /// Address of the last instruction executed
+    prev_pc: u16,
 }
 
 impl CPU {
     /// Creates a new `CPU`
-    pub fn new(rom_name: &str) -> Self {
+    pub fn new(catridge: Catridge) -> Self {
         CPU {
-            mmu: MMU::new(rom_name),
+            mmu: MMU::new(catridge),
             pc: 0x100,
             sp: 0,
             a: 0,
@@ -35,7 +71,38 @@ impl CPU {
             ime: false,
             tick: 0,
             halted: false,
+            overshoot: 0,
+            overclock: 1,
+            overclock_remainder: 0,
+            bank_stats: None,
+            tracepoints: Vec::new(),
+            trace_log: Vec::new(),
+            trace_ram_exec: false,
+            prev_pc: 0x100,
+        }
+    }
+
+    /// Loads a boot ROM and starts execution from its entry point.
+    pub fn load_boot_rom(&mut self, boot_rom: Vec<u8>) -> Result<(), CatridgeError> {
+        self.mmu.load_boot_rom(boot_rom)?;
+        self.pc = 0;
+
+        Ok(())
+    }
+
+    /// Runs the boot ROM until it unmaps itself, leaving the post-boot state.
+    /// Fails if the boot ROM locks up instead.
+    pub fn skip_boot_rom(&mut self) -> Result<(), CatridgeError> {
+        let mut clocks = 0;
+
+        while self.mmu.boot_rom_enabled() {
+            if clocks >= BOOT_ROM_TIMEOUT {
+                return Err(CatridgeError::BootRomLockup);
+            }
+            clocks += self.step() as u64;
         }
+
+        Ok(())
     }
 
     /// Reads AF register
@@ -1302,68 +1369,274 @@ impl CPU {
         }
     }
 
-    /// Execute a single instruction and handle IRQs.
+    /// Runs the CPU at a multiple of its normal clock while the PPU, timer
+    /// and other peripherals keep their timing. This reduces slowdown in some
+    /// games, but is not accurate and must stay at 1 where determinism
+    /// matters (e.g. input scripts and frame hashes).
+    pub fn set_overclock(&mut self, multiplier: u8) {
+        assert!(
+            (1..=MAX_OVERCLOCK).contains(&multiplier),
+            "Invalid overclock multiplier: {}",
+            multiplier
+        );
+
+        self.overclock = multiplier;
+        self.overclock_remainder = 0;
+    }
+
+    /// Restores a snapshot taken by cloning a `CPU`. The device connected to
+    /// the link port and the V-Blank listener stay connected.
+    pub fn restore(&mut self, snapshot: CPU) {
+        let link = self.mmu.serial.disconnect();
+        let vblank_listener = self.mmu.take_vblank_listener();
+        // Muting, volume and mono are listening settings, and hidden layers
+        // a viewing setting, not emulated state
+        let audio_settings = self.mmu.apu.settings().clone();
+        let layer_visibility = self.mmu.ppu.layer_visibility();
+
+        *self = snapshot;
+
+        if let Some(link) = link {
+            self.mmu.serial.connect(link);
+        }
+        self.mmu.set_vblank_listener(vblank_listener);
+        self.mmu.apu.set_settings(audio_settings);
+        self.mmu.ppu.set_layer_visibility(layer_visibility);
+    }
+
+    /// Same as `restore`, but keeps the current catridge RAM, so that loading
+    /// a state does not alter the battery save (e.g. when practicing).
+    pub fn restore_keeping_ram(&mut self, snapshot: CPU) {
+        let ram = self.mmu.catridge.as_ref().map(|c| c.ram().to_vec());
+
+        self.restore(snapshot);
+
+        if let (Some(ram), Some(catridge)) = (ram, self.mmu.catridge.as_mut()) {
+            catridge.load_ram(&ram);
+        }
+    }
+
+    /// Resets to a snapshot taken at power-on, like a power cycle. The
+    /// catridge RAM and RTC keep their contents.
+    pub fn reset(&mut self, power_on: &CPU) {
+        let mut catridge = self.mmu.catridge.clone();
+        if let Some(ref mut catridge) = catridge {
+            catridge.reset();
+        }
+
+        self.restore(power_on.clone());
+        self.mmu.catridge = catridge;
+    }
+
+    /// Seeds every source of nondeterminism from a single value: WRAM, HRAM
+    /// and catridge RAM are filled with a pseudo-random pattern and the RTC
+    /// ignores host time. Runs with the same seed and input are identical.
+    /// Must be called before loading a save file.
+    pub fn seed(&mut self, seed: u64) {
+        let mut rng = Rng::new(seed);
+
+        self.mmu.randomize_ram(&mut rng);
+        if let Some(ref mut catridge) = self.mmu.catridge {
+            catridge.randomize_ram(&mut rng);
+            catridge.set_emulated_rtc(true);
+        }
+    }
+
+    /// Adds a tracepoint.
+    pub fn add_tracepoint(&mut self, tracepoint: Tracepoint) {
+        self.tracepoints.push(tracepoint);
+    }
+
+    /// Returns the value of a register by name (e.g. `a` or `hl`).
+    fn reg_by_name(&self, name: &str) -> u16 {
+        match name {
+            "a" => self.a as u16,
+            "f" => self.f as u16,
+            "b" => self.b as u16,
+            "c" => self.c as u16,
+            "d" => self.d as u16,
+            "e" => self.e as u16,
+            "h" => self.h as u16,
+            "l" => self.l as u16,
+            "af" => self.af(),
+            "bc" => self.bc(),
+            "de" => self.de(),
+            "hl" => self.hl(),
+            "sp" => self.sp,
+            "pc" => self.pc,
+            _ => unreachable!("Unknown register: {}", name),
+        }
+    }
+
+    /// Logs the messages of tracepoints at the current PC.
+    fn check_tracepoints(&mut self) {
+        let pc = self.pc;
+        let msgs: Vec<String> = self
+            .tracepoints
+            .iter()
+            .filter(|tp| tp.addr == pc)
+            .map(|tp| tp.format(|name| self.reg_by_name(name), |addr| self.mmu.peek(addr)))
+            .collect();
+
+        for msg in msgs {
+            self.trace_log.push((pc, msg));
+        }
+    }
+
+    /// Logs a message to the trace log whenever execution jumps from ROM into
+    /// RAM (e.g. routines copied to HRAM for OAM DMA).
+    pub fn set_trace_ram_exec(&mut self, enable: bool) {
+        self.trace_ram_exec = enable;
+    }
+
+    /// Logs the entry into a RAM region if the previous instruction was in
+    /// ROM.
+    fn check_ram_exec(&mut self) {
+        let pc = self.pc;
+
+        if let Some(region) = mmu::ram_region(pc) {
+            if mmu::ram_region(self.prev_pc).is_none() {
+                let msg = format!("entered {} from 0x{:04x}", region, self.prev_pc);
+                self.trace_log.push((pc, msg));
+            }
+        }
+
+        self.prev_pc = pc;
+    }
+
+    /// Returns and clears the addresses and messages of hit tracepoints.
+    pub fn take_trace_log(&mut self) -> Vec<(u16, String)> {
+        mem::take(&mut self.trace_log)
+    }
+
+    /// Starts collecting ROM bank usage statistics.
+    pub fn enable_bank_stats(&mut self) {
+        self.bank_stats = Some(BankStats::new(
+            self.mmu.catridge.as_ref().map_or(2, |c| c.num_rom_banks()),
+        ));
+    }
+
+    /// Returns the ROM bank usage statistics, if enabled.
+    pub fn bank_stats(&self) -> Option<&BankStats> {
+        self.bank_stats.as_ref()
+    }
+
+    /// Passes CPU clocks on to the rest of the system, scaled down by the
+    /// overclock multiplier, and returns the number of system clocks.
+    fn update_system(&mut self, tick: u8) -> u8 {
+        let clocks = self.overclock_remainder + tick;
+        let system_tick = clocks / self.overclock;
+
+        self.overclock_remainder = clocks % self.overclock;
+        self.mmu.update(system_tick);
+
+        system_tick
+    }
+
+    /// Execute a single instruction and handle IRQs. Returns the elapsed
+    /// system clocks.
     pub fn step(&mut self) -> u8 {
         let mut total_tick = 0;
 
         self.tick = 0;
 
+        let pc = self.pc;
+        let bank = self.mmu.catridge.as_ref().map_or(1, |c| c.rom_bank_no());
+
         if self.halted {
             self.tick += 4;
         } else {
+            if !self.tracepoints.is_empty() {
+                self.check_tracepoints();
+            }
+            if self.trace_ram_exec {
+                self.check_ram_exec();
+            }
+
             self.fetch_and_exec();
+
+            self.mmu.telemetry.count_instruction(bank);
         }
 
-        total_tick += self.tick;
+        if let Some(ref mut stats) = self.bank_stats {
+            stats.record(pc, bank, self.tick);
+        }
 
-        self.mmu.update(self.tick);
+        total_tick += self.update_system(self.tick);
 
         if self.ime {
             self.tick = 0;
             self.check_irqs();
-            self.mmu.update(self.tick);
 
-            total_tick += self.tick;
+            total_tick += self.update_system(self.tick);
+        }
+
+        if self.mmu.io_trace.enabled() {
+            for access in self.mmu.io_trace.take() {
+                self.trace_log.push((pc, access.to_string()));
+            }
         }
 
         total_tick
     }
 
-    /// Checks IRQs and execute ISRs if requested.
-    fn check_irqs(&mut self) {
-        // Bit 0 has the highest priority
-        for i in 0..5 {
-            let irq = self.mmu.int_flag & (1 << i) > 0;
-            let ie = self.mmu.int_enable & (1 << i) > 0;
-
-            // If interrupt is requested and enabled
-            if irq && ie {
-                self.call_isr(i);
+    /// Runs for a given number of clocks. An instruction straddling the end of
+    /// the budget is completed, and the excess is deducted from the next call.
+    pub fn run_for_cycles(&mut self, cycles: u32) {
+        self.run_for_cycles_traced(cycles, |_| ());
+    }
+
+    /// Same as `run_for_cycles`, but calls `f` before every instruction.
+    pub fn run_for_cycles_traced<F: FnMut(&CPU)>(&mut self, cycles: u32, mut f: F) {
+        let mut elapsed = self.overshoot;
+
+        while elapsed < cycles {
+            f(self);
+            elapsed += self.step() as u32;
+        }
+
+        self.overshoot = elapsed - cycles;
+    }
+
+    /// Runs until the PPU enters V-Blank and returns the elapsed clocks. Gives
+    /// up after a frame's worth of clocks if the LCD is off.
+    pub fn run_until_vblank(&mut self) -> u32 {
+        let mut elapsed = 0;
+
+        while elapsed < CYCLES_PER_FRAME {
+            let in_vblank = self.mmu.ppu.in_vblank();
+
+            elapsed += self.step() as u32;
+
+            if !in_vblank && self.mmu.ppu.in_vblank() {
                 break;
             }
         }
+
+        elapsed
+    }
+
+    /// Checks IRQs and execute ISRs if requested.
+    fn check_irqs(&mut self) {
+        if let Some(int) = self.mmu.interrupts.pending() {
+            self.call_isr(int);
+        }
     }
 
     /// Calls requested interrupt service routine.
-    fn call_isr(&mut self, id: u8) {
+    fn call_isr(&mut self, int: Interrupt) {
         // Reset corresponding bit in IF
-        self.mmu.int_flag &= !(1 << id);
+        self.mmu.interrupts.acknowledge(int);
+        self.mmu.timeline.record(Event::Serviced(int));
         // Clear IME (disable any further interrupts)
         self.ime = false;
         self.halted = false;
 
-        let isr: u16 = match id {
-            0 => 0x40,
-            1 => 0x48,
-            2 => 0x50,
-            3 => 0x80,
-            4 => 0x70,
-            _ => panic!("Invalid IRQ id {}", id),
-        };
+        let isr = int.vector();
 
         self.tick += 8;
 
-        debug!("Calling ISR 0x{:02x}", isr);
+        debug!(target: "gbr::irq", "Calling ISR 0x{:02x} ({:?})", isr, int);
 
         self._call(isr);
     }
@@ -1525,6 +1798,25 @@ impl CPU {
         }
     }
 
+    /// Returns the address of the next instruction.
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// Returns the registers as a single line, for comparing execution traces.
+    pub fn state_line(&self) -> String {
+        format!(
+            "pc={:04x} sp={:04x} af={:04x} bc={:04x} de={:04x} hl={:04x} ime={}",
+            self.pc,
+            self.sp,
+            self.af(),
+            self.bc(),
+            self.de(),
+            self.hl(),
+            self.ime as u8
+        )
+    }
+
     /// Dumps current CPU state.
     #[allow(dead_code)]
     pub fn dump(&self) {
@@ -1535,3 +1827,89 @@ impl CPU {
         println!("T:  {}", self.tick);
     }
 }
+
+impl Savestate for CPU {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.u16(&mut self.pc);
+        buf.u16(&mut self.sp);
+        buf.u8(&mut self.a);
+        buf.u8(&mut self.f);
+        buf.u8(&mut self.b);
+        buf.u8(&mut self.c);
+        buf.u8(&mut self.d);
+        buf.u8(&mut self.e);
+        buf.u8(&mut self.h);
+        buf.u8(&mut self.l);
+        buf.bool(&mut self.ime);
+        buf.bool(&mut self.halted);
+        buf.u32(&mut self.overshoot);
+        buf.u8(&mut self.overclock_remainder);
+        buf.u16(&mut self.prev_pc);
+        self.mmu.savestate(buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cycles::{self, OPCODE_CLOCKS};
+
+    /// Returns a CPU running a ROM-only catridge.
+    fn cpu() -> CPU {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0134..0x0143].copy_from_slice(b"CLOCKS\0\0\0\0\0\0\0\0\0");
+
+        CPU::new(Catridge::from_bytes(rom).unwrap())
+    }
+
+    /// Executes an instruction from WRAM with the given flags and returns the
+    /// clocks it took. Operands point into WRAM and (HL) is in WRAM too.
+    fn clocks(opcode: u8, operand: u8, f: u8) -> u8 {
+        let mut cpu = cpu();
+
+        cpu.mmu.write(0xc000, opcode);
+        cpu.mmu.write(0xc001, operand);
+        cpu.mmu.write(0xc002, 0xd0);
+        cpu.pc = 0xc000;
+        cpu.sp = 0xdff0;
+        cpu.f = f;
+        cpu.set_hl(0xd000);
+        cpu.tick = 0;
+
+        cpu.fetch_and_exec();
+
+        cpu.tick
+    }
+
+    #[test]
+    fn opcode_clocks() {
+        for opcode in 0..=0xff {
+            // Illegal opcodes, STOP (not implemented) and the CB prefix
+            if OPCODE_CLOCKS[opcode as usize] == 0 || opcode == 0x10 || opcode == 0xcb {
+                continue;
+            }
+
+            let not_taken = OPCODE_CLOCKS[opcode as usize];
+            let taken = cycles::branch_clocks(opcode).unwrap_or(not_taken);
+            // Only one of NZ and Z (or NC and C) holds with each flag value
+            let mut actual = [clocks(opcode, 0, 0x00), clocks(opcode, 0, 0xf0)];
+            actual.sort();
+
+            let mut expected = [taken, not_taken];
+            expected.sort();
+            assert_eq!(actual, expected, "Opcode 0x{:02x}", opcode);
+        }
+    }
+
+    #[test]
+    fn cb_opcode_clocks() {
+        for opcode in 0..=0xff {
+            assert_eq!(
+                clocks(0xcb, opcode, 0),
+                cycles::cb_clocks(opcode),
+                "Opcode 0xcb 0x{:02x}",
+                opcode
+            );
+        }
+    }
+}
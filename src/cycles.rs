@@ -0,0 +1,50 @@
+/// Clocks taken by each opcode. Conditional jumps, calls and returns take
+/// these clocks when the condition is false (see `branch_clocks`). Illegal
+/// opcodes are 0 and 0xcb only counts the prefix (see `cb_clocks`).
+#[rustfmt::skip]
+pub const OPCODE_CLOCKS: [u8; 256] = [
+//  x0  x1  x2  x3  x4  x5  x6  x7  x8  x9  xa  xb  xc  xd  xe  xf
+     4, 12,  8,  8,  4,  4,  8,  4, 20,  8,  8,  8,  4,  4,  8,  4, // 0x
+     4, 12,  8,  8,  4,  4,  8,  4, 12,  8,  8,  8,  4,  4,  8,  4, // 1x
+     8, 12,  8,  8,  4,  4,  8,  4,  8,  8,  8,  8,  4,  4,  8,  4, // 2x
+     8, 12,  8,  8, 12, 12, 12,  4,  8,  8,  8,  8,  4,  4,  8,  4, // 3x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 4x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 5x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 6x
+     8,  8,  8,  8,  8,  8,  4,  8,  4,  4,  4,  4,  4,  4,  8,  4, // 7x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 8x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 9x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // ax
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // bx
+     8, 12, 12, 16, 12, 16,  8, 16,  8, 16, 12,  4, 12, 24,  8, 16, // cx
+     8, 12, 12,  0, 12, 16,  8, 16,  8, 16, 12,  0, 12,  0,  8, 16, // dx
+    12, 12,  8,  0,  0, 16,  8, 16, 16,  4, 16,  0,  0,  0,  8, 16, // ex
+    12, 12,  8,  4,  0, 16,  8, 16, 12,  8, 16,  4,  0,  0,  8, 16, // fx
+];
+
+/// Returns the clocks taken by a conditional jump, call or return when the
+/// condition is true, or `None` for other opcodes.
+pub fn branch_clocks(opcode: u8) -> Option<u8> {
+    match opcode {
+        // JR cc, r8
+        0x20 | 0x28 | 0x30 | 0x38 => Some(12),
+        // RET cc
+        0xc0 | 0xc8 | 0xd0 | 0xd8 => Some(20),
+        // JP cc, a16
+        0xc2 | 0xca | 0xd2 | 0xda => Some(16),
+        // CALL cc, a16
+        0xc4 | 0xcc | 0xd4 | 0xdc => Some(24),
+        _ => None,
+    }
+}
+
+/// Returns the clocks taken by a CB-prefixed opcode, including the prefix.
+pub fn cb_clocks(opcode: u8) -> u8 {
+    match (opcode >> 6, opcode & 7) {
+        // BIT b, (HL) only reads memory
+        (1, 6) => 12,
+        // Read-modify-write of (HL)
+        (_, 6) => 16,
+        _ => 8,
+    }
+}
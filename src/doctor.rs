@@ -0,0 +1,198 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Read;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use capabilities;
+use catridge::Catridge;
+use catridge_info::CatridgeInfo;
+use cpu::{CPU, CYCLES_PER_FRAME};
+use unlicensed;
+
+/// Frames run to look for crashes.
+const FRAMES: u64 = 600;
+/// Frames within which the first V-Blank must occur (games may keep the LCD
+/// off for a while after boot).
+const VBLANK_FRAMES: u64 = 60;
+
+/// Result of a check.
+enum Outcome {
+    Pass(String),
+    Warn(String),
+    Fail(String),
+}
+
+/// Runs `f`, turning a panic into an error carrying the panic message.
+fn catch<T, F: FnOnce() -> T>(f: F) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|e| {
+        e.downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| e.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| String::from("unknown error"))
+    })
+}
+
+/// Checks that the header parses and its checksum is valid.
+fn check_header(rom: &[u8], info: &CatridgeInfo) -> Outcome {
+    let summary = format!(
+        "\"{}\", {}KB ROM, {}KB RAM",
+        info.title,
+        info.rom_size / 1024,
+        info.ram_size / 1024
+    );
+
+    if !info.header_checksum_valid {
+        Outcome::Warn(format!(
+            "{}, header checksum is incorrect (the boot ROM would lock up)",
+            summary
+        ))
+    } else if info.rom_size != rom.len() {
+        Outcome::Warn(format!(
+            "{}, but the file is {}KB",
+            summary,
+            rom.len() / 1024
+        ))
+    } else if !info.global_checksum_valid {
+        Outcome::Warn(format!("{}, global checksum is incorrect", summary))
+    } else {
+        Outcome::Pass(summary)
+    }
+}
+
+/// Checks that the catridge type is supported.
+fn check_mapper(rom: &[u8], info: &CatridgeInfo) -> Outcome {
+    if let Some(mapper) = unlicensed::detect(rom, info) {
+        Outcome::Pass(format!("unlicensed {:?}", mapper))
+    } else if !capabilities::supports_mapper(info.mbc_type) {
+        Outcome::Fail(format!(
+            "{} (0x{:02x}) is not supported",
+            info.mapper, info.mbc_type
+        ))
+    } else if info.cgb_only {
+        Outcome::Warn(format!("{}, but the game is CGB only", info.mapper))
+    } else {
+        Outcome::Pass(info.mapper.to_string())
+    }
+}
+
+/// Checks that the game reaches its first V-Blank.
+fn check_boot(cpu: &mut CPU) -> Outcome {
+    let result = catch(|| {
+        (0..VBLANK_FRAMES).position(|_| {
+            cpu.run_until_vblank();
+            cpu.mmu.ppu.in_vblank()
+        })
+    });
+
+    match result {
+        Ok(Some(frame)) => Outcome::Pass(format!("V-Blank in frame {}", frame + 1)),
+        Ok(None) => Outcome::Fail(format!("LCD still off after {} frames", VBLANK_FRAMES)),
+        Err(e) => Outcome::Fail(e),
+    }
+}
+
+/// Checks that the game runs without executing illegal opcodes or otherwise
+/// crashing the emulator.
+fn check_run(cpu: &mut CPU) -> Outcome {
+    for frame in 0..FRAMES {
+        if let Err(e) = catch(|| cpu.run_for_cycles(CYCLES_PER_FRAME)) {
+            return Outcome::Fail(format!(
+                "{} in frame {}, pc=0x{:04x}",
+                e,
+                frame + 1,
+                cpu.pc()
+            ));
+        }
+    }
+
+    Outcome::Pass(format!("{} frames", FRAMES))
+}
+
+/// Checks that the save file can be written, without altering it.
+fn check_save(info: &CatridgeInfo, save_fname: &Path) -> Outcome {
+    if !info.has_battery {
+        return Outcome::Pass(String::from("no battery"));
+    }
+
+    let result = if save_fname.exists() {
+        OpenOptions::new().append(true).open(save_fname).map(|_| ())
+    } else {
+        File::create(save_fname).and_then(|_| fs::remove_file(save_fname))
+    };
+
+    match result {
+        Ok(()) => Outcome::Pass(save_fname.display().to_string()),
+        Err(e) => Outcome::Fail(format!("{}: {}", save_fname.display(), e)),
+    }
+}
+
+/// Runs quick checks on a ROM (`<rom>`) and prints a report, to triage games
+/// that do not work. Returns the exit code.
+pub fn main(args: &[String]) -> i32 {
+    let rom_fname = match args {
+        [fname] => fname,
+        _ => {
+            eprintln!("Usage: gbr doctor <rom>");
+            return 1;
+        }
+    };
+
+    let mut rom = Vec::new();
+    if let Err(e) = File::open(rom_fname).and_then(|mut f| f.read_to_end(&mut rom)) {
+        println!("read ... FAILED ({}: {})", rom_fname, e);
+        return 1;
+    }
+
+    // Panics are reported as failed checks
+    panic::set_hook(Box::new(|_| ()));
+
+    let info = match CatridgeInfo::from_rom(&rom) {
+        Ok(info) => info,
+        Err(e) => {
+            println!("header ... FAILED ({})", e);
+            return 1;
+        }
+    };
+
+    let mut checks = vec![("header", check_header(&rom, &info))];
+    if let Outcome::Pass(_) | Outcome::Warn(_) = checks[0].1 {
+        checks.push(("mapper", check_mapper(&rom, &info)));
+    }
+
+    let catridge = match checks.last() {
+        Some(&(_, Outcome::Fail(_))) => None,
+        _ => Catridge::from_bytes(rom.clone()).ok(),
+    };
+    if let Some(catridge) = catridge {
+        let mut cpu = CPU::new(catridge);
+
+        checks.push(("boot", check_boot(&mut cpu)));
+        if let Some(&(_, Outcome::Pass(_))) = checks.last() {
+            checks.push(("run", check_run(&mut cpu)));
+        }
+        checks.push((
+            "save",
+            check_save(&info, &Path::new(rom_fname).with_extension("sav")),
+        ));
+    }
+
+    let _ = panic::take_hook();
+
+    let mut failed = 0;
+    for (name, outcome) in checks {
+        match outcome {
+            Outcome::Pass(detail) => println!("{} ... ok ({})", name, detail),
+            Outcome::Warn(detail) => println!("{} ... warning ({})", name, detail),
+            Outcome::Fail(detail) => {
+                println!("{} ... FAILED ({})", name, detail);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        1
+    } else {
+        0
+    }
+}
@@ -0,0 +1,213 @@
+use accuracy::Accuracy;
+use catridge::{Catridge, DisabledRam, RamInit};
+use catridge_error::CatridgeError;
+use cpu::CPU;
+use storage::{MemoryStorage, StorageBackend};
+
+/// Configures the emulated system and creates it from a ROM image, so that
+/// the frontend, the headless runners and embedders set up the core the same
+/// way. Unset options default to those of a plain `gbr <rom>` run.
+///
+/// ```text
+/// let cpu = EmulatorBuilder::new(rom)
+///     .accuracy(Accuracy::Cycle)
+///     .seed(42)
+///     .save_file("game.sav")
+///     .build()?;
+/// ```
+pub struct EmulatorBuilder {
+    /// ROM image
+    rom: Vec<u8>,
+    /// Boot ROM image, and whether to run it before returning
+    boot_rom: Option<(Vec<u8>, bool)>,
+    /// Accuracy preset
+    accuracy: Accuracy,
+    /// Sprite limit overriding the preset
+    sprite_limit: Option<bool>,
+    /// Interrupt latency in clocks overriding the preset
+    irq_latency: Option<u16>,
+    /// Advance the RTC only by emulated clocks
+    emulated_rtc: bool,
+    /// Value read from disabled catridge RAM overriding the mapper default
+    disabled_ram: Option<DisabledRam>,
+    /// Grayscale pixels, width and height of the Game Boy Camera image
+    camera_image: Option<(Vec<u8>, usize, usize)>,
+    /// Seed for power-on RAM contents
+    seed: Option<u64>,
+    /// Number of players multiplexed on the joypad
+    players: usize,
+    /// Power-on catridge RAM contents overriding the seed
+    ram_init: Option<RamInit>,
+    /// Battery save to load
+    save_fname: Option<String>,
+    /// Where the battery save is loaded from
+    storage: Box<dyn StorageBackend>,
+}
+
+impl EmulatorBuilder {
+    /// Creates a new `EmulatorBuilder` for a ROM image.
+    pub fn new(rom: Vec<u8>) -> Self {
+        EmulatorBuilder {
+            rom,
+            boot_rom: None,
+            accuracy: Accuracy::Balanced,
+            sprite_limit: None,
+            irq_latency: None,
+            emulated_rtc: false,
+            disabled_ram: None,
+            camera_image: None,
+            seed: None,
+            players: 1,
+            ram_init: None,
+            save_fname: None,
+            storage: Box::new(MemoryStorage::new()),
+        }
+    }
+
+    /// Starts from a boot ROM instead of the post-boot state. With
+    /// `fast_boot`, the boot ROM has already run when the system is built.
+    pub fn boot_rom(mut self, boot_rom: Vec<u8>, fast_boot: bool) -> Self {
+        self.boot_rom = Some((boot_rom, fast_boot));
+        self
+    }
+
+    /// Sets the accuracy preset.
+    pub fn accuracy(mut self, accuracy: Accuracy) -> Self {
+        self.accuracy = accuracy;
+        self
+    }
+
+    /// Enforces or lifts the 10 sprites per scanline limit regardless of the
+    /// accuracy preset.
+    pub fn sprite_limit(mut self, enable: bool) -> Self {
+        self.sprite_limit = Some(enable);
+        self
+    }
+
+    /// Sets the interrupt latency in clocks regardless of the accuracy
+    /// preset.
+    pub fn irq_latency(mut self, clocks: u16) -> Self {
+        self.irq_latency = Some(clocks);
+        self
+    }
+
+    /// Makes the RTC advance only by emulated clocks (see
+    /// `Catridge::set_emulated_rtc`).
+    pub fn emulated_rtc(mut self, enable: bool) -> Self {
+        self.emulated_rtc = enable;
+        self
+    }
+
+    /// Sets the value read from catridge RAM while it is disabled.
+    pub fn disabled_ram(mut self, policy: DisabledRam) -> Self {
+        self.disabled_ram = Some(policy);
+        self
+    }
+
+    /// Sets the image seen by the Game Boy Camera from grayscale pixels.
+    pub fn camera_image(mut self, pixels: Vec<u8>, width: usize, height: usize) -> Self {
+        self.camera_image = Some((pixels, width, height));
+        self
+    }
+
+    /// Seeds power-on RAM contents (see `CPU::seed`).
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Connects the joypads of up to four players, multiplexed as the SGB
+    /// does for games that request it (see `Joypad::set_num_players`).
+    pub fn players(mut self, players: usize) -> Self {
+        self.players = players;
+        self
+    }
+
+    /// Fills catridge RAM with a pattern at power-on. A battery save, if
+    /// any, is loaded over it.
+    pub fn ram_init(mut self, pattern: RamInit) -> Self {
+        self.ram_init = Some(pattern);
+        self
+    }
+
+    /// Loads the battery save stored under a key, if there is one and the
+    /// catridge has one.
+    pub fn save_file(mut self, fname: &str) -> Self {
+        self.save_fname = Some(fname.to_string());
+        self
+    }
+
+    /// Sets where the battery save is loaded from. Nothing is stored by
+    /// default, so headless runs neither read nor leave saves behind.
+    pub fn storage(mut self, storage: Box<dyn StorageBackend>) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    /// Creates the catridge without loading the save.
+    fn catridge(&self) -> Result<Catridge, CatridgeError> {
+        let mut catridge = Catridge::from_bytes(self.rom.clone())?;
+
+        catridge.set_emulated_rtc(self.emulated_rtc);
+        if let Some(policy) = self.disabled_ram {
+            catridge.set_disabled_ram(policy);
+        }
+        if let Some((ref pixels, width, height)) = self.camera_image {
+            catridge.set_camera_image(pixels, width, height);
+        }
+
+        Ok(catridge)
+    }
+
+    /// Creates the catridge alone, e.g. to insert it into a running system.
+    pub fn build_catridge(&self) -> Result<Catridge, CatridgeError> {
+        let mut catridge = self.catridge()?;
+
+        if let Some(pattern) = self.ram_init {
+            catridge.init_ram(pattern);
+        }
+        if let Some(ref fname) = self.save_fname {
+            catridge.load_save(&*self.storage, fname);
+        }
+
+        Ok(catridge)
+    }
+
+    /// Creates the system, powered on.
+    pub fn build(mut self) -> Result<CPU, CatridgeError> {
+        let mut cpu = CPU::new(self.catridge()?);
+
+        if let Some((boot_rom, fast_boot)) = self.boot_rom.take() {
+            cpu.load_boot_rom(boot_rom)?;
+
+            if fast_boot {
+                cpu.skip_boot_rom()?;
+            }
+        }
+
+        if let Some(seed) = self.seed {
+            cpu.seed(seed);
+        }
+        // Applied after seeding, which randomizes RAM
+        if let Some(ref mut catridge) = cpu.mmu.catridge {
+            if let Some(pattern) = self.ram_init {
+                catridge.init_ram(pattern);
+            }
+            if let Some(ref fname) = self.save_fname {
+                catridge.load_save(&*self.storage, fname);
+            }
+        }
+
+        cpu.mmu.joypad.set_num_players(self.players);
+        cpu.mmu.ppu.set_sprite_limit(
+            self.sprite_limit
+                .unwrap_or_else(|| self.accuracy.sprite_limit()),
+        );
+        cpu.mmu.interrupts.set_latency(
+            self.irq_latency
+                .unwrap_or_else(|| self.accuracy.irq_latency()),
+        );
+
+        Ok(cpu)
+    }
+}
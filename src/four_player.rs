@@ -0,0 +1,195 @@
+use cpu::CPU;
+use joypad::MAX_PLAYERS;
+
+/// Clocks between bytes sent by the adapter.
+const CLOCKS_PER_BYTE: u32 = 4096;
+/// Header of ping packets.
+const PING_HEADER: u8 = 0xfe;
+/// Acknowledgement sent by Game Boys in response to pings.
+const PING_ACK: u8 = 0x88;
+/// Sent by player 1 to start the transmission phase.
+const START_TRANSMISSION: u8 = 0xaa;
+/// Sent by the adapter to acknowledge the start of transmission.
+const TRANSMISSION_ACK: u8 = 0xcc;
+/// Sent by player 1 to return to the ping phase.
+const RESTART: u8 = 0xff;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Phase {
+    /// Detecting connected Game Boys
+    Ping,
+    /// Acknowledging the start of transmission
+    Starting,
+    /// Exchanging packets
+    Transmission,
+}
+
+/// Four Player Adapter (DMG-07).
+///
+/// The adapter clocks every connected Game Boy. In the ping phase it sends
+/// 4-byte packets (`0xfe` followed by three status bytes holding the
+/// connected players in the upper nibble and the player number in the lower
+/// nibble) and players answer with `0x88 0x88 RATE SIZE`. Player 1 sends
+/// `0xaa` four times to start the transmission phase, during which every
+/// player sends SIZE bytes per round and receives the packets of all four
+/// players from the previous round. Four `0xff` bytes from player 1 restart
+/// the ping phase.
+///
+/// Transfer timing is simplified to a fixed byte rate (RATE is ignored).
+pub struct FourPlayerAdapter {
+    /// Current phase
+    phase: Phase,
+    /// Position of next byte within the current packet or round
+    pos: usize,
+    /// Bitmask of players that acknowledged pings
+    connected: u8,
+    /// Packet size negotiated by player 1
+    size: usize,
+    /// Packets being received in the current round
+    incoming: [Vec<u8>; MAX_PLAYERS],
+    /// Packets of all players from the previous round
+    outgoing: Vec<u8>,
+    /// Number of consecutive control bytes received from player 1
+    control_count: usize,
+    /// Elapsed clocks since the last byte
+    counter: u32,
+}
+
+impl FourPlayerAdapter {
+    /// Creates a new `FourPlayerAdapter`.
+    pub fn new() -> Self {
+        FourPlayerAdapter {
+            phase: Phase::Ping,
+            pos: 0,
+            connected: 0,
+            size: 4,
+            incoming: [Vec::new(), Vec::new(), Vec::new(), Vec::new()],
+            outgoing: Vec::new(),
+            control_count: 0,
+            counter: 0,
+        }
+    }
+
+    /// Returns the byte sent to a player at the current position.
+    fn byte_for(&self, player: usize) -> u8 {
+        match self.phase {
+            Phase::Ping if self.pos == 0 => PING_HEADER,
+            Phase::Ping => (self.connected << 4) | (player as u8 + 1),
+            Phase::Starting => TRANSMISSION_ACK,
+            Phase::Transmission => *self.outgoing.get(self.pos).unwrap_or(&0),
+        }
+    }
+
+    /// Handles a byte received from a player at the current position.
+    fn receive(&mut self, player: usize, byte: u8) {
+        match self.phase {
+            Phase::Ping => match self.pos {
+                0 | 1 if byte == PING_ACK => self.connected |= 1 << player,
+                3 if player == 0 && byte > 0 => self.size = byte as usize,
+                _ => (),
+            },
+            Phase::Transmission => {
+                if self.pos < self.size {
+                    self.incoming[player].push(byte);
+                }
+            }
+            Phase::Starting => (),
+        }
+
+        // Control bytes from player 1
+        if player == 0 {
+            let control = match self.phase {
+                Phase::Ping => START_TRANSMISSION,
+                _ => RESTART,
+            };
+
+            if byte == control {
+                self.control_count += 1;
+            } else {
+                self.control_count = 0;
+            }
+        }
+    }
+
+    /// Advances to the next byte, switching phases at packet boundaries.
+    fn advance(&mut self) {
+        self.pos += 1;
+
+        if self.phase == Phase::Transmission && self.control_count >= 4 {
+            self.phase = Phase::Ping;
+            self.pos = 0;
+            self.connected = 0;
+            self.control_count = 0;
+            return;
+        }
+
+        let len = match self.phase {
+            Phase::Ping | Phase::Starting => 4,
+            Phase::Transmission => self.size * MAX_PLAYERS,
+        };
+
+        if self.pos < len {
+            return;
+        }
+        self.pos = 0;
+
+        match self.phase {
+            Phase::Ping => {
+                if self.control_count >= 4 {
+                    self.phase = Phase::Starting;
+                }
+                self.control_count = 0;
+            }
+            Phase::Starting => {
+                self.phase = Phase::Transmission;
+                self.outgoing = vec![0; self.size * MAX_PLAYERS];
+            }
+            Phase::Transmission => {
+                // Forward the packets of this round to every player
+                for (i, packet) in self.incoming.iter_mut().enumerate() {
+                    packet.resize(self.size, 0);
+                    self.outgoing[i * self.size..(i + 1) * self.size].copy_from_slice(packet);
+                    packet.clear();
+                }
+            }
+        }
+    }
+
+    /// Clocks a byte to and from every Game Boy.
+    fn clock(&mut self, cpus: &mut [&mut CPU]) {
+        for (player, cpu) in cpus.iter_mut().enumerate() {
+            let out = self.byte_for(player);
+
+            // A Game Boy that is not ready to receive sends nothing
+            if let Some(byte) = cpu.mmu.serial.external_clock(out) {
+                self.receive(player, byte);
+            }
+        }
+
+        self.advance();
+    }
+
+    /// Runs up to four linked Game Boys in lockstep for a given number of
+    /// clocks, with the adapter clocking all of them.
+    pub fn run_for_cycles(&mut self, cpus: &mut [&mut CPU], cycles: u32) {
+        assert!(cpus.len() <= MAX_PLAYERS, "Too many players");
+
+        let mut remaining = cycles;
+
+        while remaining > 0 {
+            // Run every Game Boy up to the next byte boundary
+            let chunk = (CLOCKS_PER_BYTE - self.counter).min(remaining);
+            for cpu in cpus.iter_mut() {
+                cpu.run_for_cycles(chunk);
+            }
+
+            remaining -= chunk;
+            self.counter += chunk;
+
+            if self.counter >= CLOCKS_PER_BYTE {
+                self.counter = 0;
+                self.clock(cpus);
+            }
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use catridge_error::CatridgeError;
+use clock::CLOCKS_PER_SEC;
+use cpu::CYCLES_PER_FRAME;
+use emulator::EmulatorBuilder;
+use input_script::InputScript;
+use input_source::InputSource;
+use png;
+use profile::{Profile, SUBSYSTEMS};
+
+/// Prints usage of `gbr hash-frames`.
+fn usage() -> i32 {
+    eprintln!(
+        "Usage: gbr hash-frames <rom> [--frames <n>] [--every <n>] [--input <file>] [--seed <n>]"
+    );
+    eprintln!("                        [--dump-frames <dir>] [--metrics <file>]");
+    1
+}
+
+/// Runs a ROM headlessly and prints the hash of every `--every`th frame in
+/// the same format as the `expect` lines of test scripts. Returns the exit
+/// code. With `--dump-frames`, every frame is also saved as a numbered PNG
+/// file (e.g. `frame_000001.png`). With `--metrics`, performance metrics are
+/// written as JSON (see `metrics_json`).
+pub fn main(args: &[String]) -> i32 {
+    let mut rom_fname = None;
+    let mut frames: u64 = 300;
+    let mut every: u64 = 60;
+    let mut input = InputScript::empty();
+    let mut seed = None;
+    let mut dump_dir = None;
+    let mut metrics_fname = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) => frames = n,
+                None => return usage(),
+            },
+            "--every" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) if n > 0 => every = n,
+                _ => return usage(),
+            },
+            "--input" => match args.next() {
+                Some(fname) => match InputScript::new(fname) {
+                    Ok(script) => input = script,
+                    Err(e) => {
+                        eprintln!("Failed to load {}: {}", fname, e);
+                        return 1;
+                    }
+                },
+                None => return usage(),
+            },
+            "--seed" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) => seed = Some(n),
+                None => return usage(),
+            },
+            "--dump-frames" => match args.next() {
+                Some(dir) => dump_dir = Some(PathBuf::from(dir)),
+                None => return usage(),
+            },
+            "--metrics" => match args.next() {
+                Some(fname) => metrics_fname = Some(fname),
+                None => return usage(),
+            },
+            _ if arg.starts_with("--") => return usage(),
+            _ => rom_fname = Some(arg),
+        }
+    }
+
+    let rom_fname = match rom_fname {
+        Some(fname) => fname,
+        None => return usage(),
+    };
+
+    if let Some(ref dir) = dump_dir {
+        if let Err(e) = fs::create_dir_all(dir) {
+            eprintln!("Failed to create {}: {}", dir.display(), e);
+            return 1;
+        }
+    }
+
+    let cpu = fs::read(rom_fname)
+        .map_err(CatridgeError::Read)
+        .and_then(|rom| {
+            let mut builder = EmulatorBuilder::new(rom);
+            if let Some(seed) = seed {
+                builder = builder.seed(seed);
+            }
+            builder.build()
+        });
+    let mut cpu = match cpu {
+        Ok(cpu) => cpu,
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", rom_fname, e);
+            return 1;
+        }
+    };
+
+    let frame_budget = Duration::from_secs(1) * CYCLES_PER_FRAME / CLOCKS_PER_SEC as u32;
+    let mut elapsed = Duration::new(0, 0);
+    let mut frames_dropped = 0;
+
+    if metrics_fname.is_some() {
+        cpu.mmu.profile = Some(Profile::new());
+    }
+
+    for frame in 0..frames {
+        cpu.mmu.joypad.set_input(&input.poll(frame));
+
+        let start = Instant::now();
+        cpu.run_for_cycles(CYCLES_PER_FRAME);
+        let frame_time = start.elapsed();
+
+        elapsed += frame_time;
+        if frame_time > frame_budget {
+            frames_dropped += 1;
+        }
+
+        if let Some(ref dir) = dump_dir {
+            let fname = dir.join(format!("frame_{:06}.png", frame + 1));
+            let fname = fname.to_str().unwrap();
+
+            if let Err(e) = png::save_gray(fname, cpu.mmu.ppu.frame_buffer(), 160, 144) {
+                eprintln!("Failed to save {}: {}", fname, e);
+                return 1;
+            }
+        }
+
+        if (frame + 1) % every == 0 {
+            println!("expect {} {:016x}", frame + 1, cpu.mmu.ppu.frame_hash());
+        }
+    }
+
+    if let (Some(fname), Some(profile)) = (metrics_fname, &cpu.mmu.profile) {
+        let json = metrics_json(frames, elapsed, frames_dropped, profile);
+
+        if let Err(e) = fs::write(fname, json) {
+            eprintln!("Failed to save {}: {}", fname, e);
+            return 1;
+        }
+    }
+
+    0
+}
+
+/// Formats performance metrics of a run as JSON, e.g.
+///
+/// ```text
+/// {
+///   "frames": 300,
+///   "elapsed_secs": 0.412301,
+///   "fps": 727.625,
+///   "frames_dropped": 0,
+///   "subsystem_secs": {"cpu": 0.201344, "ppu": 0.120851, "apu": 0.061032, "other": 0.029074}
+/// }
+/// ```
+///
+/// `elapsed_secs` and `fps` count emulation only, excluding frame dumps.
+/// A frame is dropped when it took longer to emulate than to display on
+/// hardware, i.e. it could not have been shown in time.
+fn metrics_json(frames: u64, elapsed: Duration, frames_dropped: u64, profile: &Profile) -> String {
+    let fps = if elapsed > Duration::new(0, 0) {
+        frames as f64 / elapsed.as_secs_f64()
+    } else {
+        0.0
+    };
+    let subsystems: Vec<String> = SUBSYSTEMS
+        .iter()
+        .map(|&s| format!("\"{}\": {:.6}", s.name(), profile.time(s).as_secs_f64()))
+        .collect();
+
+    format!(
+        "{{\n  \"frames\": {},\n  \"elapsed_secs\": {:.6},\n  \"fps\": {:.3},\n  \
+         \"frames_dropped\": {},\n  \"subsystem_secs\": {{{}}}\n}}\n",
+        frames,
+        elapsed.as_secs_f64(),
+        fps,
+        frames_dropped,
+        subsystems.join(", ")
+    )
+}
@@ -0,0 +1,202 @@
+use mbc::{Mbc, Memory};
+use rtc::Rtc;
+use savestate::{Savestate, StateBuffer};
+
+/// Nibbles of the RTC memory holding the time: minutes of the day (0-2) and
+/// the day counter (3-5), least significant nibble first.
+const TIME_NIBBLES: usize = 6;
+
+/// HuC3, with an RTC mapped to 0xa000-0xbfff in modes other than RAM
+/// access. The RTC is driven by commands written in mode 0x0b, executed
+/// against a 256-nibble memory whose first nibbles are exchanged with the
+/// clock.
+#[derive(Clone)]
+pub struct Huc3 {
+    /// ROM bank number
+    rom_bank_no: u8,
+    /// RAM bank number
+    ram_bank_no: u8,
+    /// Value last written to 0x0000-0x1fff
+    mode: u8,
+    /// Address in the RTC memory accessed by the next command
+    addr: u8,
+    /// RTC memory, one nibble per byte
+    mem: [u8; 256],
+    /// Last command with its result in the lower nibble
+    result: u8,
+    /// Real Time Clock, keeping minutes and days
+    rtc: Rtc,
+}
+
+impl Huc3 {
+    /// Creates a new `Huc3` with RAM access disabled.
+    pub fn new() -> Self {
+        Huc3 {
+            rom_bank_no: 0,
+            ram_bank_no: 0,
+            mode: 0x00,
+            addr: 0,
+            mem: [0; 256],
+            result: 0x01,
+            rtc: Rtc::new(),
+        }
+    }
+
+    /// Returns true if RAM is mapped to 0xa000-0xbfff for reading.
+    fn ram_readable(&self) -> bool {
+        self.mode == 0x00 || self.mode == 0x0a
+    }
+
+    /// Returns true if RAM is mapped to 0xa000-0xbfff for writing.
+    fn ram_writable(&self) -> bool {
+        self.mode == 0x0a
+    }
+
+    /// Handles a write to 0xa000-0xbfff outside of RAM access.
+    fn write_regs(&mut self, val: u8) {
+        match self.mode {
+            // RTC command
+            0x0b => self.execute(val),
+            // Semaphore: commands run immediately, so there is nothing to wait for
+            0x0d => (),
+            // Infrared LED
+            0x0e => (),
+            _ => debug!(target: "gbr::mbc", "HuC3 write in mode 0x{:02x}", self.mode),
+        }
+    }
+
+    /// Handles a read from 0xa000-0xbfff outside of RAM access.
+    fn read_regs(&self) -> u8 {
+        match self.mode {
+            // RTC command result
+            0x0c => self.result,
+            // Semaphore, always ready
+            0x0d => 0x01,
+            // Infrared receiver, no light
+            0x0e => 0xc0,
+            _ => 0x01,
+        }
+    }
+
+    /// Executes an RTC command made of a 3-bit opcode and a 4-bit argument.
+    fn execute(&mut self, command: u8) {
+        let arg = command & 0x0f;
+
+        match (command >> 4) & 0x07 {
+            // Read a nibble and advance
+            0x1 => {
+                self.result = (command & 0xf0) | self.mem[self.addr as usize];
+                self.addr = self.addr.wrapping_add(1);
+            }
+            // Write a nibble
+            0x2 => self.mem[self.addr as usize] = arg,
+            // Write a nibble and advance
+            0x3 => {
+                self.mem[self.addr as usize] = arg;
+                self.addr = self.addr.wrapping_add(1);
+            }
+            // Set the lower and upper nibble of the address
+            0x4 => self.addr = (self.addr & 0xf0) | arg,
+            0x5 => self.addr = (self.addr & 0x0f) | arg << 4,
+            0x6 => match arg {
+                // Copy the clock to the memory
+                0x0 => self.load_time(),
+                // Set the clock from the memory
+                0x1 => self.store_time(),
+                // Status, reads back as ready
+                0x2 => self.result = 0x01,
+                _ => debug!(target: "gbr::mbc", "Unknown HuC3 command 0x{:02x}", command),
+            },
+            _ => debug!(target: "gbr::mbc", "Unknown HuC3 command 0x{:02x}", command),
+        }
+    }
+
+    /// Copies the minutes and days of the clock to the memory.
+    fn load_time(&mut self) {
+        let (minutes, days) = self.rtc.minutes_and_days();
+        let time = u32::from(days) << 12 | u32::from(minutes);
+
+        for i in 0..TIME_NIBBLES {
+            self.mem[i] = (time >> (i * 4)) as u8 & 0x0f;
+        }
+    }
+
+    /// Sets the minutes and days of the clock from the memory.
+    fn store_time(&mut self) {
+        let time = (0..TIME_NIBBLES).fold(0u32, |time, i| time | u32::from(self.mem[i]) << (i * 4));
+
+        self.rtc
+            .set_minutes_and_days((time & 0xfff) as u16, (time >> 12) as u16);
+    }
+}
+
+impl Mbc for Huc3 {
+    fn write(&mut self, mem: &mut Memory, addr: u16, val: u8) {
+        match addr {
+            // RAM access or RTC mode select
+            0x0000..=0x1fff => self.mode = val & 0x0f,
+            // ROM bank number
+            0x2000..=0x3fff => self.rom_bank_no = val & 0x7f,
+            // RAM bank number
+            0x4000..=0x5fff => self.ram_bank_no = val & 0x03,
+            0x6000..=0x7fff => (),
+            // RAM bank 00-03 or RTC registers
+            0xa000..=0xbfff if self.ram_writable() => {
+                mem.write_ram(self.ram_bank_no as usize, addr, val)
+            }
+            0xa000..=0xbfff => self.write_regs(val),
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn read(&self, mem: &Memory, addr: u16) -> Option<u8> {
+        match addr {
+            // ROM bank 00
+            0x0000..=0x3fff => Some(mem.read_rom(0, addr)),
+            // ROM bank 00-7f
+            0x4000..=0x7fff => Some(mem.read_rom(self.rom_bank_no(), addr)),
+            // RAM bank 00-03 or RTC registers
+            0xa000..=0xbfff if self.ram_readable() => {
+                Some(mem.read_ram(self.ram_bank_no as usize, addr))
+            }
+            0xa000..=0xbfff => Some(self.read_regs()),
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn rom_bank_no(&self) -> usize {
+        self.rom_bank_no as usize
+    }
+
+    /// Resets the registers. The clock is kept.
+    fn reset(&mut self) {
+        let rtc = self.rtc.clone();
+
+        *self = Huc3::new();
+        self.rtc = rtc;
+    }
+
+    fn update(&mut self, _mem: &mut Memory, tick: u8) {
+        self.rtc.update(tick);
+    }
+
+    fn rtc(&mut self) -> Option<&mut Rtc> {
+        Some(&mut self.rtc)
+    }
+
+    fn box_clone(&self) -> Box<dyn Mbc> {
+        Box::new(self.clone())
+    }
+}
+
+impl Savestate for Huc3 {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.u8(&mut self.rom_bank_no);
+        buf.u8(&mut self.ram_bank_no);
+        buf.u8(&mut self.mode);
+        buf.u8(&mut self.addr);
+        buf.bytes(&mut self.mem);
+        buf.u8(&mut self.result);
+        self.rtc.savestate(buf);
+    }
+}
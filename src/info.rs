@@ -0,0 +1,96 @@
+use std::fs;
+
+use catridge_info::CatridgeInfo;
+use licensee;
+use play_stats::{self, PlayStats};
+
+/// Returns "ok" or "incorrect" for a checksum.
+fn checksum_status(valid: bool) -> &'static str {
+    if valid {
+        "ok"
+    } else {
+        "incorrect"
+    }
+}
+
+/// Prints the decoded header of a ROM and its play statistics as `key: value`
+/// lines without running it. Returns the exit code.
+pub fn main(args: &[String]) -> i32 {
+    let rom_fname = match args {
+        [fname] => fname,
+        _ => {
+            eprintln!("Usage: gbr info <rom>");
+            return 1;
+        }
+    };
+
+    let rom = match fs::read(rom_fname) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", rom_fname, e);
+            return 1;
+        }
+    };
+    let info = match CatridgeInfo::from_rom(&rom) {
+        Ok(info) => info,
+        Err(e) => {
+            eprintln!("Failed to parse the header of {}: {}", rom_fname, e);
+            return 1;
+        }
+    };
+    let header = CatridgeInfo::header(&rom);
+    let (licensee_code, licensee) = licensee::decode(header);
+
+    println!("title: {}", info.title);
+    println!("mapper: 0x{:02x} {}", info.mbc_type, info.mapper);
+    println!(
+        "rom-size: {}KB ({} banks)",
+        info.rom_size / 1024,
+        info.rom_size / 0x4000
+    );
+    println!("ram-size: {}KB", info.ram_size / 1024);
+    println!(
+        "cgb: {}",
+        match (info.cgb, info.cgb_only) {
+            (_, true) => "required",
+            (true, false) => "supported",
+            (false, false) => "no",
+        }
+    );
+    println!("sgb: {}", if info.sgb { "supported" } else { "no" });
+    println!("licensee: {} {}", licensee_code, licensee);
+    println!(
+        "destination: {}",
+        if header[0x014a] == 0x00 {
+            "Japan"
+        } else {
+            "overseas"
+        }
+    );
+    println!("version: {}", header[0x014c]);
+    println!(
+        "header-checksum: 0x{:02x} {}",
+        header[0x014d],
+        checksum_status(info.header_checksum_valid)
+    );
+    println!(
+        "global-checksum: 0x{:02x}{:02x} {}",
+        header[0x014e],
+        header[0x014f],
+        checksum_status(info.global_checksum_valid)
+    );
+
+    match PlayStats::load().get(&play_stats::rom_key(&rom)) {
+        Some(entry) => {
+            println!("launches: {}", entry.launches);
+            println!("playtime: {}", play_stats::format_playtime(entry.playtime));
+            println!(
+                "last-played: {}",
+                play_stats::format_last_played(entry.last_played)
+            );
+        }
+        None => println!("launches: 0"),
+    }
+
+    0
+}
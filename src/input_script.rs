@@ -0,0 +1,97 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use input_source::{InputSource, InputState};
+use joypad::Key;
+
+/// Per-frame joypad input read from a text file.
+///
+/// Each line has a frame number followed by the keys held from that frame on,
+/// separated by `+` (e.g. `120 start` or `300 a+right`). `-` releases all
+/// keys. Empty lines and lines starting with `#` are ignored.
+pub struct InputScript {
+    /// Frame numbers and the keys held from them, in order
+    events: Vec<(u64, Vec<Key>)>,
+    /// Index of next event
+    next: usize,
+    /// Keys held by the last event applied
+    held: InputState,
+}
+
+impl InputScript {
+    /// Reads an input script from a file.
+    pub fn new(fname: &str) -> Result<Self, String> {
+        let file = File::open(fname).map_err(|e| e.to_string())?;
+        let mut script = InputScript::empty();
+
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            Self::parse_line(line)
+                .and_then(|(frame, keys)| script.push(frame, keys))
+                .map_err(|e| format!("{} at line {}", e, i + 1))?;
+        }
+
+        info!("Input script loaded from: {}", fname);
+
+        Ok(script)
+    }
+
+    /// Creates an empty input script.
+    pub fn empty() -> Self {
+        InputScript {
+            events: Vec::new(),
+            next: 0,
+            held: InputState::default(),
+        }
+    }
+
+    /// Parses a line consisting of a frame number and keys.
+    pub fn parse_line(line: &str) -> Result<(u64, Vec<Key>), String> {
+        let mut fields = line.split_whitespace();
+        let frame = fields
+            .next()
+            .and_then(|f| f.parse().ok())
+            .ok_or_else(|| String::from("Invalid frame number"))?;
+        let keys = match fields.next() {
+            Some("-") | None => Vec::new(),
+            Some(keys) => keys
+                .split('+')
+                .map(|k| Key::from_name(k).ok_or_else(|| format!("Invalid key {}", k)))
+                .collect::<Result<_, _>>()?,
+        };
+
+        Ok((frame, keys))
+    }
+
+    /// Appends an event holding `keys` from `frame` on.
+    pub fn push(&mut self, frame: u64, keys: Vec<Key>) -> Result<(), String> {
+        match self.events.last() {
+            Some(&(prev, _)) if prev > frame => Err(String::from("Frame numbers not in order")),
+            _ => {
+                self.events.push((frame, keys));
+                Ok(())
+            }
+        }
+    }
+}
+
+impl InputSource for InputScript {
+    fn poll(&mut self, frame: u64) -> InputState {
+        while self.next < self.events.len() && self.events[self.next].0 <= frame {
+            self.held = InputState::default();
+            for &key in self.events[self.next].1.iter() {
+                self.held.press(0, key);
+            }
+
+            self.next += 1;
+        }
+
+        self.held
+    }
+}
@@ -0,0 +1,43 @@
+use joypad::{Key, MAX_PLAYERS};
+
+/// Keys held by each player in a frame.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct InputState {
+    /// Held keys of each player as a bitmask of `Key::mask`
+    pub held: [u8; MAX_PLAYERS],
+}
+
+impl InputState {
+    /// Returns true if a key of a player is held.
+    #[allow(dead_code)]
+    pub fn is_held(&self, player: usize, key: Key) -> bool {
+        self.held[player] & key.mask() > 0
+    }
+
+    /// Holds a key of a player.
+    pub fn press(&mut self, player: usize, key: Key) {
+        self.held[player] |= key.mask();
+    }
+
+    /// Releases a key of a player.
+    pub fn release(&mut self, player: usize, key: Key) {
+        self.held[player] &= !key.mask();
+    }
+
+    /// Returns the keys held in either state.
+    pub fn merge(&self, other: &InputState) -> InputState {
+        let mut merged = *self;
+        for (held, &other) in merged.held.iter_mut().zip(other.held.iter()) {
+            *held |= other;
+        }
+        merged
+    }
+}
+
+/// Source of joypad input (keyboard, input script, ...), polled once per
+/// frame. States of several sources are combined with `InputState::merge`
+/// and fed to `Joypad::set_input`, so sources never touch the joypad.
+pub trait InputSource {
+    /// Returns the keys held during a frame.
+    fn poll(&mut self, frame: u64) -> InputState;
+}
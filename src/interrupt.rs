@@ -0,0 +1,205 @@
+use io_device::IODevice;
+use savestate::{Savestate, StateBuffer};
+
+/// Interrupt sources, in priority order.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Interrupt {
+    VBlank,
+    LCDStat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+impl Interrupt {
+    /// All interrupt sources, highest priority first.
+    pub const ALL: [Interrupt; 5] = [
+        Interrupt::VBlank,
+        Interrupt::LCDStat,
+        Interrupt::Timer,
+        Interrupt::Serial,
+        Interrupt::Joypad,
+    ];
+
+    /// Returns the bit of this interrupt in IE and IF.
+    fn mask(self) -> u8 {
+        1 << self as u8
+    }
+
+    /// Returns the address of the interrupt service routine.
+    pub fn vector(self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x40,
+            Interrupt::LCDStat => 0x48,
+            Interrupt::Timer => 0x50,
+            Interrupt::Serial => 0x58,
+            Interrupt::Joypad => 0x60,
+        }
+    }
+}
+
+/// Interrupt controller holding IE and IF.
+#[derive(Clone)]
+pub struct InterruptController {
+    /// Interrupt flag
+    int_flag: u8,
+    /// Interrupt enable
+    int_enable: u8,
+    /// Clocks between a device requesting an interrupt and the CPU seeing it
+    latency: u16,
+    /// Remaining clocks until each requested interrupt can be serviced
+    delay: [u16; 5],
+}
+
+impl InterruptController {
+    /// Creates a new `InterruptController`.
+    pub fn new() -> Self {
+        InterruptController {
+            int_flag: 0,
+            int_enable: 0,
+            latency: 0,
+            delay: [0; 5],
+        }
+    }
+
+    /// Delays servicing interrupts requested by devices by a number of
+    /// clocks. Interrupts requested by writing IF are not delayed.
+    pub fn set_latency(&mut self, clocks: u16) {
+        self.latency = clocks;
+    }
+
+    /// Requests an interrupt.
+    pub fn request(&mut self, int: Interrupt) {
+        if self.int_flag & int.mask() == 0 {
+            self.delay[int as usize] = self.latency;
+        }
+
+        self.int_flag |= int.mask();
+    }
+
+    /// Returns the highest priority interrupt that is requested and enabled,
+    /// if any.
+    pub fn pending(&self) -> Option<Interrupt> {
+        Interrupt::ALL.iter().cloned().find(|&int| {
+            self.int_flag & self.int_enable & int.mask() > 0 && self.delay[int as usize] == 0
+        })
+    }
+
+    /// Clears the request of an interrupt being serviced.
+    pub fn acknowledge(&mut self, int: Interrupt) {
+        self.int_flag &= !int.mask();
+    }
+}
+
+impl IODevice for InterruptController {
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            // IF
+            0xff0f => {
+                self.int_flag = val & 0x1f;
+                self.delay = [0; 5];
+            }
+            // IE
+            0xffff => self.int_enable = val,
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            // IF (upper 3 bits always read as 1)
+            0xff0f => self.int_flag | 0xe0,
+            // IE
+            0xffff => self.int_enable,
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn update(&mut self, tick: u8) {
+        for delay in self.delay.iter_mut() {
+            *delay = delay.saturating_sub(tick as u16);
+        }
+    }
+}
+
+impl Savestate for InterruptController {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.u8(&mut self.int_flag);
+        buf.u8(&mut self.int_enable);
+        for delay in self.delay.iter_mut() {
+            buf.u16(delay);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vectors() {
+        let vectors: Vec<u16> = Interrupt::ALL.iter().map(|int| int.vector()).collect();
+
+        assert_eq!(vectors, vec![0x40, 0x48, 0x50, 0x58, 0x60]);
+    }
+
+    #[test]
+    fn priority() {
+        let mut ic = InterruptController::new();
+        ic.write(0xffff, 0x1f);
+
+        for &int in Interrupt::ALL.iter().rev() {
+            ic.request(int);
+            assert_eq!(ic.pending(), Some(int));
+        }
+
+        for (i, &int) in Interrupt::ALL.iter().enumerate() {
+            assert_eq!(ic.pending(), Some(int));
+            ic.acknowledge(int);
+            assert_eq!(ic.pending(), Interrupt::ALL.get(i + 1).cloned());
+        }
+    }
+
+    #[test]
+    fn masking() {
+        let mut ic = InterruptController::new();
+
+        // Requested but not enabled
+        ic.request(Interrupt::Timer);
+        assert_eq!(ic.pending(), None);
+        assert_eq!(ic.read(0xff0f), 0xe4);
+
+        // Enabled but not requested
+        ic.write(0xffff, 0x01);
+        assert_eq!(ic.pending(), None);
+
+        ic.write(0xffff, 0x05);
+        assert_eq!(ic.pending(), Some(Interrupt::Timer));
+
+        // Requested by writing IF
+        ic.write(0xff0f, 0x01);
+        assert_eq!(ic.pending(), Some(Interrupt::VBlank));
+        assert_eq!(ic.read(0xff0f), 0xe1);
+    }
+
+    #[test]
+    fn latency() {
+        let mut ic = InterruptController::new();
+        ic.write(0xffff, 0x1f);
+        ic.set_latency(8);
+
+        ic.request(Interrupt::Serial);
+        assert_eq!(ic.pending(), None);
+        ic.update(4);
+        assert_eq!(ic.pending(), None);
+        // Requesting again does not restart the delay
+        ic.request(Interrupt::Serial);
+        ic.update(4);
+        assert_eq!(ic.pending(), Some(Interrupt::Serial));
+
+        // Writing IF is not delayed
+        ic.request(Interrupt::Joypad);
+        ic.write(0xff0f, 0x10);
+        assert_eq!(ic.pending(), Some(Interrupt::Joypad));
+    }
+}
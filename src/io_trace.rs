@@ -0,0 +1,92 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::mem;
+
+/// A read or write of a traced I/O register.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct IoAccess {
+    /// Register address
+    pub addr: u16,
+    /// Value read or written
+    pub val: u8,
+    /// The access is a write
+    pub write: bool,
+    /// System clocks elapsed since power-on at the start of the instruction
+    pub clock: u64,
+}
+
+impl fmt::Display for IoAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {:04x}={:02x} at clock {}",
+            if self.write { "write" } else { "read" },
+            self.addr,
+            self.val,
+            self.clock
+        )
+    }
+}
+
+/// Records accesses to a set of I/O registers, a lighter alternative to
+/// tracing every instruction when debugging the PPU or DMA.
+#[derive(Clone)]
+pub struct IoTrace {
+    /// Traced register addresses
+    regs: Vec<u16>,
+    /// Accesses since the last call to `take`. Reads are recorded through a
+    /// shared reference.
+    log: RefCell<Vec<IoAccess>>,
+}
+
+impl IoTrace {
+    /// Creates a new `IoTrace` tracing no registers.
+    pub fn new() -> Self {
+        IoTrace {
+            regs: Vec::new(),
+            log: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Parses a comma separated list of register addresses in hex (e.g.
+    /// `ff40,ff41,ff46`).
+    pub fn parse(spec: &str) -> Result<Vec<u16>, String> {
+        spec.split(',')
+            .map(|reg| {
+                let reg = reg.trim().trim_start_matches("0x");
+                match u16::from_str_radix(reg, 16) {
+                    Ok(addr @ 0xff00..=0xff7f) | Ok(addr @ 0xffff) => Ok(addr),
+                    Ok(addr) => Err(format!("0x{:04x} is not an I/O register", addr)),
+                    Err(_) => Err(format!("Invalid register {}", reg)),
+                }
+            })
+            .collect()
+    }
+
+    /// Sets the traced registers.
+    pub fn set_regs(&mut self, regs: Vec<u16>) {
+        self.regs = regs;
+    }
+
+    /// Returns true if any register is traced.
+    pub fn enabled(&self) -> bool {
+        !self.regs.is_empty()
+    }
+
+    /// Records an access if the register is traced.
+    pub fn record(&self, addr: u16, val: u8, write: bool, clock: u64) {
+        if self.enabled() && self.regs.contains(&addr) {
+            self.log.borrow_mut().push(IoAccess {
+                addr,
+                val,
+                write,
+                clock,
+            });
+        }
+    }
+
+    /// Returns and clears the recorded accesses.
+    pub fn take(&mut self) -> Vec<IoAccess> {
+        mem::take(self.log.get_mut())
+    }
+}
@@ -1,16 +1,35 @@
+use input_source::InputState;
 use io_device::IODevice;
+use savestate::{Savestate, StateBuffer};
+
+/// Maximum number of players (SGB multiplayer or four player adapter).
+pub const MAX_PLAYERS: usize = 4;
+/// SGB command requesting joypad multiplexing.
+const MLT_REQ: u8 = 0x11;
 
 /// Joypad
+#[derive(Clone)]
 pub struct Joypad {
     /// Joypad
     joyp: u8,
-    /// Keypress state
-    key_state: u8,
+    /// Keypress state of each player
+    key_state: [u8; MAX_PLAYERS],
+    /// Number of players connected (1, 2 or 4). The SGB is only emulated
+    /// with more than one, for multiplayer games.
+    num_players: usize,
+    /// Number of players multiplexed as requested by MLT_REQ (1, 2 or 4)
+    multiplexed: usize,
+    /// Player whose keys are currently read
+    player: usize,
+    /// SGB command packet being received
+    packet: [u8; 16],
+    /// Bits of the packet received so far, if receiving
+    packet_bits: Option<usize>,
     /// Interrupt request
     pub irq: bool,
 }
 
-#[derive(Hash, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
 pub enum Key {
     Down,
     Up,
@@ -22,49 +41,144 @@ pub enum Key {
     A,
 }
 
+impl Key {
+    /// Returns the bit of a key in the keypress state.
+    pub fn mask(self) -> u8 {
+        match self {
+            Key::Down => 0x80,
+            Key::Up => 0x40,
+            Key::Left => 0x20,
+            Key::Right => 0x10,
+            Key::Start => 0x08,
+            Key::Select => 0x04,
+            Key::B => 0x02,
+            Key::A => 0x01,
+        }
+    }
+
+    /// Returns a key by its (case insensitive) name.
+    pub fn from_name(name: &str) -> Option<Key> {
+        match name.to_ascii_lowercase().as_str() {
+            "down" => Some(Key::Down),
+            "up" => Some(Key::Up),
+            "left" => Some(Key::Left),
+            "right" => Some(Key::Right),
+            "start" => Some(Key::Start),
+            "select" => Some(Key::Select),
+            "b" => Some(Key::B),
+            "a" => Some(Key::A),
+            _ => None,
+        }
+    }
+}
+
 impl Joypad {
     /// Creates a new `Joypad`.
     pub fn new() -> Self {
         Joypad {
             joyp: 0xff,
-            key_state: 0xff,
+            key_state: [0xff; MAX_PLAYERS],
+            num_players: 1,
+            multiplexed: 1,
+            player: 0,
+            packet: [0; 16],
+            packet_bits: None,
             irq: false,
         }
     }
 
-    pub fn keydown(&mut self, key: Key) {
-        match key {
-            Key::Down => self.key_state &= !0x80,
-            Key::Up => self.key_state &= !0x40,
-            Key::Left => self.key_state &= !0x20,
-            Key::Right => self.key_state &= !0x10,
-            Key::Start => self.key_state &= !0x08,
-            Key::Select => self.key_state &= !0x04,
-            Key::B => self.key_state &= !0x02,
-            Key::A => self.key_state &= !0x01,
+    /// Sets the number of players connected. With more than one, the joypad
+    /// answers SGB MLT_REQ commands and multiplexes the players on the joypad
+    /// register once a game requests it, as the SGB does. Games that never
+    /// request it read player 1 only.
+    pub fn set_num_players(&mut self, num_players: usize) {
+        assert!(
+            (1..=MAX_PLAYERS).contains(&num_players),
+            "Invalid number of players: {}",
+            num_players
+        );
+
+        self.num_players = num_players;
+        self.multiplexed = 1;
+        self.player = 0;
+    }
+
+    /// Receives a bit of an SGB command packet sent through the joypad
+    /// register: a reset pulse (P14 and P15 low) starts a packet, then every
+    /// pulse of P14 sends a 0 and every pulse of P15 a 1, LSB first.
+    fn receive_packet_bit(&mut self, prev: u8, val: u8) {
+        if prev != 0x30 || val == 0x30 {
+            return;
         }
 
-        self.irq = true;
+        let bits = match (val, self.packet_bits) {
+            (0x00, _) => {
+                self.packet = [0; 16];
+                self.packet_bits = Some(0);
+                return;
+            }
+            (_, Some(bits)) => bits,
+            (_, None) => return,
+        };
+
+        if val == 0x10 {
+            self.packet[bits / 8] |= 1 << (bits % 8);
+        }
+
+        if bits + 1 < self.packet.len() * 8 {
+            self.packet_bits = Some(bits + 1);
+            return;
+        }
+        self.packet_bits = None;
+
+        // The first byte is the command and the number of packets
+        if self.packet[0] >> 3 == MLT_REQ {
+            self.multiplexed = match self.packet[1] & 0x03 {
+                0 => 1,
+                1 => 2,
+                _ => 4,
+            };
+            self.player = 0;
+            debug!("MLT_REQ: {} players", self.multiplexed);
+        }
     }
 
-    pub fn keyup(&mut self, key: Key) {
-        match key {
-            Key::Down => self.key_state |= 0x80,
-            Key::Up => self.key_state |= 0x40,
-            Key::Left => self.key_state |= 0x20,
-            Key::Right => self.key_state |= 0x10,
-            Key::Start => self.key_state |= 0x08,
-            Key::Select => self.key_state |= 0x04,
-            Key::B => self.key_state |= 0x02,
-            Key::A => self.key_state |= 0x01,
+    /// Sets the keys held by every player. Newly pressed keys request an
+    /// interrupt.
+    pub fn set_input(&mut self, input: &InputState) {
+        for (key_state, &held) in self.key_state.iter_mut().zip(input.held.iter()) {
+            if *key_state & held > 0 {
+                self.irq = true;
+            }
+
+            *key_state = !held;
         }
     }
+
+    /// Returns the keys a player holds as a mask of `Key::mask` bits.
+    pub fn held(&self, player: usize) -> u8 {
+        !self.key_state[player]
+    }
 }
 
 impl IODevice for Joypad {
     fn write(&mut self, addr: u16, val: u8) {
         match addr {
-            0xff00 => self.joyp = (self.joyp & 0xcf) | (val & 0x30),
+            0xff00 => {
+                let prev = self.joyp & 0x30;
+                let val = val & 0x30;
+
+                if self.num_players > 1 {
+                    self.receive_packet_bit(prev, val);
+
+                    // Deselecting both key groups advances to the next player
+                    if self.multiplexed > 1 && prev != 0x30 && val == 0x30 {
+                        self.player = (self.player + 1) % self.multiplexed;
+                    }
+                }
+
+                self.joyp = (self.joyp & 0xcf) | val
+            }
             _ => unreachable!("Unexpected address: 0x{:04x}", addr),
         }
     }
@@ -72,12 +186,17 @@ impl IODevice for Joypad {
     fn read(&self, addr: u16) -> u8 {
         match addr {
             0xff00 => {
+                let key_state = self.key_state[self.player];
+
                 // Direction keys selected
                 if self.joyp & 0x10 == 0 {
-                    (self.joyp & 0xf0) | (self.key_state >> 4) & 0x0f
+                    (self.joyp & 0xf0) | (key_state >> 4) & 0x0f
                 // Button keys selected
                 } else if self.joyp & 0x20 == 0 {
-                    (self.joyp & 0xf0) | self.key_state & 0x0f
+                    (self.joyp & 0xf0) | key_state & 0x0f
+                // Player ID (0x0f for player 1, 0x0e for player 2, ...)
+                } else if self.multiplexed > 1 {
+                    (self.joyp & 0xf0) | (0x0f - self.player as u8)
                 } else {
                     self.joyp
                 }
@@ -88,3 +207,93 @@ impl IODevice for Joypad {
 
     fn update(&mut self, _tick: u8) {}
 }
+
+impl Savestate for Joypad {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        let mut multiplexed = self.multiplexed as u8;
+        let mut player = self.player as u8;
+
+        buf.u8(&mut self.joyp);
+        buf.u8(&mut multiplexed);
+        buf.u8(&mut player);
+        buf.bool(&mut self.irq);
+
+        // Packets being received are not kept
+        if buf.is_loading() {
+            self.multiplexed = match multiplexed {
+                2 | 4 if self.num_players > 1 => multiplexed as usize,
+                _ => 1,
+            };
+            self.player = player as usize % self.multiplexed;
+            self.packet_bits = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends an SGB command packet through the joypad register.
+    fn send_packet(joypad: &mut Joypad, packet: &[u8; 16]) {
+        joypad.write(0xff00, 0x00);
+        joypad.write(0xff00, 0x30);
+        for i in 0..128 {
+            let bit = packet[i / 8] >> (i % 8) & 1;
+            joypad.write(0xff00, if bit > 0 { 0x10 } else { 0x20 });
+            joypad.write(0xff00, 0x30);
+        }
+        // Stop bit
+        joypad.write(0xff00, 0x20);
+        joypad.write(0xff00, 0x30);
+    }
+
+    /// Returns the player IDs read over a few polls of the buttons.
+    fn player_ids(joypad: &mut Joypad) -> Vec<u8> {
+        (0..4)
+            .map(|_| {
+                joypad.write(0xff00, 0x10);
+                joypad.write(0xff00, 0x30);
+                joypad.read(0xff00) & 0x0f
+            })
+            .collect()
+    }
+
+    /// Returns an MLT_REQ packet with the players field of its data.
+    fn mlt_req(players: u8) -> [u8; 16] {
+        let mut packet = [0; 16];
+        packet[0] = MLT_REQ << 3 | 1;
+        packet[1] = players;
+        packet
+    }
+
+    #[test]
+    fn multiplexing_on_request() {
+        let mut joypad = Joypad::new();
+        joypad.set_num_players(2);
+
+        // Ordinary polling reads player 1 only
+        assert_eq!(player_ids(&mut joypad), vec![0x0f; 4]);
+
+        send_packet(&mut joypad, &mlt_req(0x01));
+        let ids = player_ids(&mut joypad);
+        assert!(ids.contains(&0x0f) && ids.contains(&0x0e));
+        assert!(ids.iter().all(|&id| id == 0x0f || id == 0x0e));
+
+        send_packet(&mut joypad, &mlt_req(0x03));
+        let ids = player_ids(&mut joypad);
+        assert_eq!(ids.len(), 4);
+        assert!((0x0c..=0x0f).all(|id| ids.contains(&id)));
+
+        send_packet(&mut joypad, &mlt_req(0x00));
+        assert_eq!(player_ids(&mut joypad), vec![0x0f; 4]);
+    }
+
+    #[test]
+    fn single_player_ignores_requests() {
+        let mut joypad = Joypad::new();
+
+        send_packet(&mut joypad, &mlt_req(0x01));
+        assert_eq!(player_ids(&mut joypad), vec![0x0f; 4]);
+    }
+}
@@ -0,0 +1,121 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+
+/// Named memory region (e.g. `c0a0 player HP`).
+struct Region {
+    /// First address
+    start: u16,
+    /// Last address (inclusive)
+    end: u16,
+    /// Name
+    name: String,
+}
+
+/// Names of memory regions, persisted per game in a text file with one
+/// `<addr>[-<end>] <name>` line per region.
+pub struct Labels {
+    /// Labels filename
+    fname: Option<String>,
+    /// Regions, in the order they were defined
+    regions: Vec<Region>,
+}
+
+/// Parses an `<addr>[-<end>] <name>` line.
+pub fn parse_line(line: &str) -> Result<(u16, u16, String), String> {
+    let line = line.trim();
+    let (range, name) = match line.find(char::is_whitespace) {
+        Some(pos) => (&line[..pos], line[pos..].trim()),
+        None => return Err(String::from("Expected <addr>[-<end>] <name>")),
+    };
+
+    let parse = |addr: &str| {
+        u16::from_str_radix(addr, 16).map_err(|_| format!("Invalid address: {}", addr))
+    };
+    let (start, end) = match range.find('-') {
+        Some(pos) => (parse(&range[..pos])?, parse(&range[pos + 1..])?),
+        None => (parse(range)?, parse(range)?),
+    };
+
+    if start > end || name.is_empty() {
+        return Err(format!("Invalid label: {}", line));
+    }
+
+    Ok((start, end, name.to_string()))
+}
+
+impl Labels {
+    /// Creates an empty set of labels that is not persisted.
+    pub fn empty() -> Self {
+        Labels {
+            fname: None,
+            regions: Vec::new(),
+        }
+    }
+
+    /// Loads labels from a file. A missing file is treated as empty and
+    /// invalid lines are skipped.
+    pub fn load(fname: &str) -> Self {
+        let mut labels = Labels {
+            fname: Some(fname.to_string()),
+            regions: Vec::new(),
+        };
+
+        if let Ok(file) = File::open(fname) {
+            for (i, line) in BufReader::new(file).lines().enumerate() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                    continue;
+                }
+
+                match parse_line(&line) {
+                    Ok((start, end, name)) => labels.add(start, end, name),
+                    Err(e) => warn!("{} at line {} of {}", e, i + 1, fname),
+                }
+            }
+        }
+
+        labels
+    }
+
+    /// Defines a region, replacing an existing one with the same range.
+    pub fn add(&mut self, start: u16, end: u16, name: String) {
+        self.regions.retain(|r| r.start != start || r.end != end);
+        self.regions.push(Region { start, end, name });
+    }
+
+    /// Returns the name of the region containing an address, with the offset
+    /// into the region if the address is not its start (e.g. `score+1`).
+    pub fn describe(&self, addr: u16) -> Option<String> {
+        // Later definitions take precedence
+        self.regions
+            .iter()
+            .rev()
+            .find(|r| (r.start..=r.end).contains(&addr))
+            .map(|r| match addr - r.start {
+                0 => r.name.clone(),
+                offset => format!("{}+{}", r.name, offset),
+            })
+    }
+
+    /// Writes the labels back to the file they were loaded from.
+    pub fn save(&self) -> io::Result<()> {
+        let fname = match self.fname {
+            Some(ref fname) => fname,
+            None => return Ok(()),
+        };
+
+        let mut file = File::create(fname)?;
+        for r in self.regions.iter() {
+            if r.start == r.end {
+                writeln!(file, "{:04x} {}", r.start, r.name)?;
+            } else {
+                writeln!(file, "{:04x}-{:04x} {}", r.start, r.end, r.name)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+use ppu::{FRAME_BUFFER_SIZE, PPU};
+
+/// Shade shown while the LCD is off or the game shows a blank (all white)
+/// frame. Only the displayed image is affected, not emulation.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LcdOff {
+    /// White, like the hardware (default)
+    White,
+    /// The lightest shade BGP currently maps colors to
+    Palette,
+    /// The last frame shown with the LCD on
+    Hold,
+}
+
+impl LcdOff {
+    /// Returns a mode by its name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "white" => Some(LcdOff::White),
+            "palette" => Some(LcdOff::Palette),
+            "hold" => Some(LcdOff::Hold),
+            _ => None,
+        }
+    }
+}
+
+/// Picks the frame to display, replacing blank screens according to a mode.
+pub struct LcdOffFilter {
+    /// Shade shown instead of blank screens
+    mode: LcdOff,
+    /// Frame displayed instead of blank screens
+    frame: Box<[u8]>,
+}
+
+impl LcdOffFilter {
+    /// Creates a new `LcdOffFilter`.
+    pub fn new(mode: LcdOff) -> Self {
+        LcdOffFilter {
+            mode,
+            frame: vec![0xff; FRAME_BUFFER_SIZE].into_boxed_slice(),
+        }
+    }
+
+    /// Returns the frame to display for the last completed frame of `ppu`.
+    pub fn filter<'a>(&'a mut self, ppu: &'a PPU) -> &'a [u8] {
+        let frame = ppu.frame_buffer();
+        let blank = !ppu.lcd_enabled() || frame.iter().all(|&pixel| pixel == 0xff);
+
+        if !blank {
+            if self.mode == LcdOff::Hold {
+                self.frame.copy_from_slice(frame);
+            }
+            return frame;
+        }
+
+        match self.mode {
+            LcdOff::White => {
+                for pixel in self.frame.iter_mut() {
+                    *pixel = 0xff;
+                }
+            }
+            LcdOff::Palette => {
+                let (_, shades) = ppu.palettes()[0];
+                let lightest = shades.iter().cloned().max().unwrap_or(0xff);
+
+                for pixel in self.frame.iter_mut() {
+                    *pixel = lightest;
+                }
+            }
+            LcdOff::Hold => (),
+        }
+
+        &self.frame
+    }
+}
@@ -1,5 +1,11 @@
+use std::collections::HashMap;
 use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::iter;
 use std::path::PathBuf;
+use std::process;
+use std::sync::mpsc;
 
 #[macro_use]
 extern crate log;
@@ -9,63 +15,1591 @@ extern crate sdl2;
 use std::thread;
 use std::time;
 
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::audio::AudioSpecDesired;
+use sdl2::controller::{Axis, Button};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
 use sdl2::pixels::PixelFormatEnum;
 
+use action::Action;
+use catridge_error::CatridgeError;
+use input_source::{InputSource, InputState};
+use storage::StorageBackend;
+
+mod accuracy;
+mod action;
+mod apu;
+mod audio_fade;
+mod audio_test;
+mod bank_stats;
+mod blip;
+mod camera;
+mod capabilities;
+mod capture;
 mod catridge;
+mod catridge_error;
+mod catridge_info;
+mod cheats;
+mod clock;
+mod compare;
 mod cpu;
+#[cfg(test)]
+mod cycles;
+mod doctor;
+mod emulator;
+mod four_player;
+mod hash_frames;
+mod huc3;
+mod info;
+mod input_script;
+mod input_source;
+mod interrupt;
 mod io_device;
+mod io_trace;
 mod joypad;
+mod labels;
+mod lcd_off;
+mod licensee;
+mod mbc;
+mod mbc7;
+mod mmm01;
 mod mmu;
+mod osd;
+mod patch;
+mod play_stats;
+mod playlist;
+mod png;
+mod pool;
 mod ppu;
+mod profile;
+mod rewind;
+mod rng;
+mod rtc;
+mod save_file;
+mod savestate;
+mod scaler;
+mod serial;
+mod smoke_test;
+mod storage;
+mod telemetry;
+#[cfg(feature = "test-roms")]
+mod test_roms;
+mod tiles;
+mod time_stretch;
+mod timeline;
 mod timer;
+mod tracepoint;
+mod turbo;
+mod unlicensed;
+mod vram_watch;
+mod wav;
+
+/// Keyboard bindings of the joypad.
+const KEY_BINDINGS: [(Keycode, joypad::Key); 8] = [
+    (Keycode::Down, joypad::Key::Down),
+    (Keycode::Up, joypad::Key::Up),
+    (Keycode::Left, joypad::Key::Left),
+    (Keycode::Right, joypad::Key::Right),
+    (Keycode::Return, joypad::Key::Start),
+    (Keycode::RShift, joypad::Key::Select),
+    (Keycode::X, joypad::Key::A),
+    (Keycode::Z, joypad::Key::B),
+];
+
+/// Gamepad buttons bound to joypad keys.
+const GAMEPAD_BINDINGS: [(Button, joypad::Key); 8] = [
+    (Button::DPadDown, joypad::Key::Down),
+    (Button::DPadUp, joypad::Key::Up),
+    (Button::DPadLeft, joypad::Key::Left),
+    (Button::DPadRight, joypad::Key::Right),
+    (Button::Start, joypad::Key::Start),
+    (Button::Back, joypad::Key::Select),
+    (Button::A, joypad::Key::A),
+    (Button::B, joypad::Key::B),
+];
+
+/// Key, optionally combined with Ctrl, bound to an action.
+#[derive(Copy, Clone, PartialEq)]
+struct Hotkey {
+    /// Key
+    key: Keycode,
+    /// Ctrl must be held
+    ctrl: bool,
+}
+
+impl Hotkey {
+    /// Returns the name of the key combination (e.g. `Ctrl+F6`).
+    fn name(self) -> String {
+        if self.ctrl {
+            format!("Ctrl+{}", self.key.name())
+        } else {
+            self.key.name()
+        }
+    }
+}
+
+/// Returns a hotkey without modifiers.
+const fn key(key: Keycode) -> Hotkey {
+    Hotkey { key, ctrl: false }
+}
+
+/// Returns a hotkey combined with Ctrl.
+const fn ctrl(key: Keycode) -> Hotkey {
+    Hotkey { key, ctrl: true }
+}
+
+/// Frames of audio kept queued when pacing by the audio device.
+const AUDIO_SYNC_FRAMES: u32 = 3;
+
+/// Step of the volume hotkeys in percent.
+const VOLUME_STEP: u8 = 10;
+
+/// Gamepad trigger positions below this are treated as released.
+const TRIGGER_DEADZONE: i16 = 1024;
+
+/// Frames an on-screen message is shown for.
+const MESSAGE_FRAMES: u64 = 90;
+
+/// Number of states kept for undoing load state.
+const UNDO_STATES: usize = 4;
+
+/// Bindings of emulator hotkeys to actions.
+const HOTKEYS: [(Hotkey, Action); 30] = [
+    (key(Keycode::F1), Action::ToggleHelp),
+    (key(Keycode::F2), Action::TogglePalettes),
+    (ctrl(Keycode::F2), Action::ToggleTelemetry),
+    (
+        key(Keycode::F3),
+        Action::ToggleLayer(ppu::Layer::Background),
+    ),
+    (key(Keycode::F4), Action::ToggleLayer(ppu::Layer::Window)),
+    (key(Keycode::F5), Action::ToggleLayer(ppu::Layer::Sprites)),
+    (ctrl(Keycode::F5), Action::ToggleCollisions),
+    (key(Keycode::I), Action::ToggleInputDisplay),
+    (key(Keycode::Num1), Action::MuteChannel(1)),
+    (key(Keycode::Num2), Action::MuteChannel(2)),
+    (key(Keycode::Num3), Action::MuteChannel(3)),
+    (key(Keycode::Num4), Action::MuteChannel(4)),
+    (key(Keycode::Minus), Action::VolumeDown),
+    (key(Keycode::Equals), Action::VolumeUp),
+    (key(Keycode::F6), Action::SaveState(1)),
+    (key(Keycode::F7), Action::LoadState(1)),
+    (ctrl(Keycode::F6), Action::SaveState(2)),
+    (ctrl(Keycode::F7), Action::LoadState(2)),
+    (key(Keycode::F8), Action::UndoLoadState),
+    (key(Keycode::F9), Action::Reset),
+    (key(Keycode::C), Action::ToggleCheats),
+    (key(Keycode::F10), Action::ToggleTimeline),
+    (key(Keycode::F11), Action::Screenshot),
+    (ctrl(Keycode::F11), Action::ToggleAudioRecording),
+    (key(Keycode::F12), Action::DumpPartialFrame),
+    (key(Keycode::P), Action::TogglePause),
+    (key(Keycode::Tab), Action::ToggleFastForward),
+    (ctrl(Keycode::Tab), Action::TurboUntil),
+    (key(Keycode::Backspace), Action::Rewind),
+    (key(Keycode::Escape), Action::Quit),
+];
+
+/// Returns the action bound to a key and modifiers.
+fn find_hotkey(keycode: Keycode, keymod: Mod) -> Option<Action> {
+    let hotkey = Hotkey {
+        key: keycode,
+        ctrl: keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD),
+    };
+
+    HOTKEYS
+        .iter()
+        .find(|&&(k, _)| k == hotkey)
+        .map(|&(_, action)| action)
+}
 
 /// Translates keycode to `joypad::Key` enum.
 fn translate_keycode(key: Keycode) -> Option<joypad::Key> {
-    match key {
-        Keycode::Down => Some(joypad::Key::Down),
-        Keycode::Up => Some(joypad::Key::Up),
-        Keycode::Left => Some(joypad::Key::Left),
-        Keycode::Right => Some(joypad::Key::Right),
-        Keycode::Return => Some(joypad::Key::Start),
-        Keycode::RShift => Some(joypad::Key::Select),
-        Keycode::X => Some(joypad::Key::A),
-        Keycode::Z => Some(joypad::Key::B),
-        _ => None,
+    KEY_BINDINGS
+        .iter()
+        .find(|&&(keycode, _)| keycode == key)
+        .map(|&(_, k)| k)
+}
+
+/// Returns the lines of the key binding help screen.
+fn help_lines() -> Vec<String> {
+    let mut lines = vec![String::from("Joypad")];
+
+    // Two joypad keys per line to fit all hotkeys on the screen
+    for pair in KEY_BINDINGS.chunks(2) {
+        let keys: Vec<String> = pair
+            .iter()
+            .map(|&(keycode, key)| format!(" {:<6} {}", format!("{:?}", key), keycode.name()))
+            .collect();
+        lines.push(format!(
+            "{:<19}{}",
+            keys[0],
+            keys.get(1).unwrap_or(&String::new())
+        ));
+    }
+
+    lines.push(String::from("Hotkeys"));
+
+    for &(hotkey, action) in HOTKEYS.iter() {
+        lines.push(format!(" {:<18} {}", action.description(), hotkey.name()));
+    }
+
+    lines
+}
+
+/// Lines of the help screen fitting on the screen, including the page number.
+const HELP_LINES_PER_PAGE: usize = 23;
+
+/// Returns the number of pages of the help screen.
+fn help_pages() -> usize {
+    (help_lines().len() + HELP_LINES_PER_PAGE - 2) / (HELP_LINES_PER_PAGE - 1)
+}
+
+/// Overlays a page of the key binding help screen on a frame.
+fn draw_help(buf: &mut [u8], w: usize, scale: usize, page: usize) {
+    osd::dim(buf);
+
+    let lines = help_lines();
+    let page_lines = lines
+        .iter()
+        .skip(page * (HELP_LINES_PER_PAGE - 1))
+        .take(HELP_LINES_PER_PAGE - 1);
+
+    for (i, line) in page_lines.enumerate() {
+        osd::draw_text(buf, w, 4, 4 + i * osd::CHAR_H, scale, line, 0xff);
+    }
+
+    let footer = format!("Page {}/{} (F1 for more)", page + 1, help_pages());
+    let y = 4 + (HELP_LINES_PER_PAGE - 1) * osd::CHAR_H;
+    osd::draw_text(buf, w, 4, y, scale, &footer, 0x80);
+}
+
+/// Overlays a short message at the bottom of a frame.
+fn draw_message(buf: &mut [u8], w: usize, scale: usize, text: &str) {
+    let y = 144 - osd::CHAR_H - 4;
+
+    osd::fill_rect(
+        buf,
+        w,
+        (2, y - 2, text.len() * osd::CHAR_W + 3, 9),
+        scale,
+        0x00,
+    );
+    osd::draw_text(buf, w, 4, y, scale, text, 0xff);
+}
+
+/// Returns the speed multiplier for a gamepad trigger position, from 1 when
+/// released to `max_speed` when fully pressed.
+fn trigger_speed(value: i16, max_speed: f32) -> f32 {
+    if value < TRIGGER_DEADZONE {
+        return 1.0;
+    }
+
+    let pressure = (value - TRIGGER_DEADZONE) as f32 / (i16::MAX - TRIGGER_DEADZONE) as f32;
+    1.0 + (max_speed - 1.0) * pressure
+}
+
+/// Raises or lowers the audio volume by a step and returns a message showing
+/// the new volume.
+fn change_volume(apu: &mut apu::APU, up: bool) -> String {
+    let current = (apu.volume() * 100.0).round() as u8;
+    let volume = if up {
+        (current + VOLUME_STEP).min(100)
+    } else {
+        current.saturating_sub(VOLUME_STEP)
+    };
+
+    apu.set_volume(volume as f32 / 100.0);
+
+    format!("Volume {}%", volume)
+}
+
+/// Disables all cheat codes if any is enabled, or enables all of them
+/// otherwise. Prints the codes and returns a message showing the new state.
+fn toggle_cheats(cheats: &mut cheats::Cheats) -> String {
+    let enabled = !cheats.list().iter().any(|c| c.enabled);
+
+    for i in 0..cheats.list().len() {
+        cheats.set_enabled(i, enabled);
+    }
+    for cheat in cheats.list() {
+        println!("Cheat {} ({:?})", cheat.code, cheat.kind);
+    }
+
+    format!(
+        "Cheats {} ({})",
+        if enabled { "on" } else { "off" },
+        cheats.list().len()
+    )
+}
+
+/// Mutes or unmutes an audio channel.
+fn mute_channel(apu: &mut apu::APU, channel: u8) {
+    let muted = apu.toggle_muted(channel as usize);
+
+    println!(
+        "Channel {} {}",
+        channel,
+        if muted { "muted" } else { "unmuted" }
+    );
+}
+
+/// Joypad input from the keyboard.
+struct Keyboard {
+    /// Keys currently held
+    held: InputState,
+}
+
+impl Keyboard {
+    /// Creates a new `Keyboard`.
+    fn new() -> Self {
+        Keyboard {
+            held: InputState::default(),
+        }
+    }
+
+    /// Handles key down event.
+    fn keydown(&mut self, key: Keycode) {
+        if let Some(k) = translate_keycode(key) {
+            self.held.press(0, k);
+        }
+    }
+
+    /// Handles key up event.
+    fn keyup(&mut self, key: Keycode) {
+        if let Some(k) = translate_keycode(key) {
+            self.held.release(0, k);
+        }
+    }
+}
+
+impl InputSource for Keyboard {
+    fn poll(&mut self, _frame: u64) -> InputState {
+        self.held
+    }
+}
+
+/// State of the joypad keys from gamepads. With several players, the
+/// keyboard is player 1 and gamepads are players 2 and up in the order they
+/// were connected; otherwise gamepads are player 1 too.
+struct Gamepads {
+    /// Keys currently held
+    held: InputState,
+    /// Number of players
+    players: usize,
+}
+
+impl Gamepads {
+    /// Creates a new `Gamepads` for a number of players.
+    fn new(players: usize) -> Self {
+        Gamepads {
+            held: InputState::default(),
+            players,
+        }
+    }
+
+    /// Handles a button event of the nth connected gamepad.
+    fn button(&mut self, nth: usize, button: Button, pressed: bool) {
+        let player = if self.players > 1 { nth + 1 } else { 0 };
+        let key = GAMEPAD_BINDINGS
+            .iter()
+            .find(|&&(b, _)| b == button)
+            .map(|&(_, k)| k);
+
+        match key {
+            Some(key) if player < self.players && pressed => self.held.press(player, key),
+            Some(key) if player < self.players => self.held.release(player, key),
+            _ => (),
+        }
+    }
+}
+
+impl InputSource for Gamepads {
+    fn poll(&mut self, _frame: u64) -> InputState {
+        self.held
+    }
+}
+
+/// Keypad keys tilting MBC7 catridges left, right, up and down.
+const TILT_KEYS: [Keycode; 4] = [Keycode::Kp4, Keycode::Kp6, Keycode::Kp8, Keycode::Kp2];
+
+/// Tilt of MBC7 catridges, from the numeric keypad or the left stick of a
+/// gamepad.
+struct Tilt {
+    /// Keypad keys in `TILT_KEYS` held
+    keys: [bool; 4],
+    /// Left stick position (-1.0 to 1.0 on each axis)
+    stick: (f32, f32),
+}
+
+impl Tilt {
+    /// Creates a new, level `Tilt`.
+    fn new() -> Self {
+        Tilt {
+            keys: [false; 4],
+            stick: (0.0, 0.0),
+        }
+    }
+
+    /// Handles a key event and returns true if the key tilts.
+    fn key(&mut self, key: Keycode, held: bool) -> bool {
+        match TILT_KEYS.iter().position(|&k| k == key) {
+            Some(i) => {
+                self.keys[i] = held;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Handles a motion of the left stick.
+    fn stick(&mut self, axis: Axis, value: i16) {
+        let pos = value as f32 / i16::MAX as f32;
+
+        match axis {
+            Axis::LeftX => self.stick.0 = pos,
+            Axis::LeftY => self.stick.1 = pos,
+            _ => (),
+        }
+    }
+
+    /// Returns the tilt in g, positive to the right and towards the player.
+    /// Keys tilt fully and override the stick.
+    fn get(&self) -> (f32, f32) {
+        let axis = |neg: bool, pos: bool, stick: f32| match (neg, pos) {
+            (true, false) => -1.0,
+            (false, true) => 1.0,
+            _ => stick,
+        };
+
+        (
+            axis(self.keys[0], self.keys[1], self.stick.0),
+            axis(self.keys[2], self.keys[3], self.stick.1),
+        )
+    }
+}
+
+/// Command line options.
+struct Options {
+    /// ROM filename
+    rom_fname: String,
+    /// Boot ROM filename
+    boot_rom_fname: Option<String>,
+    /// Run the boot ROM invisibly before showing the first frame
+    fast_boot: bool,
+    /// Log filter (e.g. `cpu=trace,ppu=info`)
+    log: Option<String>,
+    /// Advance the RTC by emulated time only
+    emulated_rtc: bool,
+    /// Video scaler
+    scaler: Box<dyn scaler::Scaler>,
+    /// Shade shown while the LCD is off
+    lcd_off: lcd_off::LcdOff,
+    /// Input script filename
+    input_fname: Option<String>,
+    /// Number of players multiplexed on the joypad (1, 2 or 4)
+    players: usize,
+    /// Number of Game Boys linked through a four player adapter
+    four_player: Option<usize>,
+    /// Extra clocks per link cable transfer
+    link_latency: u16,
+    /// Number of bytes after which the link cable is disconnected
+    link_disconnect_after: Option<u64>,
+    /// Watched VRAM address range
+    watch_vram: Option<(u16, u16)>,
+    /// Lift the 10 sprites per scanline limit (inaccurate)
+    no_sprite_limit: bool,
+    /// Do not warn about an incorrect ROM header checksum
+    skip_checksum: bool,
+    /// IPS or BPS patch applied to the ROM
+    patch_fname: Option<String>,
+    /// BG color numbers (bit n for color n) shown as collisions with sprites
+    collision_colors: u8,
+    /// CPU clock multiplier (inaccurate)
+    overclock: u8,
+    /// Speed multiplier when the right trigger is fully pressed
+    max_speed: f32,
+    /// Print ROM bank usage statistics on exit
+    bank_stats: bool,
+    /// Clocks before a requested interrupt is serviced (overrides the preset)
+    irq_latency: Option<u16>,
+    /// Seconds of play kept for rewinding
+    rewind_secs: usize,
+    /// Loading a state keeps the current catridge RAM
+    state_keeps_ram: bool,
+    /// Blend consecutive frames
+    blend_frames: bool,
+    /// Do not record play statistics
+    no_stats: bool,
+    /// Tracepoints
+    tracepoints: Vec<tracepoint::Tracepoint>,
+    /// Memory region labels to add
+    labels: Vec<(u16, u16, String)>,
+    /// Seed for power-on RAM contents
+    seed: Option<u64>,
+    /// Accuracy preset
+    accuracy: accuracy::Accuracy,
+    /// Log when execution enters RAM
+    trace_ram_exec: bool,
+    /// I/O registers whose reads and writes are printed
+    io_trace: Vec<u16>,
+    /// Cheat codes
+    cheats: Vec<String>,
+    /// Pace emulation by audio consumption instead of a timer
+    audio_sync: bool,
+    /// Value read from disabled catridge RAM (overrides the mapper default)
+    disabled_ram: Option<catridge::DisabledRam>,
+    /// Power-on catridge RAM contents
+    sram_init: Option<catridge::RamInit>,
+    /// Grayscale PNG image seen by the Game Boy Camera
+    camera_image: Option<String>,
+    /// WAV file to record audio to from the start
+    record_audio: Option<String>,
+    /// File to write the emulated time at every V-Blank to
+    clock_out: Option<String>,
+    /// Audio volume in percent
+    volume: u8,
+    /// Downmix audio to mono
+    mono: bool,
+    /// Requested audio sample rate in Hz
+    sample_rate: u32,
+    /// Capture taken when an address is first executed
+    capture: Option<capture::Capture>,
+    /// State file to start from
+    load_state: Option<String>,
+    /// Memory condition that stops fast forwarding
+    turbo_until: Option<turbo::MemCondition>,
+    /// ROMs inserted at frames without a reset (`None` ejects the catridge)
+    hot_swaps: Vec<(u64, Option<String>)>,
+    /// ROMs played in turn instead of a single ROM
+    playlist: Option<playlist::Playlist>,
+}
+
+/// Prints usage and exits.
+fn usage() -> ! {
+    eprintln!("Usage: gbr [run] [options] <rom|url|->");
+    eprintln!("       gbr test [-j <jobs>] <script>...");
+    eprintln!("       gbr audio-test [--update] <script>...");
+    eprintln!("       gbr hash-frames <rom> [--frames <n>] [--every <n>] [--input <file>]");
+    eprintln!("                       [--seed <n>]");
+    eprintln!("       gbr trace <rom> [options] [--detail <frame>]");
+    eprintln!("       gbr compare <gbr|trace> <rom> [options]");
+    eprintln!("       gbr stats");
+    eprintln!("       gbr capabilities");
+    eprintln!("       gbr info <rom>");
+    eprintln!("       gbr png2tiles <image.png> -o <tiles.2bpp>");
+    eprintln!("       gbr tiles2png <tiles.2bpp> -o <image.png>");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("    --boot-rom <file>   Run a boot ROM before the catridge");
+    eprintln!("    --fast-boot         Run the boot ROM invisibly");
+    eprintln!("    --skip-checksum     Do not check the ROM header checksum");
+    eprintln!("    --patch <file>      Apply an IPS or BPS patch to the ROM");
+    eprintln!("    --log <filter>      Log filter (e.g. cpu=trace,ppu=info)");
+    eprintln!("    --emulated-rtc      Advance the RTC by emulated time only");
+    eprintln!("    --scaler <name>     Video scaler (none, scale2x, scale3x)");
+    eprintln!("    --lcd-off <mode>    Shown while the LCD is off (white, palette, hold)");
+    eprintln!("    --input <file>      Replay joypad input from a script");
+    eprintln!("    --players <n>       Multiplex the joypads of 2 or 4 players");
+    eprintln!("    --four-player <n>   Link 2 to 4 Game Boys through a four player adapter");
+    eprintln!("    --link-latency <n>  Delay link cable transfers by n clocks");
+    eprintln!("    --link-disconnect-after <n>");
+    eprintln!("                        Disconnect the link cable after n bytes");
+    eprintln!("    --watch-vram <start>-<end>");
+    eprintln!("                        Print writes to a VRAM range (e.g. 8000-87ff)");
+    eprintln!("    --no-sprite-limit   Draw all sprites on a line (inaccurate)");
+    eprintln!("    --collisions <colors>");
+    eprintln!("                        BG colors sprites collide with (e.g. 1,2,3, default 3)");
+    eprintln!("    --overclock <n>     Run the CPU n times faster (1-8, inaccurate)");
+    eprintln!("    --max-speed <n>     Speed with the right trigger fully pressed (default 4)");
+    eprintln!("    --bank-stats        Print ROM bank usage statistics on exit");
+    eprintln!("    --irq-latency <n>   Delay servicing interrupts by n clocks");
+    eprintln!("    --accuracy <preset> Accuracy preset (fast, balanced, cycle)");
+    eprintln!("    --rewind <secs>     Keep snapshots to rewind with Backspace");
+    eprintln!("    --state-keeps-ram   Loading states does not alter catridge RAM");
+    eprintln!("    --blend-frames      Blend consecutive frames like the DMG LCD");
+    eprintln!("    --audio-sync        Pace emulation by the audio device");
+    eprintln!("    --no-stats          Do not record play time and launch count");
+    eprintln!("    --trace <addr>:<message>");
+    eprintln!("                        Print a message (e.g. \"HP={{[c0a0]}} A={{a}}\") when");
+    eprintln!("                        the instruction at addr is executed");
+    eprintln!("    --trace-ram-exec    Print when execution jumps from ROM into RAM");
+    eprintln!("    --io-trace <regs>   Print reads and writes of I/O registers (e.g.");
+    eprintln!("                        ff40,ff41,ff46)");
+    eprintln!("    --cheat <code>      Apply a Game Genie or GameShark code");
+    eprintln!("    --disabled-ram <value|open-bus|random>");
+    eprintln!("                        Value read from disabled catridge RAM");
+    eprintln!("    --sram-init <value|random[:<seed>]>");
+    eprintln!("                        Catridge RAM contents when there is no save");
+    eprintln!("    --camera <image>    PNG image seen by the Game Boy Camera");
+    eprintln!("    --record-audio <file>");
+    eprintln!("                        Record audio to a WAV file");
+    eprintln!("    --clock-out <file>  Write the emulated time at every V-Blank to a file");
+    eprintln!("    --volume <percent>  Audio volume (0-100)");
+    eprintln!("    --mono              Downmix audio to mono");
+    eprintln!("    --sample-rate <hz>  Audio sample rate (e.g. 44100, 48000, 96000)");
+    eprintln!("    --capture-at pc=<addr>:<state,frame,exit>");
+    eprintln!("                        Capture the state or frame when addr is executed");
+    eprintln!("    --load-state <file> Start from a state written by --capture-at");
+    eprintln!("    --turbo-until <addr><op><value>");
+    eprintln!("                        Fast forward until a memory condition becomes true");
+    eprintln!("    --hot-swap <frame>:[<rom>]");
+    eprintln!("                        Swap the catridge at a frame without a reset");
+    eprintln!("    --playlist <file>   Play the ROMs of a playlist in turn instead of a ROM");
+    eprintln!("    --idle <secs>       Idle timeout of playlist entries (default 60)");
+    eprintln!("    --seed <n>          Seed power-on RAM and use the emulated RTC");
+    eprintln!("    --label \"<addr>[-<end>] <name>\"");
+    eprintln!("                        Name a memory region (saved to <rom>.labels)");
+    process::exit(1);
+}
+
+/// Shows or hides a PPU layer.
+fn toggle_layer(ppu: &mut ppu::PPU, layer: ppu::Layer) {
+    let visible = ppu.toggle_layer(layer);
+
+    println!("{:?} {}", layer, if visible { "shown" } else { "hidden" });
+}
+
+/// Saves the frame being rendered up to the current LY as a PNG file.
+fn dump_partial_frame(ppu: &ppu::PPU, frame: u64) {
+    let fname = format!("gbr-frame{}-ly{}.png", frame, ppu.ly());
+
+    match png::save_gray(&fname, &ppu.partial_frame(), 160, 144) {
+        Ok(()) => println!("Saved partial frame to {}", fname),
+        Err(e) => eprintln!("Failed to save {}: {}", fname, e),
+    }
+}
+
+/// Saves the last complete frame as a PNG file.
+fn screenshot(ppu: &ppu::PPU, frame: u64) {
+    let fname = format!("gbr-frame{}.png", frame);
+
+    match png::save_gray(&fname, ppu.frame_buffer(), 160, 144) {
+        Ok(()) => println!("Saved screenshot to {}", fname),
+        Err(e) => eprintln!("Failed to save {}: {}", fname, e),
+    }
+}
+
+/// Saves what a capture asks for from a snapshot taken right before its
+/// address was executed. Returns true if the emulator should exit.
+fn take_capture(capture: &capture::Capture, snapshot: &cpu::CPU, frame: u64) -> bool {
+    println!("Reached 0x{:04x} in frame {}", capture.pc, frame);
+
+    if capture.frame {
+        let fname = format!("gbr-capture-{:04x}.png", capture.pc);
+
+        match png::save_gray(&fname, &snapshot.mmu.ppu.partial_frame(), 160, 144) {
+            Ok(()) => println!("Saved frame to {}", fname),
+            Err(e) => eprintln!("Failed to save {}: {}", fname, e),
+        }
+    }
+
+    if capture.state {
+        let fname = format!("gbr-capture-{:04x}.state", capture.pc);
+
+        match storage::FileStorage.store(&fname, &savestate::save(snapshot)) {
+            Ok(()) => println!("Saved state to {}", fname),
+            Err(e) => eprintln!("Failed to save {}: {}", fname, e),
+        }
+    }
+
+    capture.exit
+}
+
+/// Starts recording audio to a WAV file.
+fn start_recording(fname: &str, sample_rate: u32) -> Option<wav::WavWriter> {
+    match wav::WavWriter::create(fname, sample_rate, 2) {
+        Ok(wav) => {
+            println!("Recording audio to {}", fname);
+            Some(wav)
+        }
+        Err(e) => {
+            eprintln!("Failed to create {}: {}", fname, e);
+            None
+        }
+    }
+}
+
+/// Writes the emulated time at every V-Blank to a file (e.g. a named pipe) as
+/// `<frame> <secs>` lines. The file is written from a thread, so that opening
+/// a pipe or a slow reader does not stall emulation.
+fn start_clock_out(fname: &str) -> mpsc::Sender<clock::VBlank> {
+    let (sender, receiver) = mpsc::channel::<clock::VBlank>();
+    let fname = fname.to_string();
+
+    thread::spawn(move || {
+        let mut file = match File::create(&fname) {
+            Ok(file) => BufWriter::new(file),
+            Err(e) => return eprintln!("Failed to create {}: {}", fname, e),
+        };
+
+        for vblank in receiver {
+            let line = writeln!(file, "{} {:.6}", vblank.frame, vblank.secs());
+            if let Err(e) = line.and_then(|()| file.flush()) {
+                return eprintln!("Failed to write {}: {}", fname, e);
+            }
+        }
+    });
+
+    sender
+}
+
+/// Stops recording audio.
+fn finish_recording(wav: wav::WavWriter) {
+    let fname = wav.fname().to_string();
+
+    match wav.finish() {
+        Ok(()) => println!("Saved audio to {}", fname),
+        Err(e) => eprintln!("Failed to save {}: {}", fname, e),
+    }
+}
+
+/// Overlays the current BGP, OBP0 and OBP1 as color swatches on a frame.
+fn draw_palettes(buf: &mut [u8], w: usize, scale: usize, ppu: &ppu::PPU) {
+    let names = ["BGP", "OBP0", "OBP1"];
+
+    for (i, &(reg, shades)) in ppu.palettes().iter().enumerate() {
+        let y = 4 + i * 10;
+
+        osd::fill_rect(buf, w, (2, y - 2, 80, 10), scale, 0x00);
+        osd::draw_text(
+            buf,
+            w,
+            4,
+            y,
+            scale,
+            &format!("{:<4} {:02X}", names[i], reg),
+            0xff,
+        );
+
+        for (j, &shade) in shades.iter().enumerate() {
+            // Swatch with a frame so that white and black stay visible
+            osd::fill_rect(buf, w, (36 + j * 11, y - 1, 9, 7), scale, 0x80);
+            osd::fill_rect(buf, w, (37 + j * 11, y, 7, 5), scale, shade);
+        }
+    }
+}
+
+/// Draws the counters of the last frame at the top right of a frame.
+fn draw_telemetry(buf: &mut [u8], w: usize, scale: usize, counters: telemetry::Counters) {
+    let lines = [
+        format!("INSTR {:6}", counters.instructions),
+        format!("LINES {:6}", counters.scanlines),
+        format!("DMA   {:6}", counters.dma_transfers),
+        format!("BANK  {:6}", counters.bank_switches),
+        format!("SMPL  {:6}", counters.samples),
+    ];
+    let x = 160 - lines[0].len() * osd::CHAR_W - 4;
+
+    osd::fill_rect(
+        buf,
+        w,
+        (
+            x - 2,
+            2,
+            lines[0].len() * osd::CHAR_W + 3,
+            lines.len() * 8 + 2,
+        ),
+        scale,
+        0x00,
+    );
+    for (i, line) in lines.iter().enumerate() {
+        osd::draw_text(buf, w, x, 4 + i * 8, scale, line, 0xff);
+    }
+}
+
+/// Positions of the keys on the input display as (key, x, y, width,
+/// height), relative to its top left corner.
+const INPUT_DISPLAY_KEYS: [(joypad::Key, usize, usize, usize, usize); 8] = [
+    (joypad::Key::Up, 6, 0, 5, 5),
+    (joypad::Key::Left, 0, 6, 5, 5),
+    (joypad::Key::Right, 12, 6, 5, 5),
+    (joypad::Key::Down, 6, 12, 5, 5),
+    (joypad::Key::B, 23, 6, 5, 5),
+    (joypad::Key::A, 30, 3, 5, 5),
+    (joypad::Key::Select, 20, 14, 6, 3),
+    (joypad::Key::Start, 28, 14, 6, 3),
+];
+
+/// Draws the keys held on the joypad at the bottom right of a frame, white
+/// when held and gray otherwise.
+fn draw_input_display(buf: &mut [u8], w: usize, scale: usize, held: u8) {
+    let (x, y) = (160 - 41, 144 - 21);
+
+    osd::fill_rect(buf, w, (x - 2, y - 2, 39, 21), scale, 0x00);
+    for &(key, kx, ky, kw, kh) in INPUT_DISPLAY_KEYS.iter() {
+        let shade = if held & key.mask() > 0 { 0xff } else { 0x55 };
+
+        osd::fill_rect(buf, w, (x + kx, y + ky, kw, kh), scale, shade);
+    }
+}
+
+/// Shades of PPU modes 0-3 on the timeline.
+const MODE_SHADES: [u8; 4] = [0x50, 0x20, 0x90, 0xc8];
+
+/// Draws the timeline of the last frame over a frame as a strip chart with
+/// one row per scanline. PPU modes are drawn as shades of gray, OAM DMA
+/// transfers checkered, interrupt requests as white marks and calls to
+/// interrupt service routines as black marks.
+fn draw_timeline(buf: &mut [u8], w: usize, timeline: &timeline::Timeline) {
+    let h = buf.len() / w;
+    let lines = (cpu::CYCLES_PER_FRAME / timeline::LINE_CLOCKS) as usize;
+    let line_clocks = timeline::LINE_CLOCKS as usize;
+    let events = timeline.last_frame();
+
+    let mut mode = 0;
+    let mut dma_end = 0;
+    let mut next = 0;
+
+    for y in 0..h {
+        for x in 0..w {
+            let clock = ((y * lines / h) * line_clocks + x * line_clocks / w) as u32;
+
+            while next < events.len() && events[next].0 <= clock {
+                match events[next].1 {
+                    timeline::Event::Mode(m) => mode = m,
+                    timeline::Event::Dma => dma_end = events[next].0 + timeline::DMA_CLOCKS,
+                    _ => {}
+                }
+                next += 1;
+            }
+
+            buf[y * w + x] = if clock < dma_end && (x + y) % 2 == 0 {
+                0xff
+            } else {
+                MODE_SHADES[mode as usize]
+            };
+        }
+    }
+
+    for &(clock, event) in events {
+        let shade = match event {
+            timeline::Event::Requested(_) => 0xff,
+            timeline::Event::Serviced(_) => 0x00,
+            _ => continue,
+        };
+
+        let line = clock as usize / line_clocks;
+        let x = clock as usize % line_clocks * w / line_clocks;
+        let top = line * h / lines;
+        let bottom = ((line + 1) * h / lines).max(top + 1).min(h);
+
+        for y in top..bottom {
+            for px in x..(x + 2).min(w) {
+                buf[y * w + px] = shade;
+            }
+        }
+    }
+}
+
+/// Parses command line options.
+fn parse_args(args: Vec<String>) -> Options {
+    let mut rom_fname = None;
+    let mut boot_rom_fname = None;
+    let mut fast_boot = false;
+    let mut log = None;
+    let mut emulated_rtc = false;
+    let mut scaler = scaler::from_name("none").unwrap();
+    let mut lcd_off = lcd_off::LcdOff::White;
+    let mut input_fname = None;
+    let mut players = 1;
+    let mut four_player = None;
+    let mut link_latency = 0;
+    let mut link_disconnect_after = None;
+    let mut watch_vram = None;
+    let mut no_sprite_limit = false;
+    let mut skip_checksum = false;
+    let mut patch_fname = None;
+    let mut collision_colors = 0x08;
+    let mut overclock = 1;
+    let mut max_speed = 4.0;
+    let mut bank_stats = false;
+    let mut irq_latency = None;
+    let mut rewind_secs = 0;
+    let mut state_keeps_ram = false;
+    let mut blend_frames = false;
+    let mut no_stats = false;
+    let mut tracepoints = Vec::new();
+    let mut labels = Vec::new();
+    let mut seed = None;
+    let mut accuracy = accuracy::Accuracy::Balanced;
+    let mut trace_ram_exec = false;
+    let mut io_trace = Vec::new();
+    let mut cheats = Vec::new();
+    let mut audio_sync = false;
+    let mut record_audio = None;
+    let mut clock_out = None;
+    let mut hot_swaps = Vec::new();
+    let mut playlist_fname = None;
+    let mut idle_secs = 60;
+    let mut capture = None;
+    let mut load_state = None;
+    let mut turbo_until = None;
+    let mut sample_rate = apu::DEFAULT_SAMPLE_RATE;
+    let mut volume = 100;
+    let mut mono = false;
+    let mut disabled_ram = None;
+    let mut sram_init = None;
+    let mut camera_image = None;
+
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--boot-rom" => boot_rom_fname = Some(args.next().unwrap_or_else(|| usage())),
+            "--fast-boot" => fast_boot = true,
+            "--log" => log = Some(args.next().unwrap_or_else(|| usage())),
+            "--emulated-rtc" => emulated_rtc = true,
+            "--scaler" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                scaler = scaler::from_name(&name).unwrap_or_else(|| usage());
+            }
+            "--lcd-off" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                lcd_off = lcd_off::LcdOff::from_name(&name).unwrap_or_else(|| usage());
+            }
+            "--input" => input_fname = Some(args.next().unwrap_or_else(|| usage())),
+            "--players" => {
+                players = match args.next().and_then(|n| n.parse().ok()) {
+                    Some(n @ 1) | Some(n @ 2) | Some(n @ 4) => n,
+                    _ => usage(),
+                }
+            }
+            "--four-player" => {
+                four_player = match args.next().and_then(|n| n.parse().ok()) {
+                    Some(n @ 2..=4) => Some(n),
+                    _ => usage(),
+                }
+            }
+            "--link-latency" => {
+                link_latency = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| usage())
+            }
+            "--link-disconnect-after" => {
+                link_disconnect_after = Some(
+                    args.next()
+                        .and_then(|n| n.parse().ok())
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            "--watch-vram" => {
+                watch_vram = Some(
+                    args.next()
+                        .and_then(|r| parse_addr_range(&r))
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            "--no-sprite-limit" => no_sprite_limit = true,
+            "--skip-checksum" => skip_checksum = true,
+            "--patch" => patch_fname = Some(args.next().unwrap_or_else(|| usage())),
+            "--collisions" => {
+                collision_colors = args
+                    .next()
+                    .and_then(|spec| parse_color_nos(&spec))
+                    .unwrap_or_else(|| usage())
+            }
+            "--bank-stats" => bank_stats = true,
+            "--blend-frames" => blend_frames = true,
+            "--no-stats" => no_stats = true,
+            "--audio-sync" => audio_sync = true,
+            "--trace-ram-exec" => trace_ram_exec = true,
+            "--io-trace" => {
+                let spec = args.next().unwrap_or_else(|| usage());
+                match io_trace::IoTrace::parse(&spec) {
+                    Ok(regs) => io_trace = regs,
+                    Err(e) => {
+                        eprintln!("Invalid I/O trace {}: {}", spec, e);
+                        usage();
+                    }
+                }
+            }
+            "--seed" => {
+                seed = Some(
+                    args.next()
+                        .and_then(|n| n.parse().ok())
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            "--label" => {
+                let spec = args.next().unwrap_or_else(|| usage());
+                match labels::parse_line(&spec) {
+                    Ok(label) => labels.push(label),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        usage();
+                    }
+                }
+            }
+            "--trace" => {
+                let spec = args.next().unwrap_or_else(|| usage());
+                match tracepoint::Tracepoint::parse(&spec) {
+                    Ok(tp) => tracepoints.push(tp),
+                    Err(e) => {
+                        eprintln!("Invalid tracepoint {}: {}", spec, e);
+                        usage();
+                    }
+                }
+            }
+            "--volume" => {
+                volume = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n <= 100)
+                    .unwrap_or_else(|| usage())
+            }
+            "--mono" => mono = true,
+            "--sample-rate" => {
+                sample_rate = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|n| (apu::MIN_SAMPLE_RATE..=apu::MAX_SAMPLE_RATE).contains(n))
+                    .unwrap_or_else(|| usage())
+            }
+            "--capture-at" => {
+                let spec = args.next().unwrap_or_else(|| usage());
+                match capture::Capture::parse(&spec) {
+                    Ok(c) => capture = Some(c),
+                    Err(e) => {
+                        eprintln!("Invalid capture {}: {}", spec, e);
+                        usage();
+                    }
+                }
+            }
+            "--turbo-until" => {
+                let spec = args.next().unwrap_or_else(|| usage());
+                match turbo::MemCondition::parse(&spec) {
+                    Ok(c) => turbo_until = Some(c),
+                    Err(e) => {
+                        eprintln!("Invalid condition {}: {}", spec, e);
+                        usage();
+                    }
+                }
+            }
+            "--hot-swap" => {
+                let spec = args.next().unwrap_or_else(|| usage());
+                let mut parts = spec.splitn(2, ':');
+                let frame = parts
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| usage());
+                let rom = parts.next().unwrap_or_else(|| usage());
+                hot_swaps.push((frame, Some(rom.to_string()).filter(|r| !r.is_empty())));
+            }
+            "--playlist" => playlist_fname = Some(args.next().unwrap_or_else(|| usage())),
+            "--idle" => {
+                idle_secs = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| usage())
+            }
+            "--cheat" => {
+                let code = args.next().unwrap_or_else(|| usage());
+                if let Err(e) = cheats::Cheat::parse(&code) {
+                    eprintln!("{}", e);
+                    usage();
+                }
+                cheats.push(code);
+            }
+            "--disabled-ram" => {
+                disabled_ram = Some(
+                    args.next()
+                        .and_then(|name| catridge::DisabledRam::from_name(&name))
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            "--sram-init" => {
+                sram_init = Some(
+                    args.next()
+                        .and_then(|name| catridge::RamInit::from_name(&name))
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            "--camera" => camera_image = Some(args.next().unwrap_or_else(|| usage())),
+            "--load-state" => load_state = Some(args.next().unwrap_or_else(|| usage())),
+            "--record-audio" => record_audio = Some(args.next().unwrap_or_else(|| usage())),
+            "--clock-out" => clock_out = Some(args.next().unwrap_or_else(|| usage())),
+            "--rewind" => {
+                rewind_secs = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| usage())
+            }
+            "--state-keeps-ram" => state_keeps_ram = true,
+            "--irq-latency" => {
+                irq_latency = Some(
+                    args.next()
+                        .and_then(|n| n.parse().ok())
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            "--accuracy" => {
+                accuracy = args
+                    .next()
+                    .and_then(|name| accuracy::Accuracy::from_name(&name))
+                    .unwrap_or_else(|| usage())
+            }
+            "--max-speed" => {
+                max_speed = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|n| (1.0..=16.0).contains(n))
+                    .unwrap_or_else(|| usage())
+            }
+            "--overclock" => {
+                overclock = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|n| (1..=cpu::MAX_OVERCLOCK).contains(n))
+                    .unwrap_or_else(|| usage())
+            }
+            _ if arg.starts_with("--") => usage(),
+            _ => rom_fname = Some(arg),
+        }
+    }
+
+    // Replayed input must see the same timing as when it was recorded
+    if overclock > 1 && input_fname.is_some() {
+        usage();
     }
+
+    let playlist = playlist_fname.map(|fname| {
+        playlist::Playlist::new(&fname, idle_secs).unwrap_or_else(|e| {
+            eprintln!("Failed to load {}: {}", fname, e);
+            process::exit(1);
+        })
+    });
+    let rom_fname = match (rom_fname, &playlist) {
+        (None, Some(playlist)) => playlist.current().to_string(),
+        (Some(rom_fname), None) => rom_fname,
+        _ => usage(),
+    };
+
+    Options {
+        rom_fname,
+        boot_rom_fname,
+        fast_boot,
+        log,
+        emulated_rtc,
+        scaler,
+        lcd_off,
+        input_fname,
+        players,
+        four_player,
+        link_latency,
+        link_disconnect_after,
+        watch_vram,
+        no_sprite_limit,
+        skip_checksum,
+        patch_fname,
+        collision_colors,
+        overclock,
+        max_speed,
+        bank_stats,
+        irq_latency,
+        rewind_secs,
+        state_keeps_ram,
+        blend_frames,
+        no_stats,
+        tracepoints,
+        labels,
+        seed,
+        accuracy,
+        trace_ram_exec,
+        io_trace,
+        cheats,
+        audio_sync,
+        disabled_ram,
+        sram_init,
+        camera_image,
+        record_audio,
+        clock_out,
+        volume,
+        mono,
+        sample_rate,
+        capture,
+        load_state,
+        turbo_until,
+        hot_swaps,
+        playlist,
+    }
+}
+
+/// Parses a comma separated list of color numbers (e.g. `1,2,3`) into a mask
+/// with bit n for color n.
+fn parse_color_nos(spec: &str) -> Option<u8> {
+    spec.split(',')
+        .try_fold(0, |mask, color_no| match color_no.trim().parse() {
+            Ok(n @ 0..=3) => Some(mask | 1 << n),
+            _ => None,
+        })
 }
 
-/// Handles key down event.
-fn handle_keydown(cpu: &mut cpu::CPU, key: Keycode) {
-    translate_keycode(key).map(|k| cpu.mmu.joypad.keydown(k));
+/// Parses an address range in hex (e.g. `8000-87ff`).
+fn parse_addr_range(range: &str) -> Option<(u16, u16)> {
+    let mut bounds = range.splitn(2, '-');
+    let start = u16::from_str_radix(bounds.next()?, 16).ok()?;
+    let end = match bounds.next() {
+        Some(end) => u16::from_str_radix(end, 16).ok()?,
+        None => start,
+    };
+
+    Some((start, end))
 }
 
-/// Handles key up event.
-fn handle_keyup(cpu: &mut cpu::CPU, key: Keycode) {
-    translate_keycode(key).map(|k| cpu.mmu.joypad.keyup(k));
+/// Translates a `--log` filter into an env_logger filter, prefixing each
+/// subsystem with the crate name (e.g. `cpu=trace` becomes `gbr::cpu=trace`).
+fn log_filters(log: &str) -> String {
+    log.split(',')
+        .map(|directive| {
+            if directive.contains('=') {
+                format!("gbr::{}", directive)
+            } else {
+                directive.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
 }
 
-/// Returns ROM filename.
-fn rom_fname() -> String {
-    env::args().nth(1).unwrap()
+/// Initializes the logger from `RUST_LOG` and the `--log` option.
+fn init_logger(log: &Option<String>) {
+    let mut builder = env_logger::Builder::from_default_env();
+
+    if let Some(ref log) = *log {
+        builder.parse_filters(&log_filters(log));
+    }
+
+    builder.init();
 }
 
-/// Returns save filename for current ROM.
-fn save_fname() -> String {
-    let mut path_buf = PathBuf::from(rom_fname());
-    path_buf.set_extension("sav");
-    path_buf.to_str().unwrap().to_string()
+/// Returns true if a ROM should be downloaded.
+fn is_url(rom_fname: &str) -> bool {
+    rom_fname.starts_with("http://") || rom_fname.starts_with("https://")
+}
+
+/// Reads a ROM image from a file, a URL or stdin (`-`).
+fn read_rom(rom_fname: &str) -> Result<Vec<u8>, CatridgeError> {
+    let mut rom = Vec::new();
+
+    if rom_fname == "-" {
+        io::stdin().read_to_end(&mut rom)?;
+    } else if is_url(rom_fname) {
+        info!("Downloading ROM from: {}", rom_fname);
+
+        let output = process::Command::new("curl")
+            .arg("-fsSL")
+            .arg(rom_fname)
+            .output()
+            .map_err(|e| CatridgeError::Download(format!("failed to run curl: {}", e)))?;
+
+        if !output.status.success() {
+            return Err(CatridgeError::Download(format!("curl {}", output.status)));
+        }
+
+        rom = output.stdout;
+    } else {
+        File::open(rom_fname)?.read_to_end(&mut rom)?;
+    }
+
+    Ok(rom)
+}
+
+/// Applies the patch given with `--patch`, if any, to a ROM image.
+fn patch_rom(rom: Vec<u8>, opts: &Options) -> Result<Vec<u8>, CatridgeError> {
+    let fname = match opts.patch_fname {
+        Some(ref fname) => fname,
+        None => return Ok(rom),
+    };
+
+    fs::read(fname)
+        .map_err(|e| e.to_string())
+        .and_then(|patch| patch::apply(&rom, &patch))
+        .map_err(|e| CatridgeError::Patch(format!("{}: {}", fname, e)))
+}
+
+/// Reads a ROM image and loads it as a catridge configured as the options
+/// ask.
+fn load_catridge(rom_fname: &str, opts: &Options) -> Result<catridge::Catridge, CatridgeError> {
+    emulator_builder(read_rom(rom_fname)?, rom_fname, opts).build_catridge()
+}
+
+/// Swaps the catridge without a reset, or ejects it. The save of the original
+/// catridge is written when it is removed, and swapped in catridges are not
+/// saved.
+fn hot_swap(cpu: &mut cpu::CPU, rom_fname: Option<&String>, save_fname: &mut Option<String>) {
+    let catridge = match rom_fname {
+        Some(fname) => match read_rom(fname).and_then(catridge::Catridge::from_bytes) {
+            Ok(catridge) => Some(catridge),
+            Err(e) => return eprintln!("Failed to insert {}: {}", fname, e),
+        },
+        None => None,
+    };
+
+    if let Some(mut removed) = cpu.mmu.swap_catridge(catridge) {
+        if let Some(fname) = save_fname.take() {
+            removed.store_save(&mut storage::FileStorage, &fname);
+        }
+    }
+
+    match rom_fname {
+        Some(fname) => println!("Inserted {}", fname),
+        None => println!("Ejected catridge"),
+    }
+}
+
+/// Configures the core for a ROM image as the options ask, loading the
+/// battery save of `rom_fname` if it has one.
+fn emulator_builder(rom: Vec<u8>, rom_fname: &str, opts: &Options) -> emulator::EmulatorBuilder {
+    let mut builder = emulator::EmulatorBuilder::new(rom)
+        .storage(Box::new(storage::FileStorage))
+        .accuracy(opts.accuracy)
+        .emulated_rtc(opts.emulated_rtc)
+        .players(opts.players);
+
+    if opts.no_sprite_limit {
+        builder = builder.sprite_limit(false);
+    }
+    if let Some(latency) = opts.irq_latency {
+        builder = builder.irq_latency(latency);
+    }
+    if let Some(policy) = opts.disabled_ram {
+        builder = builder.disabled_ram(policy);
+    }
+    if let Some(ref fname) = opts.camera_image {
+        match png::load_gray(fname) {
+            Ok((pixels, width, height)) => builder = builder.camera_image(pixels, width, height),
+            Err(e) => eprintln!("Failed to load {}: {}", fname, e),
+        }
+    }
+    if let Some(seed) = opts.seed {
+        builder = builder.seed(seed);
+    }
+    if let Some(pattern) = opts.sram_init {
+        builder = builder.ram_init(pattern);
+    }
+    if let Some(fname) = companion_fname(rom_fname, "sav") {
+        builder = builder.save_file(&fname);
+    }
+
+    builder
+}
+
+/// Switches from one ROM of the playlist to another. The state of the game
+/// being left is kept in `games` and restored when its turn comes again, and
+/// ROMs played for the first time start from power-on with their battery save
+/// loaded. Returns false, staying on the current game, if the ROM fails to
+/// load.
+fn switch_game(
+    cpu: &mut cpu::CPU,
+    power_on: &cpu::CPU,
+    opts: &Options,
+    (prev_fname, rom_fname): (&str, &str),
+    save_fname: &mut Option<String>,
+    games: &mut HashMap<String, cpu::CPU>,
+) -> bool {
+    let catridge = if games.contains_key(rom_fname) {
+        None
+    } else {
+        match load_catridge(rom_fname, opts) {
+            Ok(catridge) => Some(catridge),
+            Err(e) => {
+                eprintln!("Failed to load {}: {}", rom_fname, e);
+                return false;
+            }
+        }
+    };
+
+    flush_save(cpu, save_fname);
+    games.insert(prev_fname.to_string(), cpu.clone());
+
+    *save_fname = companion_fname(rom_fname, "sav");
+
+    match games.remove(rom_fname) {
+        Some(state) => cpu.restore(state),
+        None => {
+            cpu.mmu.swap_catridge(catridge);
+            cpu.reset(power_on);
+        }
+    }
+
+    println!("Switched to {}", rom_fname);
+    true
+}
+
+/// Writes the battery save of the inserted catridge, if it has one.
+fn flush_save(cpu: &mut cpu::CPU, save_fname: &Option<String>) {
+    if let (Some(fname), Some(catridge)) = (save_fname, &mut cpu.mmu.catridge) {
+        catridge.store_save(&mut storage::FileStorage, fname);
+    }
+}
+
+/// Writes a resume state of the system, to continue where the host
+/// suspended or terminated us on the next start.
+fn store_resume_state(cpu: &cpu::CPU, rom_fname: &str) {
+    if let Some(fname) = companion_fname(rom_fname, "resume") {
+        match storage::FileStorage.store(&fname, &savestate::save(cpu)) {
+            Ok(()) => println!("Wrote resume state to {}", fname),
+            Err(e) => eprintln!("Failed to write {}: {}", fname, e),
+        }
+    }
+}
+
+/// Removes the resume state of a ROM, if there is one, once it is no longer
+/// where to continue from.
+fn remove_resume_state(rom_fname: &str) {
+    if let Some(fname) = companion_fname(rom_fname, "resume") {
+        match storage::FileStorage.remove(&fname) {
+            Err(ref e) if e.kind() != io::ErrorKind::NotFound => {
+                eprintln!("Failed to remove {}: {}", fname, e)
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Continues from the resume state of a ROM, if the last session left one,
+/// and removes it.
+fn load_resume_state(cpu: &mut cpu::CPU, rom_fname: &str) {
+    let fname = match companion_fname(rom_fname, "resume") {
+        Some(fname) => fname,
+        None => return,
+    };
+    let data = match storage::FileStorage.load(&fname) {
+        Ok(data) => data,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", fname, e);
+            return;
+        }
+    };
+
+    match savestate::load(cpu, &data) {
+        Ok(state) => {
+            cpu.restore(state);
+            println!("Resumed from {}", fname);
+        }
+        Err(e) => eprintln!("Failed to load {}: {}", fname, e),
+    }
+    remove_resume_state(rom_fname);
+}
+
+/// Restores a state written by `savestate::save` from a file.
+fn load_state(cpu: &mut cpu::CPU, fname: &str) -> Result<(), String> {
+    let data = storage::FileStorage
+        .load(fname)
+        .map_err(|e| e.to_string())?;
+
+    cpu.restore(savestate::load(cpu, &data)?);
+    Ok(())
+}
+
+/// Returns the filename of a file kept next to a ROM (e.g. the save file
+/// with extension `sav`). ROMs read from stdin have none, and files of
+/// downloaded ROMs are kept in the current directory.
+fn companion_fname(rom_fname: &str, extension: &str) -> Option<String> {
+    let mut path_buf = if rom_fname == "-" {
+        return None;
+    } else if is_url(rom_fname) {
+        PathBuf::from(rom_fname.rsplit('/').next().unwrap())
+    } else {
+        PathBuf::from(rom_fname)
+    };
+    path_buf.set_extension(extension);
+    Some(path_buf.to_str().unwrap().to_string())
 }
 
 fn main() {
-    env_logger::init();
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(|s| s.as_str()) {
+        Some("test") => {
+            init_logger(&None);
+            process::exit(smoke_test::main(&args[1..]));
+        }
+        Some("audio-test") => {
+            init_logger(&None);
+            process::exit(audio_test::main(&args[1..]));
+        }
+        Some("hash-frames") => {
+            init_logger(&None);
+            process::exit(hash_frames::main(&args[1..]));
+        }
+        #[cfg(feature = "test-roms")]
+        Some("fetch-test-roms") => process::exit(test_roms::main(&args[1..])),
+        Some("capabilities") => process::exit(capabilities::main(&args[1..])),
+        Some("doctor") => {
+            init_logger(&None);
+            process::exit(doctor::main(&args[1..]));
+        }
+        Some("info") => process::exit(info::main(&args[1..])),
+        Some("trace") => process::exit(compare::trace_main(&args[1..])),
+        Some("compare") => process::exit(compare::compare_main(&args[1..])),
+        Some("stats") => process::exit(play_stats::main(&args[1..])),
+        Some("png2tiles") => process::exit(tiles::png2tiles(&args[1..])),
+        Some("tiles2png") => process::exit(tiles::tiles2png(&args[1..])),
+        Some("run") => run(parse_args(args[1..].to_vec())),
+        _ => run(parse_args(args)),
+    }
+}
+
+/// Reports a ROM that failed to load and exits.
+fn exit_load_error(rom_fname: &str, e: CatridgeError) -> ! {
+    eprintln!("Failed to load {}: {}", rom_fname, e);
+    process::exit(1);
+}
+
+/// Runs a ROM in a window.
+fn run(mut opts: Options) {
+    init_logger(&opts.log);
+
+    // Before opening the window, so that a ROM that fails to load only
+    // prints why
+    let rom = read_rom(&opts.rom_fname)
+        .and_then(|rom| patch_rom(rom, &opts))
+        .unwrap_or_else(|e| exit_load_error(&opts.rom_fname, e));
+    let rom_key = play_stats::rom_key(&rom);
+    let mut builder = emulator_builder(rom, &opts.rom_fname, &opts);
+    if let Some(ref fname) = opts.boot_rom_fname {
+        let boot_rom = fs::read(fname)
+            .map_err(CatridgeError::BootRomRead)
+            .unwrap_or_else(|e| exit_load_error(&opts.rom_fname, e));
+        builder = builder.boot_rom(boot_rom, opts.fast_boot);
+    }
+    let mut cpu = builder
+        .build()
+        .unwrap_or_else(|e| exit_load_error(&opts.rom_fname, e));
+    let info = cpu.mmu.catridge.as_ref().unwrap().info().clone();
+
+    if !info.header_checksum_valid && !opts.skip_checksum {
+        eprintln!(
+            "Warning: the header checksum of {} is incorrect, so a boot ROM would lock up",
+            opts.rom_fname
+        );
+    }
+    let title = info.title;
+    let mut input_script = opts.input_fname.as_ref().map(|fname| {
+        input_script::InputScript::new(fname).unwrap_or_else(|e| {
+            eprintln!("Failed to load {}: {}", fname, e);
+            process::exit(1);
+        })
+    });
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
+    let controller_subsystem = sdl_context.game_controller().unwrap();
+
+    let factor = opts.scaler.factor();
+    let (width, height) = (160 * factor, 144 * factor);
+    let window_scale = if factor < 2 { 2 } else { 1 };
 
     let window = video_subsystem
-        .window("gbr", 320, 288)
+        .window(
+            "gbr",
+            (width * window_scale) as u32,
+            (height * window_scale) as u32,
+        )
         .position_centered()
         .build()
         .unwrap();
@@ -75,35 +1609,353 @@ fn main() {
     let texture_creator = canvas.texture_creator();
 
     let mut texture = texture_creator
-        .create_texture_streaming(PixelFormatEnum::RGB24, 160, 144)
+        .create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
         .unwrap();
+    let mut scaled = vec![0; width * height];
+    let mut help_page = None;
+    let mut show_palettes = false;
+    let mut show_timeline = false;
+    let mut show_telemetry = false;
+    let mut show_input_display = false;
+    let mut show_collisions = false;
+    let mut lcd_off_filter = lcd_off::LcdOffFilter::new(opts.lcd_off);
     let mut event_pump = sdl_context.event_pump().unwrap();
 
-    let mut cpu = cpu::CPU::new(&rom_fname());
+    let audio_subsystem = sdl_context.audio().unwrap();
+    let audio_spec = AudioSpecDesired {
+        freq: Some(opts.sample_rate as i32),
+        channels: Some(2),
+        samples: None,
+    };
+    let audio_queue = audio_subsystem
+        .open_queue::<f32, _>(None, &audio_spec)
+        .unwrap();
+    audio_queue.resume();
 
-    cpu.mmu.catridge.read_save_file(&save_fname());
+    // The device may not support the requested rate
+    cpu.mmu.apu.set_sample_rate(audio_queue.spec().freq as u32);
+    let sample_rate = cpu.mmu.apu.sample_rate();
+    cpu.mmu.apu.set_volume(opts.volume as f32 / 100.0);
+    cpu.mmu.apu.set_mono(opts.mono);
+    let audio_sync_bytes = sample_rate / 60 * 2 * 4 * AUDIO_SYNC_FRAMES;
+    let mut time_stretch = time_stretch::TimeStretch::new(sample_rate);
+    let mut audio_fade = audio_fade::AudioFade::new(sample_rate);
+
+    // Play time is not attributed to the ROMs of a playlist
+    let mut play_stats = if opts.no_stats || opts.playlist.is_some() {
+        None
+    } else {
+        Some(play_stats::PlayStats::load())
+    };
+    if let Some(ref mut stats) = play_stats {
+        stats.start(&rom_key, &title);
+        // Count the launch even if the emulator crashes later
+        if let Err(e) = stats.save() {
+            warn!("Failed to save play statistics: {}", e);
+        }
+    }
+    let started = time::Instant::now();
+
+    if !title.is_empty() {
+        canvas
+            .window_mut()
+            .set_title(&format!("gbr - {}", title))
+            .unwrap();
+    }
+
+    cpu.mmu.serial.set_latency(opts.link_latency);
+    cpu.mmu
+        .serial
+        .set_disconnect_after(opts.link_disconnect_after);
+    if let Some((start, end)) = opts.watch_vram {
+        cpu.mmu.ppu.watch_vram(start, end);
+    }
+    cpu.mmu.ppu.set_frame_blending(opts.blend_frames);
+    cpu.set_overclock(opts.overclock);
+    if opts.bank_stats {
+        cpu.enable_bank_stats();
+    }
+    for tp in opts.tracepoints.iter() {
+        cpu.add_tracepoint(tp.clone());
+    }
+    cpu.set_trace_ram_exec(opts.trace_ram_exec);
+    cpu.mmu.io_trace.set_regs(opts.io_trace.clone());
+    for code in opts.cheats.iter() {
+        cpu.mmu.cheats.add(code).unwrap();
+    }
+
+    let mut save_fname = companion_fname(&opts.rom_fname, "sav");
+
+    let mut labels = match companion_fname(&opts.rom_fname, "labels") {
+        Some(ref fname) => labels::Labels::load(fname),
+        None => labels::Labels::empty(),
+    };
+    if !opts.labels.is_empty() {
+        for (start, end, name) in opts.labels.drain(..) {
+            labels.add(start, end, name);
+        }
+        if let Err(e) = labels.save() {
+            warn!("Failed to save labels: {}", e);
+        }
+    }
+
+    let mut keyboard = Keyboard::new();
+    let mut gamepads = Gamepads::new(opts.players.max(opts.four_player.unwrap_or(1)));
+    let mut tilt = Tilt::new();
+    let mut frame: u64 = 0;
+    let mut rewind = rewind::Rewind::new(opts.rewind_secs * 60);
+    let mut rewinding = false;
+    let mut fast_forward = false;
+    let mut paused = false;
+    let mut focused = true;
+    // Emulation was halted by a pause, focus loss or the help screen
+    let mut halted = false;
+    let mut turbo = opts.turbo_until.take().map(turbo::TurboUntil::new);
+    if let Some(ref mut turbo) = turbo {
+        turbo.arm(&cpu.mmu);
+        fast_forward = true;
+    }
+    // Speed multiplier set by the right trigger of a gamepad
+    let mut speed: f32 = 1.0;
+    // Gamepads only control the speed, but must be open to send events
+    let mut controllers = Vec::new();
+    let mut states = HashMap::new();
+    let mut undo_states = Vec::new();
+    let mut captured = false;
+    // Message shown on screen and the frame to hide it at
+    let mut message: Option<(String, u64)> = None;
+    let mut recording = opts
+        .record_audio
+        .as_ref()
+        .and_then(|fname| start_recording(fname, sample_rate));
+    if let Some(ref fname) = opts.clock_out {
+        cpu.mmu.set_vblank_listener(Some(start_clock_out(fname)));
+    }
+    let power_on = cpu.clone();
+    load_resume_state(&mut cpu, &opts.rom_fname);
+    if let Some(ref fname) = opts.load_state {
+        if let Err(e) = load_state(&mut cpu, fname) {
+            eprintln!("Failed to load {}: {}", fname, e);
+            process::exit(1);
+        }
+        println!("Loaded state from {}", fname);
+    }
+    // Adapter and the Game Boys of players 2 and up, running the same game.
+    // Only player 1 is shown, heard and saved.
+    let mut linked = opts.four_player.map(|n| {
+        let mut peer = power_on.clone();
+        peer.mmu.set_vblank_listener(None);
+        (four_player::FourPlayerAdapter::new(), vec![peer; n - 1])
+    });
+    let mut playlist = opts.playlist.take();
+    let mut rom_fname = opts.rom_fname.clone();
+    // States of the ROMs of the playlist not being played
+    let mut games = HashMap::new();
 
     'running: loop {
         let now = time::Instant::now();
-        let mut elapsed_tick: u32 = 0;
 
-        // Emulate one frame
-        while elapsed_tick < 456 * (144 + 10) {
-            elapsed_tick += cpu.step() as u32;
+        let was_halted = halted;
+        halted = paused || !focused || help_page.is_some();
+        if halted && !was_halted {
+            audio_queue.queue(&audio_fade.fade_out());
+        }
+
+        if halted {
+            // Nothing is emulated, but the screen is still drawn
+        } else if rewinding {
+            // Step back one frame per frame while the key is held
+            if rewind.step_back(&mut cpu) {
+                frame -= 1;
+            }
+        } else {
+            rewind.push(&cpu);
+
+            let mut input = keyboard.poll(frame).merge(&gamepads.poll(frame));
+            if let Some(ref mut script) = input_script {
+                input = input.merge(&script.poll(frame));
+            }
+            cpu.mmu.joypad.set_input(&input);
+            if let Some((_, ref mut peers)) = linked {
+                for (i, peer) in peers.iter_mut().enumerate() {
+                    let mut peer_input = InputState::default();
+                    peer_input.held[0] = input.held[i + 1];
+                    peer.mmu.joypad.set_input(&peer_input);
+                }
+            }
+            if let Some(ref mut catridge) = cpu.mmu.catridge {
+                let (x, y) = tilt.get();
+                catridge.set_tilt(x, y);
+            }
+            // Also after loading a state recorded without the timeline
+            cpu.mmu.timeline.set_enabled(show_timeline);
+            cpu.mmu.ppu.set_collision_colors(if show_collisions {
+                opts.collision_colors
+            } else {
+                0
+            });
+            frame += 1;
+
+            for swap in opts.hot_swaps.iter().filter(|s| s.0 == frame) {
+                hot_swap(&mut cpu, swap.1.as_ref(), &mut save_fname);
+            }
+
+            let active = input.held.iter().any(|&held| held != 0);
+            let next = playlist
+                .as_mut()
+                .and_then(|playlist| playlist.advance(active))
+                .map(str::to_string);
+            if let Some(next) = next.filter(|next| *next != rom_fname) {
+                let switched = switch_game(
+                    &mut cpu,
+                    &power_on,
+                    &opts,
+                    (&rom_fname, &next),
+                    &mut save_fname,
+                    &mut games,
+                );
+                if switched {
+                    rom_fname = next;
+
+                    // States of another game would be saved to the wrong file
+                    states.clear();
+                    undo_states.clear();
+                    rewind = rewind::Rewind::new(opts.rewind_secs * 60);
+
+                    if let Some(ref catridge) = cpu.mmu.catridge {
+                        canvas
+                            .window_mut()
+                            .set_title(&format!("gbr - {}", catridge.info().title))
+                            .unwrap();
+                    }
+                }
+            }
+
+            // Emulate one frame
+            match opts.capture {
+                Some(ref capture) if !captured => {
+                    let mut snapshot = None;
+                    cpu.run_for_cycles_traced(cpu::CYCLES_PER_FRAME, |cpu| {
+                        if snapshot.is_none() && cpu.pc() == capture.pc {
+                            snapshot = Some(cpu.clone());
+                        }
+                    });
+
+                    if let Some(snapshot) = snapshot {
+                        captured = true;
+                        if take_capture(capture, &snapshot, frame) {
+                            break 'running;
+                        }
+                    }
+                }
+                _ => match linked {
+                    Some((ref mut adapter, ref mut peers)) => {
+                        let mut cpus: Vec<_> =
+                            iter::once(&mut cpu).chain(peers.iter_mut()).collect();
+                        adapter.run_for_cycles(&mut cpus, cpu::CYCLES_PER_FRAME);
+                        for peer in peers.iter_mut() {
+                            peer.mmu.apu.take_samples();
+                        }
+                    }
+                    None => cpu.run_for_cycles(cpu::CYCLES_PER_FRAME),
+                },
+            }
+
+            if let Some(ref mut turbo) = turbo {
+                if turbo.check(&cpu.mmu) {
+                    let text = format!("Reached {}", turbo.condition());
+                    println!("{}", text);
+                    message = Some((text, frame + MESSAGE_FRAMES));
+                    fast_forward = false;
+                }
+            }
+        }
+
+        let samples = cpu.mmu.apu.take_samples();
+        if !fast_forward {
+            let mut output = time_stretch.process(&samples, speed);
+            audio_fade.process(&mut output);
+            audio_queue.queue(&output);
+        }
+
+        // Recorded also while fast forwarding so that the recording plays at
+        // the normal speed
+        if let Some(ref mut wav) = recording {
+            if let Err(e) = wav.write(&samples) {
+                eprintln!("Failed to write {}: {}", wav.fname(), e);
+                recording = None;
+            }
+        }
+
+        for write in cpu.mmu.ppu.take_vram_writes() {
+            println!("{}", write);
+            if let Some(name) = labels.describe(write.addr) {
+                println!("  label: {}", name);
+            }
+        }
+
+        for (pc, msg) in cpu.take_trace_log() {
+            match labels.describe(pc) {
+                Some(name) => println!("trace 0x{:04x} ({}): {}", pc, name, msg),
+                None => println!("trace 0x{:04x}: {}", pc, msg),
+            }
+        }
+
+        opts.scaler
+            .scale(lcd_off_filter.filter(&cpu.mmu.ppu), 160, 144, &mut scaled);
+
+        if show_timeline {
+            draw_timeline(&mut scaled, width, &cpu.mmu.timeline);
         }
 
+        if show_palettes {
+            draw_palettes(&mut scaled, width, factor, &cpu.mmu.ppu);
+        }
+
+        if show_input_display {
+            draw_input_display(&mut scaled, width, factor, cpu.mmu.joypad.held(0));
+        }
+
+        if show_telemetry {
+            draw_telemetry(&mut scaled, width, factor, cpu.mmu.telemetry.last_frame());
+        }
+
+        if let Some((ref text, until)) = message {
+            if frame < until {
+                draw_message(&mut scaled, width, factor, text);
+            }
+        }
+
+        if let Some(page) = help_page {
+            draw_help(&mut scaled, width, factor, page);
+        }
+
+        // Not over screens replacing the game
+        let collisions = if show_collisions && !show_timeline && help_page.is_none() {
+            Some(cpu.mmu.ppu.collisions())
+        } else {
+            None
+        };
+
         texture
             .with_lock(None, |buf: &mut [u8], pitch: usize| {
-                let fb = cpu.mmu.ppu.frame_buffer();
-
-                for y in 0..144 {
-                    for x in 0..160 {
+                for y in 0..height {
+                    for x in 0..width {
                         let offset = y * pitch + x * 3;
-                        let color = fb[y * 160 + x];
+                        let color = scaled[y * width + x];
 
                         buf[offset] = color;
                         buf[offset + 1] = color;
                         buf[offset + 2] = color;
+
+                        // Tint collisions red
+                        if let Some(collisions) = collisions {
+                            if collisions[(y / factor) * 160 + x / factor] {
+                                buf[offset] = 0x80 | color >> 1;
+                                buf[offset + 1] = color >> 1;
+                                buf[offset + 2] = color >> 1;
+                            }
+                        }
                     }
                 }
             })
@@ -114,31 +1966,242 @@ fn main() {
         canvas.present();
 
         for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
+            let (action, pressed) = match event {
+                // SDL turns SIGINT and SIGTERM into quit events
+                Event::Quit { .. } => (Action::Quit, true),
+                // The host may suspend or kill us without further notice
+                // (e.g. on handheld devices), so write the save and a resume
+                // state while we can
+                Event::AppTerminating { .. } => {
+                    store_resume_state(&cpu, &rom_fname);
+                    (Action::Quit, true)
+                }
+                Event::AppWillEnterBackground { .. } | Event::AppLowMemory { .. } => {
+                    flush_save(&mut cpu, &save_fname);
+                    store_resume_state(&cpu, &rom_fname);
+                    continue;
+                }
+                // Still running, so a later normal quit must not resume
+                Event::AppDidEnterForeground { .. } => {
+                    remove_resume_state(&rom_fname);
+                    continue;
+                }
+                Event::Window { win_event, .. } => {
+                    match win_event {
+                        WindowEvent::FocusLost => focused = false,
+                        WindowEvent::FocusGained => focused = true,
+                        _ => (),
+                    }
+                    continue;
+                }
+                // SDL also reports gamepads connected at startup as added
+                Event::ControllerDeviceAdded { which, .. } => {
+                    match controller_subsystem.open(which) {
+                        Ok(controller) => {
+                            println!("Connected {}", controller.name());
+                            controllers.push(controller);
+                        }
+                        Err(e) => warn!("Failed to open gamepad {}: {}", which, e),
+                    }
+                    continue;
+                }
+                Event::ControllerAxisMotion {
+                    axis: Axis::TriggerRight,
+                    value,
                     ..
-                } => break 'running,
+                } => {
+                    let new_speed = trigger_speed(value, opts.max_speed);
+                    if (new_speed * 10.0).round() != (speed * 10.0).round() {
+                        message =
+                            Some((format!("Speed {:.1}x", new_speed), frame + MESSAGE_FRAMES));
+                    }
+                    speed = new_speed;
+                    continue;
+                }
+                Event::ControllerAxisMotion { axis, value, .. } => {
+                    tilt.stick(axis, value);
+                    continue;
+                }
+                Event::ControllerButtonDown { which, button, .. }
+                | Event::ControllerButtonUp { which, button, .. } => {
+                    let pressed = matches!(event, Event::ControllerButtonDown { .. });
+                    if let Some(nth) = controllers.iter().position(|c| c.instance_id() == which) {
+                        gamepads.button(nth, button, pressed);
+                    }
+                    continue;
+                }
                 Event::KeyDown {
                     keycode: Some(keycode),
+                    keymod,
+                    repeat,
                     ..
-                } => handle_keydown(&mut cpu, keycode),
+                } => match find_hotkey(keycode, keymod) {
+                    Some(action) if !repeat => (action, true),
+                    Some(_) => continue,
+                    None => {
+                        if !tilt.key(keycode, true) {
+                            keyboard.keydown(keycode);
+                        }
+                        continue;
+                    }
+                },
                 Event::KeyUp {
                     keycode: Some(keycode),
                     ..
-                } => handle_keyup(&mut cpu, keycode),
-                _ => (),
+                } => match find_hotkey(keycode, Mod::NOMOD) {
+                    Some(action) if action.is_held() => (action, false),
+                    Some(_) => continue,
+                    None => {
+                        if !tilt.key(keycode, false) {
+                            keyboard.keyup(keycode);
+                        }
+                        continue;
+                    }
+                },
+                _ => continue,
+            };
+
+            match action {
+                Action::ToggleHelp => {
+                    help_page = match help_page {
+                        None => Some(0),
+                        Some(page) if page + 1 < help_pages() => Some(page + 1),
+                        Some(_) => None,
+                    }
+                }
+                Action::MuteChannel(channel) => mute_channel(&mut cpu.mmu.apu, channel),
+                Action::VolumeUp | Action::VolumeDown => {
+                    let text = change_volume(&mut cpu.mmu.apu, action == Action::VolumeUp);
+                    println!("{}", text);
+                    message = Some((text, frame + MESSAGE_FRAMES));
+                }
+                Action::ToggleCheats => {
+                    let text = toggle_cheats(&mut cpu.mmu.cheats);
+                    println!("{}", text);
+                    message = Some((text, frame + MESSAGE_FRAMES));
+                }
+                Action::TogglePause => {
+                    paused = !paused;
+                    println!("{}", if paused { "Paused" } else { "Resumed" });
+                    message = if paused {
+                        Some((String::from("Paused"), frame + MESSAGE_FRAMES))
+                    } else {
+                        None
+                    };
+                }
+                Action::TogglePalettes => show_palettes = !show_palettes,
+                Action::ToggleTimeline => show_timeline = !show_timeline,
+                Action::ToggleTelemetry => show_telemetry = !show_telemetry,
+                Action::ToggleInputDisplay => show_input_display = !show_input_display,
+                Action::ToggleCollisions => show_collisions = !show_collisions,
+                Action::ToggleLayer(layer) => toggle_layer(&mut cpu.mmu.ppu, layer),
+                Action::DumpPartialFrame => dump_partial_frame(&cpu.mmu.ppu, frame),
+                Action::Screenshot => screenshot(&cpu.mmu.ppu, frame),
+                Action::ToggleAudioRecording => match recording.take() {
+                    Some(wav) => finish_recording(wav),
+                    None => {
+                        recording = start_recording(&format!("gbr-audio{}.wav", frame), sample_rate)
+                    }
+                },
+                Action::Rewind => rewinding = pressed,
+                Action::SaveState(slot) => {
+                    states.insert(slot, (frame, cpu.clone()));
+                    println!("Saved state {}", slot);
+                }
+                Action::LoadState(slot) => match states.get(&slot) {
+                    Some(&(state_frame, ref state)) => {
+                        // Keep the current state in case the wrong slot was loaded
+                        if undo_states.len() >= UNDO_STATES {
+                            undo_states.remove(0);
+                        }
+                        undo_states.push((frame, cpu.clone()));
+
+                        if opts.state_keeps_ram {
+                            cpu.restore_keeping_ram(state.clone());
+                        } else {
+                            cpu.restore(state.clone());
+                        }
+                        frame = state_frame;
+                        println!("Loaded state {}", slot);
+                    }
+                    None => println!("State {} is empty", slot),
+                },
+                Action::UndoLoadState => match undo_states.pop() {
+                    Some((undo_frame, state)) => {
+                        if opts.state_keeps_ram {
+                            cpu.restore_keeping_ram(state);
+                        } else {
+                            cpu.restore(state);
+                        }
+                        frame = undo_frame;
+                        println!("Undid load state");
+                    }
+                    None => println!("No load state to undo"),
+                },
+                Action::ToggleFastForward => {
+                    fast_forward = !fast_forward;
+                    println!("Fast forward {}", if fast_forward { "on" } else { "off" });
+                }
+                Action::TurboUntil => match turbo {
+                    Some(ref mut turbo) => {
+                        turbo.arm(&cpu.mmu);
+                        fast_forward = true;
+                        let text = format!("Fast forward until {}", turbo.condition());
+                        println!("{}", text);
+                        message = Some((text, frame + MESSAGE_FRAMES));
+                    }
+                    None => println!("No --turbo-until condition given"),
+                },
+                Action::Reset => {
+                    cpu.reset(&power_on);
+                    if let Some((ref mut adapter, ref mut peers)) = linked {
+                        *adapter = four_player::FourPlayerAdapter::new();
+                        for peer in peers.iter_mut() {
+                            peer.reset(&power_on);
+                            peer.mmu.set_vblank_listener(None);
+                        }
+                    }
+                    println!("Reset");
+                }
+                Action::Quit => break 'running,
             }
         }
 
-        let wait = time::Duration::from_micros(1000000 / 60);
-        let elapsed = now.elapsed();
+        if fast_forward && !halted {
+            continue;
+        }
 
-        if wait > elapsed {
-            thread::sleep(wait - elapsed);
+        // Rewinding produces no audio, and time-stretched audio lags behind the
+        // speed changes, so they are always paced by the timer
+        if opts.audio_sync && !rewinding && !halted && speed == 1.0 {
+            // Wait until the audio device has consumed all but a few frames
+            while audio_queue.size() > audio_sync_bytes {
+                thread::sleep(time::Duration::from_millis(1));
+            }
+        } else {
+            let wait = time::Duration::from_micros((1000000.0 / 60.0 / speed) as u64);
+            let elapsed = now.elapsed();
+
+            if wait > elapsed {
+                thread::sleep(wait - elapsed);
+            }
         }
     }
 
-    cpu.mmu.catridge.write_save_file(&save_fname());
+    if let Some(wav) = recording {
+        finish_recording(wav);
+    }
+
+    flush_save(&mut cpu, &save_fname);
+
+    if let Some(ref mut stats) = play_stats {
+        stats.add_playtime(&rom_key, started.elapsed().as_secs());
+        if let Err(e) = stats.save() {
+            warn!("Failed to save play statistics: {}", e);
+        }
+    }
+
+    if let Some(stats) = cpu.bank_stats() {
+        println!("{}", stats);
+    }
 }
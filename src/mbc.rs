@@ -0,0 +1,531 @@
+use std::sync::Arc;
+
+use camera::Camera;
+use catridge_info::CatridgeInfo;
+use huc3::Huc3;
+use mbc7::Mbc7;
+use mmm01::Mmm01;
+use rtc::Rtc;
+use savestate::{Savestate, StateBuffer};
+use unlicensed::{self, Unlicensed, WisdomTree};
+
+/// ROM and RAM of a catridge, banked by its mapper.
+#[derive(Clone)]
+pub struct Memory {
+    /// ROM, shared between snapshots
+    pub rom: Arc<Vec<u8>>,
+    /// RAM (or EEPROM) backed up by the battery
+    pub ram: Vec<u8>,
+}
+
+impl Memory {
+    /// Returns the number of 16KB ROM banks.
+    pub fn num_rom_banks(&self) -> usize {
+        self.rom.len() / 0x4000
+    }
+
+    /// Reads from a 16KB ROM bank. Bank numbers wrap around the ROM size,
+    /// like the unconnected upper address lines.
+    pub fn read_rom(&self, bank_no: usize, addr: u16) -> u8 {
+        let offset = (16 * 1024) * (bank_no & (self.num_rom_banks() - 1));
+        self.rom[(addr & 0x3fff) as usize + offset]
+    }
+
+    /// Reads from an 8KB RAM bank. Reads beyond the RAM return 0xff.
+    pub fn read_ram(&self, bank_no: usize, addr: u16) -> u8 {
+        let offset = (8 * 1024) * bank_no;
+        *self
+            .ram
+            .get((addr & 0x1fff) as usize + offset)
+            .unwrap_or(&0xff)
+    }
+
+    /// Writes to an 8KB RAM bank. Writes beyond the RAM are ignored.
+    pub fn write_ram(&mut self, bank_no: usize, addr: u16, val: u8) {
+        let offset = (8 * 1024) * bank_no;
+        if let Some(b) = self.ram.get_mut((addr & 0x1fff) as usize + offset) {
+            *b = val;
+        }
+    }
+}
+
+/// Memory bank controller: the registers of a catridge type mapped to
+/// 0x0000-0x7fff and 0xa000-0xbfff, and any hardware besides ROM and RAM.
+pub trait Mbc: Send + Savestate {
+    /// Handles a write to 0x0000-0x7fff or 0xa000-0xbfff.
+    fn write(&mut self, mem: &mut Memory, addr: u16, val: u8);
+
+    /// Handles a read from 0x0000-0x7fff or 0xa000-0xbfff. Returns `None`
+    /// for reads from disabled RAM, whose value depends on the catridge.
+    fn read(&self, mem: &Memory, addr: u16) -> Option<u8>;
+
+    /// Returns the ROM bank mapped to 0x4000-0x7fff, before wrapping around
+    /// the ROM size.
+    fn rom_bank_no(&self) -> usize;
+
+    /// Resets the registers. RAM and the RTC are kept.
+    fn reset(&mut self);
+
+    /// Progresses the hardware on the catridge for a given number of ticks.
+    fn update(&mut self, _mem: &mut Memory, _tick: u8) {}
+
+    /// Returns the RTC, if the mapper has one.
+    fn rtc(&mut self) -> Option<&mut Rtc> {
+        None
+    }
+
+    /// Sets the tilt sensed by an accelerometer in g.
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+
+    /// Sets the image seen by a camera from grayscale pixels.
+    fn set_camera_image(&mut self, _pixels: &[u8], _width: usize, _height: usize) {}
+
+    /// Returns a boxed copy, to clone catridges.
+    fn box_clone(&self) -> Box<dyn Mbc>;
+}
+
+impl Clone for Box<dyn Mbc> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// Creates the mapper of a ROM, whose catridge type must be supported (see
+/// `capabilities::supports_mapper`).
+pub fn from_rom(rom: &[u8], info: &CatridgeInfo) -> Box<dyn Mbc> {
+    if let Some(mapper) = unlicensed::detect(rom, info) {
+        return match mapper {
+            Unlicensed::WisdomTree => Box::new(WisdomTree::new()),
+        };
+    }
+
+    match info.mbc_type {
+        0x00 | 0x08 | 0x09 => Box::new(NoMbc),
+        0x01..=0x03 => Box::new(Mbc1::new()),
+        0x05 | 0x06 => Box::new(Mbc2::new()),
+        0x0b..=0x0d => Box::new(Mmm01::new()),
+        0x0f..=0x13 => Box::new(Mbc3::new(info.has_rtc)),
+        0x19..=0x1e => Box::new(Mbc5::new(info.mbc_type >= 0x1c)),
+        0x22 => Box::new(Mbc7::new()),
+        0xfc => Box::new(Camera::new()),
+        0xfe => Box::new(Huc3::new()),
+        t => unreachable!("Unsupported catridge type: 0x{:02x}", t),
+    }
+}
+
+/// ROM only catridges, optionally with up to 8KB of RAM wired directly to
+/// 0xa000-0xbfff. Writes to 0x0000-0x7fff are ignored.
+#[derive(Clone)]
+pub struct NoMbc;
+
+impl Mbc for NoMbc {
+    fn write(&mut self, mem: &mut Memory, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x7fff => (),
+            // RAM, always enabled
+            0xa000..=0xbfff => mem.write_ram(0, addr, val),
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn read(&self, mem: &Memory, addr: u16) -> Option<u8> {
+        match addr {
+            // ROM bank 00
+            0x0000..=0x3fff => Some(mem.read_rom(0, addr)),
+            // ROM bank 01
+            0x4000..=0x7fff => Some(mem.read_rom(1, addr)),
+            // Nothing drives the data bus without RAM
+            0xa000..=0xbfff if mem.ram.is_empty() => None,
+            // RAM
+            0xa000..=0xbfff => Some(mem.read_ram(0, addr)),
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn rom_bank_no(&self) -> usize {
+        1
+    }
+
+    fn reset(&mut self) {}
+
+    fn box_clone(&self) -> Box<dyn Mbc> {
+        Box::new(self.clone())
+    }
+}
+
+/// MBC1.
+#[derive(Clone)]
+pub struct Mbc1 {
+    /// RAM enable
+    ram_enable: bool,
+    /// ROM bank number (lower 5 bits)
+    bank_no_lower: u8,
+    /// RAM bank number or ROM bank number (upper 2 bits)
+    bank_no_upper: u8,
+    /// ROM/RAM mode select
+    mode: bool,
+}
+
+impl Mbc1 {
+    /// Creates a new `Mbc1`.
+    pub fn new() -> Self {
+        Mbc1 {
+            ram_enable: false,
+            bank_no_lower: 0,
+            bank_no_upper: 0,
+            mode: false,
+        }
+    }
+
+    /// Returns the RAM bank mapped to 0xa000-0xbfff.
+    fn ram_bank_no(&self) -> usize {
+        if self.mode {
+            self.bank_no_upper as usize
+        } else {
+            0
+        }
+    }
+}
+
+impl Mbc for Mbc1 {
+    fn write(&mut self, mem: &mut Memory, addr: u16, val: u8) {
+        match addr {
+            // RAM enable
+            0x0000..=0x1fff => self.ram_enable = val & 0x0f == 0x0a,
+            // ROM bank number (lower 5 bits)
+            0x2000..=0x3fff => self.bank_no_lower = val & 0x1f,
+            // RAM bank number or ROM bank number (upper 2 bits)
+            0x4000..=0x5fff => self.bank_no_upper = val & 0x03,
+            // ROM/RAM mode select
+            0x6000..=0x7fff => self.mode = val & 0x01 > 0,
+            // RAM bank 00-03
+            0xa000..=0xbfff => {
+                if self.ram_enable {
+                    mem.write_ram(self.ram_bank_no(), addr, val);
+                }
+            }
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn read(&self, mem: &Memory, addr: u16) -> Option<u8> {
+        match addr {
+            // ROM bank 00
+            0x0000..=0x3fff => Some(mem.read_rom(0, addr)),
+            // ROM bank 01-7f
+            0x4000..=0x7fff => Some(mem.read_rom(self.rom_bank_no(), addr)),
+            // RAM bank 00-03
+            0xa000..=0xbfff if self.ram_enable => Some(mem.read_ram(self.ram_bank_no(), addr)),
+            0xa000..=0xbfff => None,
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn rom_bank_no(&self) -> usize {
+        let bank_no = if self.mode {
+            self.bank_no_lower
+        } else {
+            self.bank_no_upper << 5 | self.bank_no_lower
+        };
+
+        match bank_no {
+            0 | 0x20 | 0x40 | 0x60 => bank_no as usize + 1,
+            _ => bank_no as usize,
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Mbc1::new();
+    }
+
+    fn box_clone(&self) -> Box<dyn Mbc> {
+        Box::new(self.clone())
+    }
+}
+
+/// MBC2, with 512 4-bit cells of RAM built in. Its registers share
+/// 0x0000-0x3fff and are told apart by bit 8 of the address.
+#[derive(Clone)]
+pub struct Mbc2 {
+    /// RAM enable
+    ram_enable: bool,
+    /// ROM bank number
+    rom_bank_no: u8,
+}
+
+impl Mbc2 {
+    /// Creates a new `Mbc2`.
+    pub fn new() -> Self {
+        Mbc2 {
+            ram_enable: false,
+            rom_bank_no: 1,
+        }
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn write(&mut self, mem: &mut Memory, addr: u16, val: u8) {
+        match addr {
+            // RAM enable
+            0x0000..=0x3fff if addr & 0x0100 == 0 => self.ram_enable = val & 0x0f == 0x0a,
+            // ROM bank number
+            0x0000..=0x3fff => self.rom_bank_no = val & 0x0f,
+            0x4000..=0x7fff => (),
+            // RAM, mirrored across 0xa000-0xbfff. Only the lower nibble is
+            // stored, the upper one reads as 1s.
+            0xa000..=0xbfff => {
+                if self.ram_enable {
+                    mem.write_ram(0, addr & 0x01ff, val | 0xf0);
+                }
+            }
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn read(&self, mem: &Memory, addr: u16) -> Option<u8> {
+        match addr {
+            // ROM bank 00
+            0x0000..=0x3fff => Some(mem.read_rom(0, addr)),
+            // ROM bank 01-0f
+            0x4000..=0x7fff => Some(mem.read_rom(self.rom_bank_no(), addr)),
+            // RAM
+            0xa000..=0xbfff if self.ram_enable => Some(mem.read_ram(0, addr & 0x01ff) | 0xf0),
+            0xa000..=0xbfff => None,
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn rom_bank_no(&self) -> usize {
+        match self.rom_bank_no {
+            0 => 1,
+            bank_no => bank_no as usize,
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Mbc2::new();
+    }
+
+    fn box_clone(&self) -> Box<dyn Mbc> {
+        Box::new(self.clone())
+    }
+}
+
+/// MBC3, with an RTC on some catridges.
+#[derive(Clone)]
+pub struct Mbc3 {
+    /// RAM and RTC enable
+    ram_enable: bool,
+    /// ROM bank number
+    rom_bank_no: u8,
+    /// RAM bank number or RTC register select
+    ram_bank_no: u8,
+    /// Real Time Clock
+    rtc: Rtc,
+    /// The RTC is present and keeps time
+    has_rtc: bool,
+}
+
+impl Mbc3 {
+    /// Creates a new `Mbc3`.
+    pub fn new(has_rtc: bool) -> Self {
+        Mbc3 {
+            ram_enable: false,
+            rom_bank_no: 0,
+            ram_bank_no: 0,
+            rtc: Rtc::new(),
+            has_rtc,
+        }
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn write(&mut self, mem: &mut Memory, addr: u16, val: u8) {
+        match addr {
+            // RAM and RTC enable
+            0x0000..=0x1fff => self.ram_enable = val & 0x0f == 0x0a,
+            // ROM bank number
+            0x2000..=0x3fff => self.rom_bank_no = val & 0x7f,
+            // RAM bank number or RTC register select
+            0x4000..=0x5fff => self.ram_bank_no = val,
+            // Latch clock data
+            0x6000..=0x7fff => self.rtc.latch(val),
+            // RAM bank 00-03 or RTC register
+            0xa000..=0xbfff => {
+                if !self.ram_enable {
+                    return;
+                }
+                match self.ram_bank_no {
+                    0x00..=0x03 => mem.write_ram(self.ram_bank_no as usize, addr, val),
+                    reg => self.rtc.write(reg, val),
+                }
+            }
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn read(&self, mem: &Memory, addr: u16) -> Option<u8> {
+        match addr {
+            // ROM bank 00
+            0x0000..=0x3fff => Some(mem.read_rom(0, addr)),
+            // ROM bank 01-7f
+            0x4000..=0x7fff => Some(mem.read_rom(self.rom_bank_no(), addr)),
+            // RAM bank 00-03 or RTC register
+            0xa000..=0xbfff if self.ram_enable => match self.ram_bank_no {
+                0x00..=0x03 => Some(mem.read_ram(self.ram_bank_no as usize, addr)),
+                reg => Some(self.rtc.read(reg)),
+            },
+            0xa000..=0xbfff => None,
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn rom_bank_no(&self) -> usize {
+        match self.rom_bank_no {
+            0 => 1,
+            bank_no => bank_no as usize,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.ram_enable = false;
+        self.rom_bank_no = 0;
+        self.ram_bank_no = 0;
+    }
+
+    fn update(&mut self, _mem: &mut Memory, tick: u8) {
+        if self.has_rtc {
+            self.rtc.update(tick);
+        }
+    }
+
+    fn rtc(&mut self) -> Option<&mut Rtc> {
+        Some(&mut self.rtc)
+    }
+
+    fn box_clone(&self) -> Box<dyn Mbc> {
+        Box::new(self.clone())
+    }
+}
+
+/// MBC5, with 9-bit ROM bank numbers and up to 16 RAM banks. On catridges
+/// with a rumble motor, bit 3 of the RAM bank number drives the motor.
+#[derive(Clone)]
+pub struct Mbc5 {
+    /// RAM enable
+    ram_enable: bool,
+    /// ROM bank number
+    rom_bank_no: u16,
+    /// RAM bank number
+    ram_bank_no: u8,
+    /// Has a rumble motor
+    has_rumble: bool,
+}
+
+impl Mbc5 {
+    /// Creates a new `Mbc5`.
+    pub fn new(has_rumble: bool) -> Self {
+        Mbc5 {
+            ram_enable: false,
+            rom_bank_no: 1,
+            ram_bank_no: 0,
+            has_rumble,
+        }
+    }
+
+    /// Returns the RAM bank mapped to 0xa000-0xbfff.
+    fn ram_bank_no(&self) -> usize {
+        if self.has_rumble {
+            (self.ram_bank_no & 0x07) as usize
+        } else {
+            self.ram_bank_no as usize
+        }
+    }
+}
+
+impl Mbc for Mbc5 {
+    fn write(&mut self, mem: &mut Memory, addr: u16, val: u8) {
+        match addr {
+            // RAM enable
+            0x0000..=0x1fff => self.ram_enable = val == 0x0a,
+            // ROM bank number (lower 8 bits)
+            0x2000..=0x2fff => self.rom_bank_no = self.rom_bank_no & 0x100 | val as u16,
+            // ROM bank number (9th bit)
+            0x3000..=0x3fff => {
+                self.rom_bank_no = self.rom_bank_no & 0xff | (val as u16 & 0x01) << 8
+            }
+            // RAM bank number
+            0x4000..=0x5fff => self.ram_bank_no = val & 0x0f,
+            0x6000..=0x7fff => (),
+            // RAM bank 00-0f
+            0xa000..=0xbfff => {
+                if self.ram_enable {
+                    mem.write_ram(self.ram_bank_no(), addr, val);
+                }
+            }
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn read(&self, mem: &Memory, addr: u16) -> Option<u8> {
+        match addr {
+            // ROM bank 00
+            0x0000..=0x3fff => Some(mem.read_rom(0, addr)),
+            // ROM bank 000-1ff (bank 0 is not remapped to 1)
+            0x4000..=0x7fff => Some(mem.read_rom(self.rom_bank_no(), addr)),
+            // RAM bank 00-0f
+            0xa000..=0xbfff if self.ram_enable => Some(mem.read_ram(self.ram_bank_no(), addr)),
+            0xa000..=0xbfff => None,
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn rom_bank_no(&self) -> usize {
+        self.rom_bank_no as usize
+    }
+
+    fn reset(&mut self) {
+        *self = Mbc5::new(self.has_rumble);
+    }
+
+    fn box_clone(&self) -> Box<dyn Mbc> {
+        Box::new(self.clone())
+    }
+}
+
+impl Savestate for NoMbc {
+    fn savestate(&mut self, _buf: &mut StateBuffer) {}
+}
+
+impl Savestate for Mbc1 {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.bool(&mut self.ram_enable);
+        buf.u8(&mut self.bank_no_lower);
+        buf.u8(&mut self.bank_no_upper);
+        buf.bool(&mut self.mode);
+    }
+}
+
+impl Savestate for Mbc2 {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.bool(&mut self.ram_enable);
+        buf.u8(&mut self.rom_bank_no);
+    }
+}
+
+impl Savestate for Mbc3 {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.bool(&mut self.ram_enable);
+        buf.u8(&mut self.rom_bank_no);
+        buf.u8(&mut self.ram_bank_no);
+        self.rtc.savestate(buf);
+    }
+}
+
+impl Savestate for Mbc5 {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.bool(&mut self.ram_enable);
+        buf.u16(&mut self.rom_bank_no);
+        buf.u8(&mut self.ram_bank_no);
+    }
+}
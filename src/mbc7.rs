@@ -0,0 +1,369 @@
+use mbc::{Mbc, Memory};
+use savestate::{Savestate, StateBuffer};
+
+/// Size of the 93LC56 EEPROM (128 16-bit words).
+pub const EEPROM_SIZE: usize = 256;
+
+/// Accelerometer reading when level.
+const ACCEL_CENTER: f32 = 0x81d0 as f32;
+/// Change of the accelerometer reading per 1g of tilt.
+const ACCEL_PER_G: f32 = 0x70 as f32;
+
+/// Step of the EEPROM serial protocol.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum EepromState {
+    /// Waiting for the start bit
+    Idle,
+    /// Receiving the 2-bit opcode and 8-bit address
+    Command { bits: u16, count: u8 },
+    /// Shifting out a word, MSB first
+    Read { word: u16, count: u8 },
+    /// Receiving a word to write to an address, or to every address
+    Write {
+        addr: Option<u8>,
+        word: u16,
+        count: u8,
+    },
+    /// Waiting for chip select to go low
+    Done,
+}
+
+/// 93LC56 serial EEPROM in 16-bit organization, driven through bit 7 (chip
+/// select), bit 6 (clock), bit 1 (data in) and bit 0 (data out) of a
+/// register. The contents are kept by the catridge as its RAM, with words
+/// stored little endian.
+#[derive(Clone)]
+struct Eeprom {
+    /// Chip select
+    cs: bool,
+    /// Serial clock
+    clk: bool,
+    /// Data in
+    di: bool,
+    /// Data out (1 also means ready after a write)
+    dout: bool,
+    /// Writes and erases are enabled (EWEN)
+    write_enable: bool,
+    /// Protocol step
+    state: EepromState,
+}
+
+impl Eeprom {
+    /// Creates a new, write-protected `Eeprom`.
+    fn new() -> Self {
+        Eeprom {
+            cs: false,
+            clk: false,
+            di: false,
+            dout: true,
+            write_enable: false,
+            state: EepromState::Idle,
+        }
+    }
+
+    /// Returns the pin states as read from the register.
+    fn read(&self) -> u8 {
+        (self.cs as u8) << 7 | (self.clk as u8) << 6 | (self.di as u8) << 1 | self.dout as u8
+    }
+
+    /// Sets the pins from a register write. Bits are shifted on rising
+    /// clock edges while chip select is high.
+    fn write(&mut self, val: u8, mem: &mut [u8]) {
+        let (cs, clk, di) = (val & 0x80 > 0, val & 0x40 > 0, val & 0x02 > 0);
+        let rising = !self.clk && clk;
+
+        self.cs = cs;
+        self.clk = clk;
+        self.di = di;
+
+        if !cs {
+            self.state = EepromState::Idle;
+            self.dout = true;
+        } else if rising {
+            self.clock(mem);
+        }
+    }
+
+    /// Handles a rising clock edge.
+    fn clock(&mut self, mem: &mut [u8]) {
+        let di = self.di as u16;
+
+        self.state = match self.state {
+            EepromState::Idle if self.di => EepromState::Command { bits: 0, count: 0 },
+            EepromState::Idle => EepromState::Idle,
+            EepromState::Command { bits, count } if count < 9 => EepromState::Command {
+                bits: bits << 1 | di,
+                count: count + 1,
+            },
+            EepromState::Command { bits, .. } => self.execute(bits << 1 | di, mem),
+            EepromState::Read { word, count } => {
+                self.dout = word & 0x8000 > 0;
+                match count {
+                    1 => EepromState::Done,
+                    _ => EepromState::Read {
+                        word: word << 1,
+                        count: count - 1,
+                    },
+                }
+            }
+            EepromState::Write { addr, word, count } if count < 15 => EepromState::Write {
+                addr,
+                word: word << 1 | di,
+                count: count + 1,
+            },
+            EepromState::Write { addr, word, .. } => {
+                let word = word << 1 | di;
+                match addr {
+                    Some(addr) => self.store(mem, addr, word),
+                    None => (0..(EEPROM_SIZE / 2) as u8).for_each(|a| self.store(mem, a, word)),
+                }
+                self.dout = true;
+                EepromState::Done
+            }
+            EepromState::Done => EepromState::Done,
+        };
+    }
+
+    /// Executes a command made of a 2-bit opcode and an 8-bit address.
+    fn execute(&mut self, command: u16, mem: &mut [u8]) -> EepromState {
+        let addr = (command & 0x7f) as u8;
+
+        match command >> 8 {
+            // READ, preceded by a dummy 0 bit
+            0b10 => {
+                self.dout = false;
+                let word =
+                    u16::from(mem[addr as usize * 2]) | u16::from(mem[addr as usize * 2 + 1]) << 8;
+                EepromState::Read { word, count: 16 }
+            }
+            // WRITE
+            0b01 => EepromState::Write {
+                addr: Some(addr),
+                word: 0,
+                count: 0,
+            },
+            // ERASE
+            0b11 => {
+                self.store(mem, addr, 0xffff);
+                EepromState::Done
+            }
+            _ => match (command >> 6) & 0x3 {
+                // EWEN
+                0b11 => {
+                    self.write_enable = true;
+                    EepromState::Done
+                }
+                // EWDS
+                0b00 => {
+                    self.write_enable = false;
+                    EepromState::Done
+                }
+                // ERAL
+                0b10 => {
+                    for addr in 0..(EEPROM_SIZE / 2) as u8 {
+                        self.store(mem, addr, 0xffff);
+                    }
+                    EepromState::Done
+                }
+                // WRAL
+                _ => EepromState::Write {
+                    addr: None,
+                    word: 0,
+                    count: 0,
+                },
+            },
+        }
+    }
+
+    /// Writes a word unless writes are disabled.
+    fn store(&self, mem: &mut [u8], addr: u8, word: u16) {
+        if self.write_enable {
+            mem[addr as usize * 2] = word as u8;
+            mem[addr as usize * 2 + 1] = (word >> 8) as u8;
+        }
+    }
+}
+
+/// MBC7, with an accelerometer and an EEPROM mapped to 0xa000-0xafff while
+/// both RAM enables are set.
+#[derive(Clone)]
+pub struct Mbc7 {
+    /// ROM bank number
+    rom_bank_no: u8,
+    /// First RAM enable (0x0a written to 0x0000-0x1fff)
+    ram_enable: bool,
+    /// Second RAM enable (0x40 written to 0x4000-0x5fff)
+    ram_enable2: bool,
+    /// Latch was erased and waits for 0xaa
+    latch_ready: bool,
+    /// Latched X and Y readings of the accelerometer
+    latched: (u16, u16),
+    /// Current tilt in g, positive to the right and towards the player
+    tilt: (f32, f32),
+    /// EEPROM holding the save data
+    eeprom: Eeprom,
+}
+
+impl Mbc7 {
+    /// Creates a new `Mbc7`, level and with the registers disabled.
+    pub fn new() -> Self {
+        Mbc7 {
+            rom_bank_no: 0,
+            ram_enable: false,
+            ram_enable2: false,
+            latch_ready: false,
+            latched: (0x8000, 0x8000),
+            tilt: (0.0, 0.0),
+            eeprom: Eeprom::new(),
+        }
+    }
+
+    /// Returns true if the registers are mapped.
+    fn enabled(&self) -> bool {
+        self.ram_enable && self.ram_enable2
+    }
+
+    /// Handles a write to the registers at 0xa000-0xbfff.
+    fn write_regs(&mut self, addr: u16, val: u8, eeprom: &mut [u8]) {
+        if !self.enabled() || addr >= 0xb000 {
+            return;
+        }
+
+        match (addr >> 4) & 0xf {
+            // Erase the latched readings
+            0x0 if val == 0x55 => {
+                self.latch_ready = true;
+                self.latched = (0x8000, 0x8000);
+            }
+            // Latch the readings
+            0x1 if val == 0xaa && self.latch_ready => {
+                self.latch_ready = false;
+                self.latched = (
+                    (ACCEL_CENTER + self.tilt.0 * ACCEL_PER_G) as u16,
+                    (ACCEL_CENTER + self.tilt.1 * ACCEL_PER_G) as u16,
+                );
+            }
+            0x8 => self.eeprom.write(val, eeprom),
+            _ => (),
+        }
+    }
+
+    /// Handles a read from the registers at 0xa000-0xbfff.
+    fn read_regs(&self, addr: u16) -> u8 {
+        if !self.enabled() || addr >= 0xb000 {
+            return 0xff;
+        }
+
+        match (addr >> 4) & 0xf {
+            0x2 => self.latched.0 as u8,
+            0x3 => (self.latched.0 >> 8) as u8,
+            0x4 => self.latched.1 as u8,
+            0x5 => (self.latched.1 >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read(),
+            _ => 0xff,
+        }
+    }
+}
+
+impl Mbc for Mbc7 {
+    fn write(&mut self, mem: &mut Memory, addr: u16, val: u8) {
+        match addr {
+            // RAM enable 1
+            0x0000..=0x1fff => self.ram_enable = val & 0x0f == 0x0a,
+            // ROM bank number
+            0x2000..=0x3fff => self.rom_bank_no = val,
+            // RAM enable 2
+            0x4000..=0x5fff => self.ram_enable2 = val == 0x40,
+            0x6000..=0x7fff => (),
+            // Accelerometer and EEPROM
+            0xa000..=0xbfff => self.write_regs(addr, val, &mut mem.ram),
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn read(&self, mem: &Memory, addr: u16) -> Option<u8> {
+        match addr {
+            // ROM bank 00
+            0x0000..=0x3fff => Some(mem.read_rom(0, addr)),
+            // ROM bank 00-7f
+            0x4000..=0x7fff => Some(mem.read_rom(self.rom_bank_no(), addr)),
+            // Accelerometer and EEPROM
+            0xa000..=0xbfff => Some(self.read_regs(addr)),
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn rom_bank_no(&self) -> usize {
+        self.rom_bank_no as usize
+    }
+
+    /// Resets the registers. The EEPROM contents and the tilt are kept.
+    fn reset(&mut self) {
+        let tilt = self.tilt;
+
+        *self = Mbc7::new();
+        self.tilt = tilt;
+    }
+
+    /// Sets the tilt in g (-1.0 to 1.0 on each axis), positive to the right
+    /// and towards the player.
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+
+    fn box_clone(&self) -> Box<dyn Mbc> {
+        Box::new(self.clone())
+    }
+}
+
+impl Savestate for Eeprom {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.bool(&mut self.cs);
+        buf.bool(&mut self.clk);
+        buf.bool(&mut self.di);
+        buf.bool(&mut self.dout);
+        buf.bool(&mut self.write_enable);
+
+        // Tag followed by the fields of every variant, unused ones zero
+        let (mut tag, mut addr, mut word, mut count) = match self.state {
+            EepromState::Idle => (0, None, 0, 0),
+            EepromState::Command { bits, count } => (1, None, bits, count),
+            EepromState::Read { word, count } => (2, None, word, count),
+            EepromState::Write { addr, word, count } => (3, addr, word, count),
+            EepromState::Done => (4, None, 0, 0),
+        };
+        let mut has_addr = addr.is_some();
+        let mut addr_byte = addr.unwrap_or(0);
+        buf.u8(&mut tag);
+        buf.bool(&mut has_addr);
+        buf.u8(&mut addr_byte);
+        buf.u16(&mut word);
+        buf.u8(&mut count);
+        addr = Some(addr_byte).filter(|_| has_addr);
+
+        self.state = match tag {
+            0 => EepromState::Idle,
+            1 => EepromState::Command { bits: word, count },
+            2 => EepromState::Read { word, count },
+            3 => EepromState::Write { addr, word, count },
+            4 => EepromState::Done,
+            _ => {
+                buf.fail("resume state has an invalid EEPROM state");
+                EepromState::Idle
+            }
+        };
+    }
+}
+
+impl Savestate for Mbc7 {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.u8(&mut self.rom_bank_no);
+        buf.bool(&mut self.ram_enable);
+        buf.bool(&mut self.ram_enable2);
+        buf.bool(&mut self.latch_ready);
+        buf.u16(&mut self.latched.0);
+        buf.u16(&mut self.latched.1);
+        self.eeprom.savestate(buf);
+    }
+}
@@ -0,0 +1,193 @@
+use mbc::{Mbc, Memory};
+use savestate::{Savestate, StateBuffer};
+
+/// MMM01 multicart mapper.
+///
+/// At power-on the catridge is unmapped: the last 32KB of the ROM (the game
+/// selection menu) is mapped to 0x0000-0x7fff, and every register may be
+/// written. The menu then picks a game by setting the upper bank bits and
+/// masks, and maps it by setting bit 6 of 0x0000-0x1fff. From then on the
+/// catridge behaves like an MBC1 confined to the banks of the game, until
+/// the next reset.
+#[derive(Clone)]
+pub struct Mmm01 {
+    /// RAM enable
+    ram_enable: bool,
+    /// The menu has mapped a game and the upper bits are locked
+    mapped: bool,
+    /// ROM bank bits 0-4
+    rom_bank_low: u8,
+    /// ROM bank bits 5-6, only writable while unmapped
+    rom_bank_mid: u8,
+    /// ROM bank bits 7-8, only writable while unmapped
+    rom_bank_high: u8,
+    /// Bits 1-4 of `rom_bank_low` kept from the menu
+    rom_bank_mask: u8,
+    /// RAM bank bits 0-1
+    ram_bank_low: u8,
+    /// RAM bank bits 2-3, only writable while unmapped
+    ram_bank_high: u8,
+    /// Bits of `ram_bank_low` kept from the menu
+    ram_bank_mask: u8,
+    /// MBC1 banking mode, RAM banking is used when set
+    mode: bool,
+    /// The banking mode is locked
+    mode_locked: bool,
+}
+
+impl Mmm01 {
+    /// Creates a new, unmapped `Mmm01`.
+    pub fn new() -> Self {
+        Mmm01 {
+            ram_enable: false,
+            mapped: false,
+            rom_bank_low: 0,
+            rom_bank_mid: 0,
+            rom_bank_high: 0,
+            rom_bank_mask: 0,
+            ram_bank_low: 0,
+            ram_bank_high: 0,
+            ram_bank_mask: 0,
+            mode: false,
+            mode_locked: false,
+        }
+    }
+
+    /// Handles a write to the registers at 0x0000-0x7fff.
+    fn write_regs(&mut self, addr: u16, val: u8) {
+        match addr {
+            // RAM enable, RAM bank mask and map enable
+            0x0000..=0x1fff => {
+                self.ram_enable = val & 0x0f == 0x0a;
+                if !self.mapped {
+                    self.ram_bank_mask = (val >> 4) & 0x03;
+                    self.mapped = val & 0x40 > 0;
+                }
+            }
+            // ROM bank number
+            0x2000..=0x3fff => {
+                if !self.mapped {
+                    self.rom_bank_mid = (val >> 5) & 0x03;
+                }
+                let keep = self.rom_bank_mask << 1;
+                self.rom_bank_low = (self.rom_bank_low & keep) | (val & !keep & 0x1f);
+            }
+            // RAM bank number and upper ROM bank bits
+            0x4000..=0x5fff => {
+                let keep = self.ram_bank_mask;
+                self.ram_bank_low = (self.ram_bank_low & keep) | (val & !keep & 0x03);
+                if !self.mapped {
+                    self.ram_bank_high = (val >> 2) & 0x03;
+                    self.rom_bank_high = (val >> 4) & 0x03;
+                    self.mode_locked = val & 0x40 > 0;
+                }
+            }
+            // Banking mode and ROM bank mask
+            0x6000..=0x7fff => {
+                if !self.mode_locked {
+                    self.mode = val & 0x01 > 0;
+                }
+                if !self.mapped {
+                    self.rom_bank_mask = (val >> 2) & 0x0f;
+                }
+            }
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    /// Returns the upper ROM bank bits selecting the game.
+    fn rom_base(&self) -> usize {
+        (self.rom_bank_high as usize) << 7 | (self.rom_bank_mid as usize) << 5
+    }
+
+    /// Returns the ROM bank mapped to 0x0000-0x3fff, before wrapping around
+    /// the ROM size.
+    fn rom_bank0_no(&self) -> usize {
+        // The second to last bank, once wrapped
+        if !self.mapped {
+            return !1;
+        }
+
+        let low = self.rom_bank_low & (self.rom_bank_mask << 1);
+        self.rom_base() | low as usize
+    }
+
+    /// Returns the RAM bank mapped to 0xa000-0xbfff.
+    fn ram_bank_no(&self) -> usize {
+        let low = if self.mode || !self.mapped {
+            self.ram_bank_low
+        } else {
+            self.ram_bank_low & self.ram_bank_mask
+        };
+
+        (self.ram_bank_high as usize) << 2 | low as usize
+    }
+}
+
+impl Mbc for Mmm01 {
+    fn write(&mut self, mem: &mut Memory, addr: u16, val: u8) {
+        match addr {
+            // RAM enable, ROM and RAM bank numbers, banking mode and masks
+            0x0000..=0x7fff => self.write_regs(addr, val),
+            // RAM bank 00-0f
+            0xa000..=0xbfff => {
+                if self.ram_enable {
+                    mem.write_ram(self.ram_bank_no(), addr, val);
+                }
+            }
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn read(&self, mem: &Memory, addr: u16) -> Option<u8> {
+        match addr {
+            // ROM bank 00 of the game, or the menu while unmapped
+            0x0000..=0x3fff => Some(mem.read_rom(self.rom_bank0_no(), addr)),
+            // ROM bank 01-7f of the game
+            0x4000..=0x7fff => Some(mem.read_rom(self.rom_bank_no(), addr)),
+            // RAM bank 00-0f
+            0xa000..=0xbfff if self.ram_enable => Some(mem.read_ram(self.ram_bank_no(), addr)),
+            0xa000..=0xbfff => None,
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn rom_bank_no(&self) -> usize {
+        // The last bank, once wrapped
+        if !self.mapped {
+            return !0;
+        }
+
+        // Like MBC1, bank 0 of the game maps bank 1
+        let low = match self.rom_bank_low & !(self.rom_bank_mask << 1) {
+            0 => self.rom_bank_low | 1,
+            _ => self.rom_bank_low,
+        };
+        self.rom_base() | low as usize
+    }
+
+    /// Resets to the unmapped state, showing the menu.
+    fn reset(&mut self) {
+        *self = Mmm01::new();
+    }
+
+    fn box_clone(&self) -> Box<dyn Mbc> {
+        Box::new(self.clone())
+    }
+}
+
+impl Savestate for Mmm01 {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.bool(&mut self.ram_enable);
+        buf.bool(&mut self.mapped);
+        buf.u8(&mut self.rom_bank_low);
+        buf.u8(&mut self.rom_bank_mid);
+        buf.u8(&mut self.rom_bank_high);
+        buf.u8(&mut self.rom_bank_mask);
+        buf.u8(&mut self.ram_bank_low);
+        buf.u8(&mut self.ram_bank_high);
+        buf.u8(&mut self.ram_bank_mask);
+        buf.bool(&mut self.mode);
+        buf.bool(&mut self.mode_locked);
+    }
+}
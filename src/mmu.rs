@@ -1,13 +1,47 @@
+use std::mem;
+use std::sync::mpsc::Sender;
+
+use apu::APU;
 use catridge::Catridge;
+use catridge_error::CatridgeError;
+use cheats::Cheats;
+use clock::VBlank;
+use interrupt::{Interrupt, InterruptController};
 use io_device::IODevice;
+use io_trace::IoTrace;
 use joypad::Joypad;
 use ppu::PPU;
+use profile::{Profile, Subsystem};
+use rng::Rng;
+use savestate::{Savestate, StateBuffer};
+use serial::Serial;
+use telemetry::Telemetry;
+use timeline::{Event, Timeline};
 use timer::Timer;
 
+/// Returns the name of the region containing an address outside ROM, or
+/// `None` for ROM.
+pub fn ram_region(addr: u16) -> Option<&'static str> {
+    match addr {
+        0x0000..=0x7fff => None,
+        0x8000..=0x9fff => Some("VRAM"),
+        0xa000..=0xbfff => Some("SRAM"),
+        0xc000..=0xfdff => Some("WRAM"),
+        0xfe00..=0xfe9f => Some("OAM"),
+        0xff80..=0xfffe => Some("HRAM"),
+        _ => Some("I/O"),
+    }
+}
+
 /// Memory space.
+#[derive(Clone)]
 pub struct MMU {
-    /// Catridge
-    pub catridge: Catridge,
+    /// Catridge slot, empty after ejecting the catridge
+    pub catridge: Option<Catridge>,
+    /// Boot ROM
+    boot_rom: Vec<u8>,
+    /// Boot ROM is mapped to 0x0000-0x00ff
+    boot_rom_enable: bool,
     /// RAM
     ram: [u8; 0x2000],
     /// High RAM
@@ -16,38 +50,113 @@ pub struct MMU {
     pub joypad: Joypad,
     /// Timer
     timer: Timer,
+    /// Serial port
+    pub serial: Serial,
     // TODO should this be public?
     /// Pixel Processing Unit
     pub ppu: PPU,
-    /// Interrupt flag
-    pub int_flag: u8,
-    /// Interrupt enable
-    pub int_enable: u8,
+    /// Audio Processing Unit
+    pub apu: APU,
+    /// Interrupt controller
+    pub interrupts: InterruptController,
+    /// Cheat codes
+    pub cheats: Cheats,
+    /// Timeline of PPU modes, interrupts and DMA transfers
+    pub timeline: Timeline,
+    /// Per-frame counters of the work done
+    pub telemetry: Telemetry,
+    /// Accesses to traced I/O registers
+    pub io_trace: IoTrace,
+    /// Host time spent in each subsystem, if measured
+    pub profile: Option<Profile>,
+    /// System clocks elapsed since power-on
+    clock: u64,
+    /// Number of V-Blanks since power-on
+    vblanks: u64,
+    /// Receives the emulated time at every V-Blank
+    vblank_listener: Option<Sender<VBlank>>,
 }
 
 impl MMU {
     /// Creates a new `MMU`.
-    pub fn new(rom_name: &str) -> Self {
+    pub fn new(catridge: Catridge) -> Self {
         MMU {
-            catridge: Catridge::new(rom_name),
+            catridge: Some(catridge),
+            boot_rom: Vec::new(),
+            boot_rom_enable: false,
             ram: [0; 0x2000],
             hram: [0; 0x7f],
             joypad: Joypad::new(),
             ppu: PPU::new(),
+            apu: APU::new(),
             timer: Timer::new(),
-            int_flag: 0,
-            int_enable: 0,
+            serial: Serial::new(),
+            interrupts: InterruptController::new(),
+            cheats: Cheats::new(),
+            timeline: Timeline::new(),
+            telemetry: Telemetry::new(),
+            io_trace: IoTrace::new(),
+            profile: None,
+            clock: 0,
+            vblanks: 0,
+            vblank_listener: None,
         }
     }
 
+    /// Sends the emulated time to a channel at the start of every V-Blank,
+    /// or stops sending it. Sending stops by itself when the receiver is
+    /// dropped.
+    pub fn set_vblank_listener(&mut self, listener: Option<Sender<VBlank>>) {
+        self.vblank_listener = listener;
+    }
+
+    /// Removes the V-Blank listener and returns it.
+    pub fn take_vblank_listener(&mut self) -> Option<Sender<VBlank>> {
+        self.vblank_listener.take()
+    }
+
+    /// Returns the system clocks elapsed since power-on.
+    #[allow(dead_code)]
+    pub fn clock(&self) -> u64 {
+        self.clock
+    }
+
+    /// Maps a boot ROM to 0x0000-0x00ff until it is disabled through 0xff50.
+    pub fn load_boot_rom(&mut self, boot_rom: Vec<u8>) -> Result<(), CatridgeError> {
+        if boot_rom.len() != 0x100 {
+            return Err(CatridgeError::InvalidBootRom(boot_rom.len()));
+        }
+
+        self.boot_rom = boot_rom;
+        self.boot_rom_enable = true;
+
+        Ok(())
+    }
+
+    /// Fills WRAM and HRAM with random data, like after power-on.
+    pub fn randomize_ram(&mut self, rng: &mut Rng) {
+        rng.fill(&mut self.ram);
+        rng.fill(&mut self.hram);
+    }
+
+    /// Returns true if the boot ROM is still mapped.
+    pub fn boot_rom_enabled(&self) -> bool {
+        self.boot_rom_enable
+    }
+
     /// Starts a DMA transfer.
     // TODO OAM DMA Timing
     fn do_dma(&mut self, val: u8) {
-        if val < 0x80 || 0xdf < val {
-            panic!("Invalid DMA source address")
-        }
+        self.timeline.record(Event::Dma);
+        self.telemetry.count_dma();
 
-        let src_base = (val as u16) << 8;
+        // Sources above WRAM read from WRAM, as echo RAM does
+        let src_base = if val >= 0xe0 {
+            warn!(target: "gbr::ppu", "OAM DMA from 0x{:02x}00 reads WRAM", val);
+            ((val - 0x20) as u16) << 8
+        } else {
+            (val as u16) << 8
+        };
         let dst_base = 0xfe00;
 
         for i in 0..0xa0 {
@@ -56,15 +165,45 @@ impl MMU {
         }
     }
 
+    /// Inserts a catridge (or empties the slot) while running, without a
+    /// reset, and returns the catridge removed. Some games and exploits rely
+    /// on swapping catridges on a powered Game Boy.
+    pub fn swap_catridge(&mut self, catridge: Option<Catridge>) -> Option<Catridge> {
+        mem::replace(&mut self.catridge, catridge)
+    }
+
+    fn write_catridge(&mut self, addr: u16, val: u8) {
+        if let Some(ref mut catridge) = self.catridge {
+            catridge.write(addr, val);
+        }
+    }
+
+    fn read_catridge(&self, addr: u16, peek: bool) -> u8 {
+        match self.catridge {
+            Some(ref catridge) if peek => catridge.peek(addr),
+            Some(ref catridge) => catridge.read(addr),
+            // The data bus is pulled up without a catridge
+            None => 0xff,
+        }
+    }
+
     /// Writes a byte to an address.
     pub fn write(&mut self, addr: u16, val: u8) {
+        self.io_trace.record(addr, val, true, self.clock);
+
+        if let 0x0000..=0x7fff | 0xa000..=0xdfff = addr {
+            if let Some(ref catridge) = self.catridge {
+                catridge.set_open_bus(val);
+            }
+        }
+
         match addr {
             // ROM
-            0x0000..=0x7fff => self.catridge.write(addr, val),
+            0x0000..=0x7fff => self.write_catridge(addr, val),
             // VRAM
             0x8000..=0x9fff => self.ppu.write(addr, val),
             // External RAM
-            0xa000..=0xbfff => self.catridge.write(addr, val),
+            0xa000..=0xbfff => self.write_catridge(addr, val),
             // RAM
             0xc000..=0xdfff => self.ram[(addr & 0x1fff) as usize] = val,
             // Echo RAM
@@ -73,31 +212,67 @@ impl MMU {
             0xfe00..=0xfe9f => self.ppu.write(addr, val),
             // Joypad
             0xff00 => self.joypad.write(addr, val),
+            // Serial
+            0xff01..=0xff02 => self.serial.write(addr, val),
             // Timer
             0xff04..=0xff07 => self.timer.write(addr, val),
             // Interrupt flag
-            0xff0f => self.int_flag = val,
+            0xff0f => self.interrupts.write(addr, val),
+            // APU
+            0xff10..=0xff26 | 0xff30..=0xff3f => self.apu.write(addr, val),
             // PPU
             0xff40..=0xff45 | 0xff47..=0xff4b => self.ppu.write(addr, val),
             // OAM DMA
-            0xff46 => self.do_dma(val),
+            0xff46 => {
+                self.ppu.write(addr, val);
+                self.do_dma(val);
+            }
+            // Boot ROM disable
+            0xff50 if val != 0 => self.boot_rom_enable = false,
             // HRAM
             0xff80..=0xfffe => self.hram[(addr & 0x7f) as usize] = val,
             // Interrupt enable
-            0xffff => self.int_enable = val,
+            0xffff => self.interrupts.write(addr, val),
             _ => (),
         }
     }
 
     /// Reads a byte from an address.
     pub fn read(&self, addr: u16) -> u8 {
+        let val = self.read_byte(addr, false);
+
+        // ROM, catridge RAM and WRAM share the external data bus
+        if let 0x0000..=0x7fff | 0xa000..=0xdfff = addr {
+            if let Some(ref catridge) = self.catridge {
+                catridge.set_open_bus(val);
+            }
+        }
+
+        self.io_trace.record(addr, val, false, self.clock);
+
+        val
+    }
+
+    /// Reads a byte without side effects: the open bus and the random values
+    /// of disabled catridge RAM are left alone and nothing is traced. For
+    /// reads on behalf of the user, e.g. tracepoints, that must not change
+    /// emulation.
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.read_byte(addr, true)
+    }
+
+    /// Returns the byte at an address. Catridge reads are peeks if `peek` is
+    /// true.
+    fn read_byte(&self, addr: u16, peek: bool) -> u8 {
         match addr {
+            // Boot ROM
+            0x0000..=0x00ff if self.boot_rom_enable => self.boot_rom[addr as usize],
             // ROM
-            0x0000..=0x7fff => self.catridge.read(addr),
+            0x0000..=0x7fff => self.cheats.patch_rom(addr, self.read_catridge(addr, peek)),
             // VRAM
             0x8000..=0x9fff => self.ppu.read(addr),
             // External RAM
-            0xa000..=0xbfff => self.catridge.read(addr),
+            0xa000..=0xbfff => self.read_catridge(addr, peek),
             // RAM
             0xc000..=0xdfff => self.ram[(addr & 0x1fff) as usize],
             // Echo RAM
@@ -106,45 +281,161 @@ impl MMU {
             0xfe00..=0xfe9f => self.ppu.read(addr),
             // Joypad
             0xff00 => self.joypad.read(addr),
+            // Serial
+            0xff01..=0xff02 => self.serial.read(addr),
             // Timer
             0xff04..=0xff07 => self.timer.read(addr),
             // Interrupt flag
-            0xff0f => self.int_flag,
+            0xff0f => self.interrupts.read(addr),
+            // APU
+            0xff10..=0xff26 | 0xff30..=0xff3f => self.apu.read(addr),
             // PPU
-            0xff40..=0xff45 | 0xff47..=0xff4b => self.ppu.read(addr),
+            0xff40..=0xff4b => self.ppu.read(addr),
             // HRAM
             0xff80..=0xfffe => self.hram[(addr & 0x7f) as usize],
             // Interrupt enable
-            0xffff => self.int_enable,
+            0xffff => self.interrupts.read(addr),
             _ => 0xff,
         }
     }
 
+    /// Writes the RAM values of cheats.
+    fn apply_cheats(&mut self) {
+        for (addr, val) in self.cheats.ram_writes() {
+            self.write(addr, val);
+        }
+    }
+
+    /// Requests an interrupt from a device.
+    fn request(&mut self, int: Interrupt) {
+        self.interrupts.request(int);
+        self.timeline.record(Event::Requested(int));
+    }
+
+    /// Sends the emulated time to the V-Blank listener.
+    fn notify_vblank(&mut self) {
+        self.vblanks += 1;
+
+        let vblank = VBlank {
+            frame: self.vblanks,
+            clocks: self.clock,
+        };
+        let sent = match self.vblank_listener {
+            Some(ref listener) => listener.send(vblank).is_ok(),
+            None => true,
+        };
+        if !sent {
+            self.vblank_listener = None;
+        }
+    }
+
+    /// Charges the host time since the last lap to a subsystem, if
+    /// profiling.
+    fn lap(&mut self, subsystem: Subsystem) {
+        if let Some(ref mut profile) = self.profile {
+            profile.lap(subsystem);
+        }
+    }
+
     /// Progresses the clock for a given number of ticks.
     pub fn update(&mut self, tick: u8) {
-        self.catridge.update(tick);
+        // The time since the last update was spent executing instructions
+        self.lap(Subsystem::Cpu);
+
+        let mode = self.ppu.mode();
+
+        self.clock += tick as u64;
+        self.timeline.update(tick);
+        self.telemetry.update(tick);
+        if let Some(ref mut catridge) = self.catridge {
+            catridge.update(tick);
+        }
+        self.lap(Subsystem::Other);
         self.ppu.update(tick);
+        self.lap(Subsystem::Ppu);
+
+        if self.ppu.mode() != mode {
+            if self.ppu.mode() == 2 && self.ppu.ly() == 0 {
+                self.timeline.start_frame();
+                self.telemetry.start_frame();
+            }
+            // Scanlines are rendered when entering Pixel Transfer
+            if self.ppu.mode() == 3 {
+                self.telemetry.count_scanline();
+            }
+            self.timeline.record(Event::Mode(self.ppu.mode()));
+        }
+
+        let samples = self.apu.pending_samples();
+        self.lap(Subsystem::Other);
+        self.apu.update(tick);
+        self.lap(Subsystem::Apu);
+        self.telemetry
+            .count_samples(self.apu.pending_samples().saturating_sub(samples));
         self.timer.update(tick);
+        self.serial.update(tick);
         self.joypad.update(tick);
+        self.interrupts.update(tick);
 
         if self.ppu.irq_vblank {
-            self.int_flag |= 0x1;
+            // Cheats are applied once per frame, like a GameShark does
+            self.apply_cheats();
+            self.notify_vblank();
+            self.request(Interrupt::VBlank);
             self.ppu.irq_vblank = false;
         }
 
         if self.ppu.irq_lcdc {
-            self.int_flag |= 0x2;
+            self.request(Interrupt::LCDStat);
             self.ppu.irq_lcdc = false;
         }
 
         if self.timer.irq {
-            self.int_flag |= 0x4;
+            self.request(Interrupt::Timer);
             self.timer.irq = false;
         }
 
+        if self.timer.div_apu {
+            self.apu.step_frame_sequencer();
+            self.timer.div_apu = false;
+        }
+
+        if self.serial.irq {
+            self.request(Interrupt::Serial);
+            self.serial.irq = false;
+        }
+
         if self.joypad.irq {
-            self.int_flag |= 0x10;
+            self.request(Interrupt::Joypad);
             self.joypad.irq = false;
         }
+
+        self.lap(Subsystem::Other);
+    }
+}
+
+impl Savestate for MMU {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.bool(&mut self.boot_rom_enable);
+        buf.vec(&mut self.boot_rom);
+        buf.bytes(&mut self.ram);
+        buf.bytes(&mut self.hram);
+        self.joypad.savestate(buf);
+        self.timer.savestate(buf);
+        self.serial.savestate(buf);
+        self.ppu.savestate(buf);
+        self.apu.savestate(buf);
+        self.interrupts.savestate(buf);
+
+        let mut inserted = self.catridge.is_some();
+        buf.bool(&mut inserted);
+        match self.catridge {
+            Some(ref mut catridge) if inserted => catridge.savestate(buf),
+            None if !inserted => (),
+            _ => buf.fail("resume state is for another catridge"),
+        }
+
+        buf.u64(&mut self.clock);
+        buf.u64(&mut self.vblanks);
     }
 }
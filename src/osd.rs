@@ -0,0 +1,122 @@
+/// Width of a character cell in pixels (including spacing).
+pub const CHAR_W: usize = 4;
+/// Height of a character cell in pixels (including spacing).
+pub const CHAR_H: usize = 6;
+
+/// Returns the 3x5 glyph of a character. Each row is stored in the lower 3
+/// bits, MSB first. Lower case letters are rendered as upper case.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        _ => [0; 5],
+    }
+}
+
+/// Draws text on a grayscale buffer `w` pixels wide. `x` and `y` are given in
+/// unscaled pixels and each font pixel is drawn as a `scale`x`scale` block.
+/// Text outside of the buffer is clipped.
+pub fn draw_text(
+    buf: &mut [u8],
+    w: usize,
+    x: usize,
+    y: usize,
+    scale: usize,
+    text: &str,
+    color: u8,
+) {
+    let h = buf.len() / w;
+
+    for (i, c) in text.chars().enumerate() {
+        let rows = glyph(c);
+
+        for (gy, row) in rows.iter().enumerate() {
+            for gx in 0..3 {
+                if row >> (2 - gx) & 1 == 0 {
+                    continue;
+                }
+
+                for sy in 0..scale {
+                    for sx in 0..scale {
+                        let px = (x + i * CHAR_W + gx) * scale + sx;
+                        let py = (y + gy) * scale + sy;
+
+                        if px < w && py < h {
+                            buf[py * w + px] = color;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Darkens a grayscale buffer so that text drawn on top stays readable.
+pub fn dim(buf: &mut [u8]) {
+    for pixel in buf.iter_mut() {
+        *pixel /= 4;
+    }
+}
+
+/// Fills a rectangle on a grayscale buffer `w` pixels wide. Coordinates are
+/// given in unscaled pixels as in `draw_text`.
+pub fn fill_rect(
+    buf: &mut [u8],
+    w: usize,
+    rect: (usize, usize, usize, usize),
+    scale: usize,
+    color: u8,
+) {
+    let h = buf.len() / w;
+    let (x, y, rw, rh) = rect;
+
+    for py in (y * scale)..((y + rh) * scale).min(h) {
+        for px in (x * scale)..((x + rw) * scale).min(w) {
+            buf[py * w + px] = color;
+        }
+    }
+}
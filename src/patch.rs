@@ -0,0 +1,312 @@
+use png::crc32;
+
+/// Largest ROM a BPS patch may produce (MBC5 catridges hold up to 8 MiB).
+const MAX_TARGET_SIZE: usize = 8 * 1024 * 1024;
+
+/// Applies an IPS or BPS patch to a ROM image, telling the format by the
+/// magic at the start of the patch.
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.starts_with(b"PATCH") {
+        apply_ips(rom, &patch[5..])
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(rom, patch)
+    } else {
+        Err("not an IPS or BPS patch".to_string())
+    }
+}
+
+/// Reads bytes from a patch, failing at its end.
+struct Reader<'a> {
+    /// Patch
+    data: &'a [u8],
+    /// Offset of the next byte
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Reads `len` bytes.
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() - self.pos < len {
+            return Err("patch is truncated".to_string());
+        }
+
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Reads a big-endian number of `len` bytes.
+    fn be(&mut self, len: usize) -> Result<usize, String> {
+        Ok(self.bytes(len)?.iter().fold(0, |n, &b| n << 8 | b as usize))
+    }
+
+    /// Reads a BPS variable-length number: 7 bits per byte, least
+    /// significant first, with the top bit set on the last byte.
+    fn varint(&mut self) -> Result<usize, String> {
+        let out_of_range = || "patch has a number out of range".to_string();
+        let mut n = 0usize;
+        let mut shift = 1usize;
+
+        loop {
+            let b = self.bytes(1)?[0];
+            n = ((b & 0x7f) as usize)
+                .checked_mul(shift)
+                .and_then(|v| n.checked_add(v))
+                .ok_or_else(out_of_range)?;
+            if b & 0x80 > 0 {
+                return Ok(n);
+            }
+            shift = shift.checked_mul(0x80).ok_or_else(out_of_range)?;
+            n = n.checked_add(shift).ok_or_else(out_of_range)?;
+        }
+    }
+}
+
+/// Applies the records of an IPS patch following the `PATCH` magic. Each
+/// record writes a run of bytes, or one byte repeated (RLE), to a 24-bit
+/// offset, growing the ROM if needed. The records end with `EOF`,
+/// optionally followed by the size to truncate the ROM to.
+fn apply_ips(rom: &[u8], records: &[u8]) -> Result<Vec<u8>, String> {
+    let mut rom = rom.to_vec();
+    let mut r = Reader {
+        data: records,
+        pos: 0,
+    };
+
+    loop {
+        let offset = r.be(3)?;
+        // "EOF"
+        if offset == 0x45_4f46 {
+            break;
+        }
+
+        let len = r.be(2)?;
+        let data = match len {
+            0 => {
+                let len = r.be(2)?;
+                vec![r.bytes(1)?[0]; len]
+            }
+            _ => r.bytes(len)?.to_vec(),
+        };
+
+        if rom.len() < offset + data.len() {
+            rom.resize(offset + data.len(), 0);
+        }
+        rom[offset..offset + data.len()].copy_from_slice(&data);
+    }
+
+    if r.data.len() - r.pos >= 3 {
+        rom.truncate(r.be(3)?);
+    }
+
+    Ok(rom)
+}
+
+/// Applies a BPS patch. The target is built by actions that copy from the
+/// source at the same offset, from the patch, or from anywhere in the source
+/// or the target written so far. CRC-32s of the source, the target and the
+/// patch itself catch patches made for another ROM or damaged ones.
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.len() < 4 + 12 {
+        return Err("patch is truncated".to_string());
+    }
+
+    let footer = patch.len() - 12;
+    let crc = |offset: usize| {
+        let b = &patch[footer + offset..footer + offset + 4];
+        u32::from(b[0]) | u32::from(b[1]) << 8 | u32::from(b[2]) << 16 | u32::from(b[3]) << 24
+    };
+
+    if crc32(&patch[..footer + 8]) != crc(8) {
+        return Err("patch is corrupted (checksum mismatch)".to_string());
+    }
+    if crc32(rom) != crc(0) {
+        return Err("patch is for a different ROM (checksum mismatch)".to_string());
+    }
+
+    let mut r = Reader {
+        data: &patch[..footer],
+        pos: 4,
+    };
+    let source_size = r.varint()?;
+    let target_size = r.varint()?;
+    let metadata_size = r.varint()?;
+    r.bytes(metadata_size)?;
+
+    if source_size != rom.len() {
+        return Err("patch is for a different ROM (size mismatch)".to_string());
+    }
+    if target_size > MAX_TARGET_SIZE {
+        return Err("patched ROM is too large".to_string());
+    }
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_offset = 0usize;
+    let mut target_offset = 0usize;
+
+    while r.pos < footer {
+        let action = r.varint()?;
+        let len = (action >> 2) + 1;
+        let start = target.len();
+        let end = match start.checked_add(len) {
+            Some(end) if end <= target_size => end,
+            _ => return Err("patch writes beyond the target size".to_string()),
+        };
+
+        match action & 0x03 {
+            // Source read: copy from the source at the same offset
+            0 => match rom.get(start..end) {
+                Some(data) => target.extend_from_slice(data),
+                None => return Err("patch reads beyond the source".to_string()),
+            },
+            // Target read: copy from the patch
+            1 => target.extend_from_slice(r.bytes(len)?),
+            // Source copy: copy from anywhere in the source
+            2 => {
+                source_offset = relative_offset(source_offset, r.varint()?)?;
+                match rom.get(source_offset..source_offset.saturating_add(len)) {
+                    Some(data) => target.extend_from_slice(data),
+                    None => return Err("patch reads beyond the source".to_string()),
+                }
+                source_offset += len;
+            }
+            // Target copy: copy from the target byte by byte, as the copy
+            // may overlap with itself to repeat a pattern
+            _ => {
+                target_offset = relative_offset(target_offset, r.varint()?)?;
+                if target_offset >= start {
+                    return Err("patch reads beyond the target".to_string());
+                }
+                for _ in 0..len {
+                    let b = target[target_offset];
+                    target.push(b);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != target_size {
+        return Err("patch ends before the target is complete".to_string());
+    }
+    if crc32(&target) != crc(4) {
+        return Err("patched ROM is corrupted (checksum mismatch)".to_string());
+    }
+
+    Ok(target)
+}
+
+/// Moves a BPS copy offset by a signed delta, whose lowest bit is the sign.
+fn relative_offset(offset: usize, delta: usize) -> Result<usize, String> {
+    let offset = if delta & 1 > 0 {
+        offset.checked_sub(delta >> 1)
+    } else {
+        offset.checked_add(delta >> 1)
+    };
+
+    offset.ok_or_else(|| "patch has an invalid copy offset".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes a BPS variable-length number.
+    fn varint(mut n: usize, out: &mut Vec<u8>) {
+        loop {
+            let b = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                out.push(0x80 | b);
+                return;
+            }
+            out.push(b);
+            n -= 1;
+        }
+    }
+
+    /// Appends the source, target and patch CRC-32s to a BPS patch body.
+    fn with_footer(source: &[u8], target: &[u8], mut patch: Vec<u8>) -> Vec<u8> {
+        for &crc in [crc32(source), crc32(target)].iter() {
+            patch.extend_from_slice(&crc.to_le_bytes());
+        }
+        let crc = crc32(&patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn ips() {
+        let mut patch = b"PATCH".to_vec();
+        // Two bytes at 0x000001
+        patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xaa, 0xbb]);
+        // 0xcc twice at 0x000006 (RLE), growing the ROM
+        patch.extend_from_slice(&[0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x02, 0xcc]);
+        // EOF, then truncate to 7 bytes
+        patch.extend_from_slice(b"EOF");
+        patch.extend_from_slice(&[0x00, 0x00, 0x07]);
+
+        assert_eq!(
+            apply(&[0; 4], &patch).unwrap(),
+            vec![0x00, 0xaa, 0xbb, 0x00, 0x00, 0x00, 0xcc]
+        );
+    }
+
+    #[test]
+    fn ips_truncated() {
+        let patch = b"PATCH\x00\x00\x01\x00\x02\xaa";
+
+        assert_eq!(apply(&[0; 4], patch).unwrap_err(), "patch is truncated");
+    }
+
+    #[test]
+    fn bps() {
+        let source = [1, 2, 3, 4, 5, 6, 7, 8];
+        let target = [1, 2, 9, 9, 9, 7, 8, 9, 9, 9, 7];
+
+        let mut body = b"BPS1".to_vec();
+        varint(source.len(), &mut body);
+        varint(target.len(), &mut body);
+        varint(0, &mut body);
+        // Source read of 2 bytes
+        varint(1 << 2, &mut body);
+        // Target read of 3 bytes
+        varint((2 << 2) | 1, &mut body);
+        body.extend_from_slice(&[9, 9, 9]);
+        // Source copy of 2 bytes from 6
+        varint((1 << 2) | 2, &mut body);
+        varint(6 << 1, &mut body);
+        // Target copy of 4 bytes from 2, overlapping with itself
+        varint((3 << 2) | 3, &mut body);
+        varint(2 << 1, &mut body);
+
+        let patch = with_footer(&source, &target, body);
+        assert_eq!(apply(&source, &patch).unwrap(), target.to_vec());
+
+        let other = [0; 8];
+        assert_eq!(
+            apply(&other, &patch).unwrap_err(),
+            "patch is for a different ROM (checksum mismatch)"
+        );
+
+        let mut corrupted = patch.clone();
+        corrupted[8] ^= 0xff;
+        assert_eq!(
+            apply(&source, &corrupted).unwrap_err(),
+            "patch is corrupted (checksum mismatch)"
+        );
+    }
+
+    #[test]
+    fn bps_number_out_of_range() {
+        let mut body = b"BPS1".to_vec();
+        body.extend_from_slice(&[0x00; 12]);
+        body.push(0x80);
+
+        let patch = with_footer(&[], &[], body);
+        assert_eq!(
+            apply(&[], &patch).unwrap_err(),
+            "patch has a number out of range"
+        );
+    }
+}
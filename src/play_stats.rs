@@ -0,0 +1,182 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Play statistics of a ROM.
+pub struct Entry {
+    /// Title from the catridge header
+    pub title: String,
+    /// Number of times the ROM was started
+    pub launches: u64,
+    /// Total play time in seconds
+    pub playtime: u64,
+    /// Last time the ROM was started (seconds since the Unix epoch)
+    pub last_played: u64,
+}
+
+/// Per-ROM play statistics, stored as a tab-separated file in the user's
+/// data directory (`$XDG_DATA_HOME/gbr/stats.tsv`).
+pub struct PlayStats {
+    /// Database filename
+    fname: Option<PathBuf>,
+    /// Entries keyed by ROM hash
+    entries: BTreeMap<String, Entry>,
+}
+
+/// Returns the database filename, if a data directory can be found.
+fn default_fname() -> Option<PathBuf> {
+    let data_dir = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".local/share"),
+    };
+
+    Some(data_dir.join("gbr").join("stats.tsv"))
+}
+
+/// Returns the current time in seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Returns the key identifying a ROM image (64-bit FNV-1a hash in hex).
+pub fn rom_key(rom: &[u8]) -> String {
+    let hash = rom.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+
+    format!("{:016x}", hash)
+}
+
+/// Parses a database line: key, launches, play time, last played and title.
+fn parse_line(line: &str) -> Option<(String, Entry)> {
+    let mut fields = line.splitn(5, '\t');
+
+    let key = fields.next()?.to_string();
+    let launches = fields.next()?.parse().ok()?;
+    let playtime = fields.next()?.parse().ok()?;
+    let last_played = fields.next()?.parse().ok()?;
+    let title = fields.next()?.to_string();
+
+    Some((
+        key,
+        Entry {
+            title,
+            launches,
+            playtime,
+            last_played,
+        },
+    ))
+}
+
+impl PlayStats {
+    /// Loads the statistics. A missing database is treated as empty and
+    /// malformed lines are skipped.
+    pub fn load() -> Self {
+        let fname = default_fname();
+        let mut entries = BTreeMap::new();
+
+        if let Some(file) = fname.as_ref().and_then(|f| File::open(f).ok()) {
+            for line in BufReader::new(file).lines() {
+                match line.ok().as_ref().and_then(|l| parse_line(l)) {
+                    Some((key, entry)) => {
+                        entries.insert(key, entry);
+                    }
+                    None => warn!("Skipping malformed play statistics line"),
+                }
+            }
+        }
+
+        PlayStats { fname, entries }
+    }
+
+    /// Returns the statistics of a ROM.
+    pub fn get(&self, key: &str) -> Option<&Entry> {
+        self.entries.get(key)
+    }
+
+    /// Records a start of a ROM.
+    pub fn start(&mut self, key: &str, title: &str) {
+        let entry = self.entries.entry(key.to_string()).or_insert(Entry {
+            title: String::new(),
+            launches: 0,
+            playtime: 0,
+            last_played: 0,
+        });
+
+        // Tabs and newlines would break the database format
+        entry.title = title.replace(|c: char| c.is_control(), " ");
+        entry.launches += 1;
+        entry.last_played = now();
+    }
+
+    /// Adds play time to a ROM.
+    pub fn add_playtime(&mut self, key: &str, secs: u64) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.playtime += secs;
+        }
+    }
+
+    /// Writes the statistics back to the database.
+    pub fn save(&self) -> io::Result<()> {
+        let fname = match self.fname {
+            Some(ref fname) => fname,
+            None => return Ok(()),
+        };
+
+        if let Some(dir) = fname.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut file = File::create(fname)?;
+        for (key, e) in self.entries.iter() {
+            writeln!(
+                file,
+                "{}\t{}\t{}\t{}\t{}",
+                key, e.launches, e.playtime, e.last_played, e.title
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats a duration in seconds as hours and minutes.
+pub fn format_playtime(secs: u64) -> String {
+    format!("{}h{:02}m", secs / 3600, secs / 60 % 60)
+}
+
+/// Formats the last time a ROM was started as the number of days since.
+pub fn format_last_played(last_played: u64) -> String {
+    format!("{} days ago", now().saturating_sub(last_played) / 86400)
+}
+
+/// Prints the statistics of all ROMs, most recently played first. Returns
+/// the exit code.
+pub fn main(_args: &[String]) -> i32 {
+    let stats = PlayStats::load();
+    let mut entries: Vec<&Entry> = stats.entries.values().collect();
+    entries.sort_by_key(|e| Reverse(e.last_played));
+
+    println!(
+        "{:<16}  {:>8}  {:>9}  Last played",
+        "Title", "Launches", "Playtime"
+    );
+    for e in entries {
+        println!(
+            "{:<16}  {:>8}  {:>9}  {}",
+            e.title,
+            e.launches,
+            format_playtime(e.playtime),
+            format_last_played(e.last_played)
+        );
+    }
+
+    0
+}
@@ -0,0 +1,112 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// Frames per second, to convert durations to frames.
+const FRAMES_PER_SEC: u64 = 60;
+
+/// How long a ROM of a playlist is played.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Duration {
+    /// A fixed number of frames
+    Frames(u64),
+    /// Until no key has been held for the idle timeout
+    Idle,
+}
+
+/// List of ROMs played in turn, e.g. for demo kiosks.
+///
+/// Each line has a duration in seconds (or `idle` to play until nobody has
+/// touched the joypad for a while) followed by a ROM, e.g. `300 tetris.gb`
+/// or `idle demo.gb`. Empty lines and lines starting with `#` are ignored.
+pub struct Playlist {
+    /// ROM filenames and their durations, in order
+    entries: Vec<(Duration, String)>,
+    /// Index of the ROM being played
+    current: usize,
+    /// Frames the current ROM has been played for
+    frames: u64,
+    /// Frames since a key was last held
+    idle_frames: u64,
+    /// Frames without input after which `Duration::Idle` entries end
+    idle_timeout: u64,
+}
+
+impl Playlist {
+    /// Reads a playlist from a file. `idle_secs` is the idle timeout.
+    pub fn new(fname: &str, idle_secs: u64) -> Result<Self, String> {
+        let file = File::open(fname).map_err(|e| e.to_string())?;
+        let mut entries = Vec::new();
+
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let entry = Self::parse_line(line).map_err(|e| format!("{} at line {}", e, i + 1))?;
+            entries.push(entry);
+        }
+
+        if entries.is_empty() {
+            return Err(String::from("Playlist is empty"));
+        }
+
+        info!("Playlist loaded from: {}", fname);
+
+        Ok(Playlist {
+            entries,
+            current: 0,
+            frames: 0,
+            idle_frames: 0,
+            idle_timeout: idle_secs * FRAMES_PER_SEC,
+        })
+    }
+
+    /// Parses a line consisting of a duration and a ROM filename.
+    fn parse_line(line: &str) -> Result<(Duration, String), String> {
+        let mut fields = line.splitn(2, char::is_whitespace);
+        let duration = match fields.next() {
+            Some("idle") => Duration::Idle,
+            Some(secs) => secs
+                .parse::<u64>()
+                .map(|secs| Duration::Frames(secs * FRAMES_PER_SEC))
+                .map_err(|_| format!("Invalid duration {}", secs))?,
+            None => return Err(String::from("Missing duration")),
+        };
+        let rom_fname = fields
+            .next()
+            .map(str::trim)
+            .filter(|f| !f.is_empty())
+            .ok_or_else(|| String::from("Missing ROM"))?;
+
+        Ok((duration, rom_fname.to_string()))
+    }
+
+    /// Returns the filename of the ROM being played.
+    pub fn current(&self) -> &str {
+        &self.entries[self.current].1
+    }
+
+    /// Advances by a frame, with `active` telling if any key is held. Returns
+    /// the filename of the next ROM when the current one is over.
+    pub fn advance(&mut self, active: bool) -> Option<&str> {
+        self.frames += 1;
+        self.idle_frames = if active { 0 } else { self.idle_frames + 1 };
+
+        let over = match self.entries[self.current].0 {
+            Duration::Frames(frames) => self.frames >= frames,
+            Duration::Idle => self.idle_frames >= self.idle_timeout,
+        };
+        if !over {
+            return None;
+        }
+
+        self.current = (self.current + 1) % self.entries.len();
+        self.frames = 0;
+        self.idle_frames = 0;
+
+        Some(self.current())
+    }
+}
@@ -0,0 +1,475 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+/// Maximum length of a stored deflate block.
+const MAX_STORED_BLOCK: usize = 0xffff;
+
+/// Computes the CRC-32 used by PNG chunks (and zip, BPS patches etc.).
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 > 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+/// Computes the Adler-32 checksum used by zlib streams.
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    b << 16 | a
+}
+
+/// Wraps data in a zlib stream of uncompressed (stored) deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut chunks = data.chunks(MAX_STORED_BLOCK).peekable();
+
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xff, 0xff]);
+    }
+
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+
+        out.push(last as u8);
+        out.extend_from_slice(&[len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+        out.extend_from_slice(chunk);
+    }
+
+    let adler = adler32(data);
+    out.extend_from_slice(&adler.to_be_bytes());
+
+    out
+}
+
+/// Writes a PNG chunk.
+fn write_chunk<W: Write>(w: &mut W, kind: &[u8], data: &[u8]) -> io::Result<()> {
+    let len = data.len() as u32;
+    w.write_all(&len.to_be_bytes())?;
+
+    let mut body = kind.to_vec();
+    body.extend_from_slice(data);
+    w.write_all(&body)?;
+
+    let crc = crc32(&body);
+    w.write_all(&crc.to_be_bytes())
+}
+
+/// Encodes an 8-bit grayscale image as PNG.
+pub fn encode_gray<W: Write>(
+    w: &mut W,
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+) -> io::Result<()> {
+    w.write_all(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a])?;
+
+    let mut ihdr = Vec::new();
+    for &dim in &[width as u32, height as u32] {
+        ihdr.extend_from_slice(&dim.to_be_bytes());
+    }
+    // 8-bit grayscale, deflate, adaptive filtering, no interlace
+    ihdr.extend_from_slice(&[8, 0, 0, 0, 0]);
+    write_chunk(w, b"IHDR", &ihdr)?;
+
+    // Every scanline is prefixed with filter type 0 (none)
+    let mut raw = Vec::with_capacity((width + 1) * height);
+    for row in pixels.chunks(width).take(height) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    write_chunk(w, b"IDAT", &zlib_stored(&raw))?;
+
+    write_chunk(w, b"IEND", &[])
+}
+
+/// Saves an 8-bit grayscale image as a PNG file.
+pub fn save_gray(fname: &str, pixels: &[u8], width: usize, height: usize) -> io::Result<()> {
+    let mut file = File::create(fname)?;
+
+    encode_gray(&mut file, pixels, width, height)
+}
+
+/// Returns an error for a malformed or unsupported PNG file.
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Base lengths of length codes 257-285.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+/// Extra bits of length codes 257-285.
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+/// Base distances of distance codes 0-29.
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+/// Extra bits of distance codes 0-29.
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which code length code lengths are stored.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Reads a deflate stream bit by bit, LSB first.
+struct BitReader<'a> {
+    /// Compressed data
+    data: &'a [u8],
+    /// Position of next bit
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    /// Reads `n` bits as an integer.
+    fn bits(&mut self, n: u8) -> io::Result<u32> {
+        let mut val = 0;
+
+        for i in 0..n {
+            let byte = *self
+                .data
+                .get(self.pos >> 3)
+                .ok_or_else(|| invalid("Unexpected end of deflate stream"))?;
+
+            val |= ((byte >> (self.pos & 7)) as u32 & 1) << i;
+            self.pos += 1;
+        }
+
+        Ok(val)
+    }
+
+    /// Skips to the next byte boundary.
+    fn align(&mut self) {
+        self.pos = (self.pos + 7) & !7;
+    }
+}
+
+/// Canonical Huffman code.
+struct Huffman {
+    /// Number of codes of each length
+    counts: [u16; 16],
+    /// Symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Builds a code from the code length of each symbol.
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut symbols = Vec::new();
+        for len in 1..16 {
+            for (sym, &l) in lengths.iter().enumerate() {
+                if l as usize == len {
+                    symbols.push(sym as u16);
+                }
+            }
+        }
+
+        Huffman { counts, symbols }
+    }
+
+    /// Decodes a symbol.
+    fn decode(&self, r: &mut BitReader) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+
+        for len in 1..16 {
+            code |= r.bits(1)? as i32;
+
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(invalid("Invalid Huffman code"))
+    }
+}
+
+/// Reads the code lengths of a dynamic Huffman block.
+fn dynamic_codes(r: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    let hlit = r.bits(5)? as usize + 257;
+    let hdist = r.bits(5)? as usize + 1;
+    let hclen = r.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0; 19];
+    for &i in &CODE_LENGTH_ORDER[..hclen] {
+        code_lengths[i] = r.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(hlit + hdist);
+    while lengths.len() < hlit + hdist {
+        let (len, repeat) = match code_length_code.decode(r)? {
+            sym @ 0..=15 => (sym as u8, 1),
+            16 => {
+                let prev = *lengths
+                    .last()
+                    .ok_or_else(|| invalid("No length to repeat"))?;
+                (prev, 3 + r.bits(2)?)
+            }
+            17 => (0, 3 + r.bits(3)?),
+            _ => (0, 11 + r.bits(7)?),
+        };
+
+        for _ in 0..repeat {
+            lengths.push(len);
+        }
+    }
+
+    if lengths.len() > hlit + hdist {
+        return Err(invalid("Too many code lengths"));
+    }
+
+    Ok((
+        Huffman::new(&lengths[..hlit]),
+        Huffman::new(&lengths[hlit..]),
+    ))
+}
+
+/// Decompresses a raw deflate stream.
+fn inflate(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut r = BitReader { data, pos: 0 };
+    let mut out = Vec::new();
+
+    loop {
+        let last = r.bits(1)? > 0;
+
+        match r.bits(2)? {
+            // Stored
+            0 => {
+                r.align();
+                let len = r.bits(16)? as usize;
+                r.bits(16)?;
+
+                let start = r.pos >> 3;
+                let block = data
+                    .get(start..start + len)
+                    .ok_or_else(|| invalid("Unexpected end of deflate stream"))?;
+                out.extend_from_slice(block);
+                r.pos += len * 8;
+            }
+            // Fixed or dynamic Huffman
+            kind @ 1..=2 => {
+                let (lit, dist) = if kind == 1 {
+                    let mut lengths = [8; 288];
+                    for len in lengths[144..256].iter_mut() {
+                        *len = 9;
+                    }
+                    for len in lengths[256..280].iter_mut() {
+                        *len = 7;
+                    }
+                    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+                } else {
+                    dynamic_codes(&mut r)?
+                };
+
+                loop {
+                    let sym = lit.decode(&mut r)? as usize;
+
+                    if sym < 256 {
+                        out.push(sym as u8);
+                        continue;
+                    }
+                    if sym == 256 {
+                        break;
+                    }
+
+                    let i = sym - 257;
+                    if i >= LENGTH_BASE.len() {
+                        return Err(invalid("Invalid length code"));
+                    }
+                    let len = LENGTH_BASE[i] as usize + r.bits(LENGTH_EXTRA[i])? as usize;
+
+                    let i = dist.decode(&mut r)? as usize;
+                    if i >= DIST_BASE.len() {
+                        return Err(invalid("Invalid distance code"));
+                    }
+                    let d = DIST_BASE[i] as usize + r.bits(DIST_EXTRA[i])? as usize;
+
+                    if d > out.len() {
+                        return Err(invalid("Distance too far back"));
+                    }
+                    for _ in 0..len {
+                        let byte = out[out.len() - d];
+                        out.push(byte);
+                    }
+                }
+            }
+            _ => return Err(invalid("Invalid deflate block type")),
+        }
+
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+/// Predicts a byte for PNG filtering with the Paeth predictor.
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Decodes a PNG image to 8-bit grayscale. Only non-interlaced images with
+/// 8 bits per channel are supported. Fully transparent pixels are white.
+pub fn decode_gray(png: &[u8]) -> io::Result<(Vec<u8>, usize, usize)> {
+    if png.len() < 8 || png[..8] != [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a] {
+        return Err(invalid("Not a PNG file"));
+    }
+
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut idat = Vec::new();
+    let mut pos = 8;
+
+    while pos + 8 <= png.len() {
+        let len = u32::from_be_bytes([png[pos], png[pos + 1], png[pos + 2], png[pos + 3]]) as usize;
+        let kind = &png[pos + 4..pos + 8];
+        let data = png
+            .get(pos + 8..pos + 8 + len)
+            .ok_or_else(|| invalid("Truncated chunk"))?;
+
+        match kind {
+            b"IHDR" if len >= 13 => header = Some(data),
+            b"PLTE" => palette = data,
+            b"IDAT" => idat.extend_from_slice(data),
+            b"IEND" => break,
+            _ => (),
+        }
+
+        pos += len + 12;
+    }
+
+    let header = header.ok_or_else(|| invalid("Missing IHDR"))?;
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let (depth, color_type, interlace) = (header[8], header[9], header[12]);
+
+    let channels = match color_type {
+        0 | 3 => 1,
+        4 => 2,
+        2 => 3,
+        6 => 4,
+        _ => return Err(invalid("Invalid color type")),
+    };
+    if depth != 8 || interlace != 0 {
+        return Err(invalid("Only 8-bit non-interlaced images are supported"));
+    }
+
+    // Skip the zlib header
+    let raw = inflate(idat.get(2..).ok_or_else(|| invalid("Missing IDAT"))?)?;
+
+    let stride = width * channels;
+    if raw.len() < (stride + 1) * height {
+        return Err(invalid("Image data too short"));
+    }
+
+    // Undo filtering
+    let mut pixels = vec![0u8; stride * height];
+    for y in 0..height {
+        let filter = raw[y * (stride + 1)];
+        let line = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+
+        for x in 0..stride {
+            let a = if x >= channels {
+                pixels[y * stride + x - channels]
+            } else {
+                0
+            };
+            let b = if y > 0 {
+                pixels[(y - 1) * stride + x]
+            } else {
+                0
+            };
+            let c = if x >= channels && y > 0 {
+                pixels[(y - 1) * stride + x - channels]
+            } else {
+                0
+            };
+
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(invalid("Invalid filter type")),
+            };
+
+            pixels[y * stride + x] = line[x].wrapping_add(predicted);
+        }
+    }
+
+    let luma =
+        |r: u8, g: u8, b: u8| ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8;
+
+    let gray = pixels
+        .chunks(channels)
+        .map(|p| match color_type {
+            0 => Ok(p[0]),
+            4 if p[1] == 0 => Ok(0xff),
+            4 => Ok(p[0]),
+            2 => Ok(luma(p[0], p[1], p[2])),
+            6 if p[3] == 0 => Ok(0xff),
+            6 => Ok(luma(p[0], p[1], p[2])),
+            _ => match palette.get(p[0] as usize * 3..p[0] as usize * 3 + 3) {
+                Some(rgb) => Ok(luma(rgb[0], rgb[1], rgb[2])),
+                None => Err(invalid("Palette index out of range")),
+            },
+        })
+        .collect::<io::Result<Vec<u8>>>()?;
+
+    Ok((gray, width, height))
+}
+
+/// Loads a PNG file as an 8-bit grayscale image.
+pub fn load_gray(fname: &str) -> io::Result<(Vec<u8>, usize, usize)> {
+    let mut png = Vec::new();
+    File::open(fname)?.read_to_end(&mut png)?;
+
+    decode_gray(&png)
+}
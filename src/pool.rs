@@ -0,0 +1,65 @@
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Runs independent jobs (e.g. headless emulator instances) on a fixed number
+/// of threads. Each job owns all of its state, so instances never interfere.
+pub struct Pool {
+    /// Number of worker threads
+    threads: usize,
+}
+
+impl Pool {
+    /// Creates a new `Pool` with a given number of worker threads.
+    pub fn new(threads: usize) -> Self {
+        Pool {
+            threads: threads.max(1),
+        }
+    }
+
+    /// Runs `f` on every job and returns the results in the order of `jobs`.
+    /// Panics if a job panics.
+    pub fn run<J, R, F>(&self, jobs: Vec<J>, f: F) -> Vec<R>
+    where
+        J: Send + 'static,
+        R: Send + 'static,
+        F: Fn(J) -> R + Send + Sync + 'static,
+    {
+        let num_jobs = jobs.len();
+        let queue = Arc::new(Mutex::new(jobs.into_iter().enumerate()));
+        let f = Arc::new(f);
+        let (tx, rx) = mpsc::channel();
+
+        let workers: Vec<_> = (0..self.threads.min(num_jobs))
+            .map(|_| {
+                let queue = queue.clone();
+                let f = f.clone();
+                let tx = tx.clone();
+
+                thread::spawn(move || loop {
+                    let job = queue.lock().unwrap().next();
+
+                    match job {
+                        Some((i, job)) => tx.send((i, f(job))).unwrap(),
+                        None => break,
+                    }
+                })
+            })
+            .collect();
+
+        drop(tx);
+
+        let mut results: Vec<Option<R>> = (0..num_jobs).map(|_| None).collect();
+        for (i, result) in rx {
+            results[i] = Some(result);
+        }
+
+        for worker in workers {
+            if worker.join().is_err() {
+                panic!("Job panicked");
+            }
+        }
+
+        results.into_iter().map(|r| r.unwrap()).collect()
+    }
+}
@@ -1,9 +1,29 @@
+use std::mem;
+
 use io_device::IODevice;
+use savestate::{Savestate, StateBuffer};
+use vram_watch::VramWrite;
 
 /// Width of screen in pixels.
 const SCREEN_W: u8 = 160;
 /// Height of screen in pixels.
 const SCREEN_H: u8 = 144;
+/// Number of tile rows in the tile sets (384 tiles of 8 rows).
+const NUM_TILE_ROWS: usize = 384 * 8;
+/// Size of a frame buffer in pixels.
+pub const FRAME_BUFFER_SIZE: usize = (SCREEN_W as usize) * (SCREEN_H as usize);
+/// Maximum number of sprites rendered on a scanline.
+const MAX_SPRITES_PER_LINE: usize = 10;
+/// Number of sprites in OAM.
+const NUM_SPRITES: usize = 40;
+
+/// Layers that can be hidden for debugging.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Layer {
+    Background,
+    Window,
+    Sprites,
+}
 
 #[derive(Copy, Clone, PartialEq)]
 enum BGPriority {
@@ -12,6 +32,7 @@ enum BGPriority {
 }
 
 /// Pixel Processing Unit.
+#[derive(Clone)]
 pub struct PPU {
     /// VRAM
     vram: [u8; 0x2000],
@@ -47,12 +68,47 @@ pub struct PPU {
     pub irq_lcdc: bool,
     /// Elapsed clocks in current mode
     counter: u16,
-    /// Frame buffer
-    frame_buffer: [u8; (SCREEN_W as usize) * (SCREEN_H as usize)],
+    /// Frame buffer being rendered
+    back_buffer: Box<[u8]>,
+    /// Last completed frame
+    front_buffer: Box<[u8]>,
     /// Current scanline
     scanline: [u8; SCREEN_W as usize],
     /// Background priority
     bg_prio: [BGPriority; SCREEN_W as usize],
+    /// BG and window color numbers of the current scanline
+    bg_color_no: [u8; SCREEN_W as usize],
+    /// BG color numbers (bit n for color n) that sprite pixels collide with,
+    /// or 0 to skip collision detection
+    collision_colors: u8,
+    /// Pixels where sprites collide with BG in the frame being rendered
+    collision_back: Box<[bool]>,
+    /// Pixels where sprites collide with BG in the last completed frame
+    collision_front: Box<[bool]>,
+    /// Decoded color numbers of each tile row
+    tile_cache: [u64; NUM_TILE_ROWS],
+    /// Whether each entry of the tile cache is up to date
+    tile_cache_valid: [bool; NUM_TILE_ROWS],
+    /// Generation counter bumped on OAM and LCDC writes
+    oam_generation: u32,
+    /// OAM entries visible on each scanline
+    line_sprites: [[u8; NUM_SPRITES]; SCREEN_H as usize],
+    /// Number of OAM entries visible on each scanline
+    line_sprite_count: [u8; SCREEN_H as usize],
+    /// OAM generation each scanline's sprites were searched at
+    line_sprite_generation: [u32; SCREEN_H as usize],
+    /// Whether the 10 sprites per scanline limit is enforced
+    sprite_limit: bool,
+    /// Whether completed frames are blended with the previous frame
+    blend_frames: bool,
+    /// Previous completed frame before blending
+    prev_frame: Box<[u8]>,
+    /// Whether each layer (background, window, sprites) is drawn
+    layer_visible: [bool; 3],
+    /// Watched VRAM address range
+    vram_watch: Option<(u16, u16)>,
+    /// Writes to the watched VRAM address range
+    vram_writes: Vec<VramWrite>,
 }
 
 impl PPU {
@@ -84,14 +140,30 @@ impl PPU {
             irq_lcdc: false,
             counter: 0,
             scanline: [0; SCREEN_W as usize],
-            frame_buffer: [0; (SCREEN_W as usize) * (SCREEN_H as usize)],
+            back_buffer: vec![0; FRAME_BUFFER_SIZE].into_boxed_slice(),
+            front_buffer: vec![0; FRAME_BUFFER_SIZE].into_boxed_slice(),
             bg_prio: [BGPriority::Color0; SCREEN_W as usize],
+            bg_color_no: [0; SCREEN_W as usize],
+            collision_colors: 0,
+            collision_back: vec![false; FRAME_BUFFER_SIZE].into_boxed_slice(),
+            collision_front: vec![false; FRAME_BUFFER_SIZE].into_boxed_slice(),
+            tile_cache: [0; NUM_TILE_ROWS],
+            tile_cache_valid: [false; NUM_TILE_ROWS],
+            oam_generation: 1,
+            line_sprites: [[0; NUM_SPRITES]; SCREEN_H as usize],
+            line_sprite_count: [0; SCREEN_H as usize],
+            line_sprite_generation: [0; SCREEN_H as usize],
+            sprite_limit: true,
+            blend_frames: false,
+            prev_frame: vec![0; FRAME_BUFFER_SIZE].into_boxed_slice(),
+            layer_visible: [true; 3],
+            vram_watch: None,
+            vram_writes: Vec::new(),
         }
     }
 
-    /// Fetches tile data from VRAM.
-    fn fetch_tile(&self, tile_no: u8, offset_y: u8, tile_data_sel: bool) -> (u8, u8) {
-        // Fetch tile data from tile set
+    /// Returns the VRAM address of a tile row.
+    fn tile_row_addr(&self, tile_no: u8, offset_y: u8, tile_data_sel: bool) -> u16 {
         let tile_data_addr = if tile_data_sel {
             // Use tile set #1 (0x0000-0x07ff) and #2 (0x0800-0x0fff)
             (tile_no as u16) << 4
@@ -99,7 +171,14 @@ impl PPU {
             // Use tile set #2 (0x0800-0x0fff) and #3 (0x1000-0x17ff)
             (0x1000 as u16).wrapping_add(((tile_no as i8 as i16) << 4) as u16)
         };
-        let row_addr = tile_data_addr + (offset_y << 1) as u16;
+
+        tile_data_addr + (offset_y << 1) as u16
+    }
+
+    /// Fetches tile data from VRAM.
+    fn fetch_tile(&self, tile_no: u8, offset_y: u8, tile_data_sel: bool) -> (u8, u8) {
+        // Fetch tile data from tile set
+        let row_addr = self.tile_row_addr(tile_no, offset_y, tile_data_sel);
 
         let tile0 = self.vram[row_addr as usize];
         let tile1 = self.vram[(row_addr + 1) as usize];
@@ -148,12 +227,100 @@ impl PPU {
         }
     }
 
-    /// Returns the color number at a given position from tile data.
-    fn get_color_no(&self, tile: (u8, u8), bitpos: u8) -> u8 {
-        let lo_bit = tile.0 >> bitpos & 1;
-        let hi_bit = tile.1 >> bitpos & 1;
+    /// Returns BGP, OBP0 and OBP1 along with the brightness each of them
+    /// currently maps color numbers 0-3 to.
+    pub fn palettes(&self) -> [(u8, [u8; 4]); 3] {
+        let decode = |palette| {
+            (
+                palette,
+                [
+                    self.map_color(0, palette),
+                    self.map_color(1, palette),
+                    self.map_color(2, palette),
+                    self.map_color(3, palette),
+                ],
+            )
+        };
+
+        [decode(self.bgp), decode(self.obp0), decode(self.obp1)]
+    }
+
+    /// Spreads the bits of a byte into the bytes of a u64, MSB first (i.e.
+    /// bit 7 goes to byte 0 and bit 0 goes to byte 7).
+    fn spread_bits(bits: u8) -> u64 {
+        // Broadcast the byte and keep bit 7-i in byte i
+        let masked = (bits as u64).wrapping_mul(0x0101_0101_0101_0101) & 0x0102_0408_1020_4080;
+        // Carry every non-zero byte into its MSB, then move it down to bit 0
+        (masked.wrapping_add(0x7f7f_7f7f_7f7f_7f7f) >> 7) & 0x0101_0101_0101_0101
+    }
+
+    /// Decodes a tile row into eight color numbers packed in a u64, one per
+    /// byte, with the leftmost pixel in the lowest byte.
+    pub fn decode_tile_row(tile: (u8, u8)) -> u64 {
+        Self::spread_bits(tile.0) | Self::spread_bits(tile.1) << 1
+    }
+
+    /// Returns the color number of a pixel in a decoded tile row.
+    pub fn get_color_no(row: u64, offset_x: u8) -> u8 {
+        (row >> (offset_x << 3)) as u8 & 0x3
+    }
+
+    /// Encodes eight color numbers (leftmost pixel first) into the two bytes
+    /// of a tile row. This is the inverse of `decode_tile_row`.
+    pub fn encode_tile_row(color_nos: &[u8]) -> (u8, u8) {
+        color_nos
+            .iter()
+            .take(8)
+            .fold((0, 0), |(lo, hi), &color_no| {
+                (lo << 1 | color_no & 1, hi << 1 | color_no >> 1 & 1)
+            })
+    }
+
+    /// Returns a decoded tile row, decoding it only if VRAM has been written
+    /// since it was last decoded.
+    fn decoded_tile_row(&mut self, tile_no: u8, offset_y: u8) -> u64 {
+        let row_addr = self.tile_row_addr(tile_no, offset_y, true);
+        let idx = (row_addr >> 1) as usize;
+
+        if !self.tile_cache_valid[idx] {
+            let tile = self.fetch_tile(tile_no, offset_y, true);
+
+            self.tile_cache[idx] = Self::decode_tile_row(tile);
+            self.tile_cache_valid[idx] = true;
+        }
+
+        self.tile_cache[idx]
+    }
+
+    /// Searches OAM for sprites visible on the current scanline, reusing the
+    /// previous result unless OAM or LCDC has been written since.
+    fn search_oam(&mut self) {
+        let ly = self.ly as usize;
+
+        if self.line_sprite_generation[ly] != self.oam_generation {
+            let height = if self.lcdc & 0x4 > 0 { 16 } else { 8 };
+            let mut n_sprites = 0;
+
+            for i in 0..NUM_SPRITES {
+                let sprite_y = self.oam[i << 2];
+
+                // Check if sprite is visible on this scanline
+                if sprite_y <= self.ly + 16 - height || sprite_y > self.ly + 16 {
+                    continue;
+                }
+
+                self.line_sprites[ly][n_sprites] = i as u8;
 
-        hi_bit << 1 | lo_bit
+                // Up to 10 sprites can be rendered on one scanline
+                n_sprites += 1;
+                if self.sprite_limit && n_sprites >= MAX_SPRITES_PER_LINE {
+                    break;
+                }
+            }
+
+            self.line_sprite_count[ly] = n_sprites as u8;
+            self.line_sprite_generation[ly] = self.oam_generation;
+        }
     }
 
     /// Renders BG.
@@ -166,7 +333,7 @@ impl PPU {
         let mut offset_x = self.scx & 0x7;
         let mut offset_y = self.scy.wrapping_add(self.ly) & 0x7;
 
-        let mut tile = self.fetch_bg_tile(tile_x, tile_y, offset_y);
+        let mut tile = Self::decode_tile_row(self.fetch_bg_tile(tile_x, tile_y, offset_y));
 
         let mut window = false;
 
@@ -178,14 +345,26 @@ impl PPU {
                     tile_y = (self.ly - self.wy) >> 3;
                     offset_x = 0;
                     offset_y = (self.ly - self.wy) & 0x7;
-                    tile = self.fetch_window_tile(tile_x, tile_y, offset_y);
+                    tile = Self::decode_tile_row(self.fetch_window_tile(tile_x, tile_y, offset_y));
                     window = true;
                 }
             }
 
-            let color_no = self.get_color_no(tile, 7 - offset_x);
+            let layer = if window {
+                Layer::Window
+            } else {
+                Layer::Background
+            };
+
+            // Hidden layers are drawn as color 0 so that all sprites show
+            let color_no = if self.layer_visible[layer as usize] {
+                Self::get_color_no(tile, offset_x)
+            } else {
+                0
+            };
             let color = self.map_color(color_no, self.bgp);
 
+            self.bg_color_no[x as usize] = color_no;
             self.bg_prio[x as usize] = if color_no == 0 {
                 BGPriority::Color0
             } else {
@@ -201,23 +380,25 @@ impl PPU {
                 offset_x = 0;
                 tile_x += 1;
 
-                if window {
-                    tile = self.fetch_window_tile(tile_x, tile_y, offset_y);
+                tile = Self::decode_tile_row(if window {
+                    self.fetch_window_tile(tile_x, tile_y, offset_y)
                 } else {
-                    tile = self.fetch_bg_tile(tile_x, tile_y, offset_y);
-                }
+                    self.fetch_bg_tile(tile_x, tile_y, offset_y)
+                });
             }
         }
     }
 
     /// Renders sprites.
     fn render_sprites(&mut self) {
-        let mut n_sprites = 0;
-        let height = if self.lcdc & 0x4 > 0 { 16 } else { 8 };
+        self.search_oam();
+
+        let ly = self.ly as usize;
+        let sprites = self.line_sprites[ly];
 
-        for i in 0..40 {
+        for &i in &sprites[..self.line_sprite_count[ly] as usize] {
             // Parse OAM entry
-            let entry_addr = i << 2;
+            let entry_addr = (i as usize) << 2;
             let sprite_y = self.oam[entry_addr];
             let sprite_x = self.oam[entry_addr + 1];
             let flags = self.oam[entry_addr + 3];
@@ -231,17 +412,6 @@ impl PPU {
                 self.obp0
             };
 
-            // Check if sprite is visible on this scanline
-            if sprite_y <= self.ly + 16 - height || sprite_y > self.ly + 16 {
-                continue;
-            }
-
-            // Up to 10 sprites can be rendered on one scanline
-            n_sprites += 1;
-            if n_sprites > 10 {
-                break;
-            }
-
             // Check if sprite is within the screen
             if sprite_x == 0 || sprite_x > SCREEN_W + 8 - 1 {
                 continue;
@@ -267,8 +437,8 @@ impl PPU {
                 (self.ly + 16 - sprite_y) & 0x7
             };
 
-            // Fetch tile data
-            let tile = self.fetch_tile(tile_no, offset_y, true);
+            // Fetch decoded tile data
+            let row = self.decoded_tile_row(tile_no, offset_y);
 
             for offset_x in 0..8 {
                 if offset_x + sprite_x < 8 {
@@ -281,11 +451,20 @@ impl PPU {
                     break;
                 }
 
-                let bitpos = if flip_x { offset_x } else { 7 - offset_x };
-                let color_no = self.get_color_no(tile, bitpos);
+                let color_no = if flip_x {
+                    Self::get_color_no(row, 7 - offset_x)
+                } else {
+                    Self::get_color_no(row, offset_x)
+                };
                 if color_no == 0 {
                     continue;
                 }
+                // Sprites behind BG collide too, as games test hitboxes
+                // regardless of priority
+                if self.collision_colors & (1 << self.bg_color_no[x as usize]) > 0 {
+                    let ix = (x as usize) + ly * (SCREEN_W as usize);
+                    self.collision_back[ix] = true;
+                }
                 if self.bg_prio[x as usize] == BGPriority::Color123 && obj_prio {
                     continue;
                 }
@@ -298,22 +477,153 @@ impl PPU {
 
     /// Renders a scanline.
     fn render_scanline(&mut self) {
+        let line = (self.ly as usize) * (SCREEN_W as usize);
+        for collision in &mut self.collision_back[line..line + SCREEN_W as usize] {
+            *collision = false;
+        }
+        // BG is white with BG and window disabled
+        self.bg_color_no = [0; SCREEN_W as usize];
+
         if self.lcdc & 0x1 > 0 {
             self.render_bg();
         }
-        if self.lcdc & 0x2 > 0 {
+        if self.lcdc & 0x2 > 0 && self.layer_visible[Layer::Sprites as usize] {
             self.render_sprites();
         }
 
         for x in 0..SCREEN_W {
             let ix = (x as usize) + (self.ly as usize) * (SCREEN_W as usize);
-            self.frame_buffer[ix] = self.scanline[x as usize];
+            self.back_buffer[ix] = self.scanline[x as usize];
         }
     }
 
-    /// Returns the current contents of the frame buffer.
+    /// Returns the last completed frame. Frames are rendered to a back buffer,
+    /// so the frame stays unchanged until the next V-Blank and can be
+    /// uploaded without copying it first.
     pub fn frame_buffer(&self) -> &[u8] {
-        &self.frame_buffer
+        &self.front_buffer
+    }
+
+    /// Enables or disables the 10 sprites per scanline limit. Disabling it
+    /// removes sprite flicker in busy scenes but is not accurate.
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.sprite_limit = enabled;
+        self.oam_generation = self.oam_generation.wrapping_add(1);
+    }
+
+    /// Shows or hides a layer and returns whether it is now visible. Hiding
+    /// a layer only affects the rendered image, not emulation.
+    pub fn toggle_layer(&mut self, layer: Layer) -> bool {
+        let visible = &mut self.layer_visible[layer as usize];
+        *visible = !*visible;
+
+        *visible
+    }
+
+    /// Returns whether each layer (background, window, sprites) is drawn.
+    pub fn layer_visibility(&self) -> [bool; 3] {
+        self.layer_visible
+    }
+
+    /// Shows or hides each layer (background, window, sprites).
+    pub fn set_layer_visibility(&mut self, visible: [bool; 3]) {
+        self.layer_visible = visible;
+    }
+
+    /// Sets the BG color numbers that sprite pixels collide with, as a mask
+    /// with bit n for color n. Collisions are only detected while the mask
+    /// is not 0.
+    pub fn set_collision_colors(&mut self, mask: u8) {
+        self.collision_colors = mask & 0x0f;
+    }
+
+    /// Returns the pixels of the last completed frame where a non-zero
+    /// sprite pixel overlaps one of the BG colors set by
+    /// `set_collision_colors`.
+    pub fn collisions(&self) -> &[bool] {
+        &self.collision_front
+    }
+
+    /// Makes each published frame the average of the last two completed
+    /// frames. Games that flicker objects on alternate frames for
+    /// transparency (e.g. Faceball 2000) rely on the LCD blending them.
+    pub fn set_frame_blending(&mut self, enabled: bool) {
+        self.blend_frames = enabled;
+        self.prev_frame.copy_from_slice(&self.front_buffer);
+    }
+
+    /// Blends the just published frame with the previous one.
+    fn blend_with_prev_frame(&mut self) {
+        for (pixel, prev) in self.front_buffer.iter_mut().zip(self.prev_frame.iter_mut()) {
+            let raw = *pixel;
+
+            *pixel = ((raw as u16 + *prev as u16) / 2) as u8;
+            *prev = raw;
+        }
+    }
+
+    /// Returns the frame being rendered with the scanlines rendered so far.
+    /// Scanlines that have not been rendered yet are filled with a
+    /// checkerboard. In V-Blank or with the LCD off, the last completed frame
+    /// is returned instead.
+    pub fn partial_frame(&self) -> Vec<u8> {
+        if self.lcdc & 0x80 == 0 || self.stat & 0x3 == 1 {
+            return self.front_buffer.to_vec();
+        }
+
+        // The current scanline is rendered on entering Pixel Transfer mode
+        let rendered = match self.stat & 0x3 {
+            3 | 0 => self.ly as usize + 1,
+            _ => self.ly as usize,
+        };
+        let split = rendered * (SCREEN_W as usize);
+
+        let mut frame = self.back_buffer.to_vec();
+        for (i, pixel) in frame[split..].iter_mut().enumerate() {
+            let (x, y) = (i % SCREEN_W as usize, rendered + i / SCREEN_W as usize);
+            *pixel = if (x / 4 + y / 4) % 2 == 0 { 0x00 } else { 0xff };
+        }
+
+        frame
+    }
+
+    /// Returns the current scanline.
+    pub fn ly(&self) -> u8 {
+        self.ly
+    }
+
+    /// Returns the current mode (STAT bits 0-1).
+    pub fn mode(&self) -> u8 {
+        self.stat & 0x3
+    }
+
+    /// Watches writes to a VRAM address range (inclusive).
+    pub fn watch_vram(&mut self, start: u16, end: u16) {
+        self.vram_watch = Some((start, end));
+    }
+
+    /// Returns and clears the recorded writes to the watched VRAM range.
+    pub fn take_vram_writes(&mut self) -> Vec<VramWrite> {
+        mem::take(&mut self.vram_writes)
+    }
+
+    /// Returns a stable 64-bit FNV-1a hash of the last completed frame.
+    pub fn frame_hash(&self) -> u64 {
+        self.front_buffer
+            .iter()
+            .fold(0xcbf2_9ce4_8422_2325, |hash, &pixel| {
+                (hash ^ pixel as u64).wrapping_mul(0x0000_0100_0000_01b3)
+            })
+    }
+
+    /// Returns true if the LCD is on.
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc & 0x80 > 0
+    }
+
+    /// Returns true if the LCD is on and in V-Blank mode.
+    pub fn in_vblank(&self) -> bool {
+        self.lcdc & 0x80 > 0 && self.stat & 0x3 == 1
     }
 
     /// Checks LYC interrupt.
@@ -352,7 +662,19 @@ impl IODevice for PPU {
             0x8000..=0x9fff => {
                 // VRAM is inaccessible during pixel transfer
                 if self.stat & 0x3 != 3 {
-                    self.vram[(addr & 0x1fff) as usize] = val
+                    if let Some((start, end)) = self.vram_watch {
+                        if (start..=end).contains(&addr) {
+                            let write = VramWrite::new(&self.vram, self.lcdc, addr, val);
+                            self.vram_writes.push(write);
+                        }
+                    }
+
+                    self.vram[(addr & 0x1fff) as usize] = val;
+
+                    // Invalidate the decoded tile row
+                    if addr < 0x9800 {
+                        self.tile_cache_valid[((addr & 0x1fff) >> 1) as usize] = false;
+                    }
                 }
             }
 
@@ -361,6 +683,7 @@ impl IODevice for PPU {
                 // OAM is only accessible during H-Blank and V-Blank
                 if self.stat & 0x3 == 0 || self.stat & 0x3 == 1 {
                     self.oam[(addr & 0x00ff) as usize] = val;
+                    self.oam_generation = self.oam_generation.wrapping_add(1);
                 }
             }
 
@@ -375,11 +698,16 @@ impl IODevice for PPU {
                     self.update_mode_interrupt();
                 }
 
+                if self.lcdc & 0x4 != val & 0x4 {
+                    self.oam_generation = self.oam_generation.wrapping_add(1);
+                }
+
                 self.lcdc = val;
             }
             0xff41 => self.stat = (val & 0xf8) | (self.stat & 0x3),
             0xff42 => self.scy = val,
             0xff43 => self.scx = val,
+            // LY is read-only
             0xff44 => (),
             0xff45 => {
                 if self.lyc != val {
@@ -387,13 +715,15 @@ impl IODevice for PPU {
                     self.update_lyc_interrupt();
                 }
             }
+            // The transfer itself is done by the MMU
+            0xff46 => self.dma = val,
             0xff47 => self.bgp = val,
             0xff48 => self.obp0 = val,
             0xff49 => self.obp1 = val,
             0xff4a => self.wy = val,
             0xff4b => self.wx = val,
 
-            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+            _ => warn!(target: "gbr::ppu", "Ignoring write to 0x{:04x}", addr),
         }
     }
 
@@ -433,7 +763,10 @@ impl IODevice for PPU {
             0xff4a => self.wy,
             0xff4b => self.wx,
 
-            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+            _ => {
+                warn!(target: "gbr::ppu", "Reading unmapped 0x{:04x}", addr);
+                0xff
+            }
         }
     }
 
@@ -473,6 +806,14 @@ impl IODevice for PPU {
                         // Transition to V-Blank mode
                         self.stat = (self.stat & 0xf8) | 1;
                         self.irq_vblank = true;
+
+                        // Publish the completed frame
+                        mem::swap(&mut self.front_buffer, &mut self.back_buffer);
+                        mem::swap(&mut self.collision_front, &mut self.collision_back);
+
+                        if self.blend_frames {
+                            self.blend_with_prev_frame();
+                        }
                     } else {
                         // Transition to OAM Search mode
                         self.stat = (self.stat & 0xf8) | 2;
@@ -502,3 +843,33 @@ impl IODevice for PPU {
         }
     }
 }
+
+impl Savestate for PPU {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.bytes(&mut self.vram);
+        buf.bytes(&mut self.oam);
+        buf.u8(&mut self.lcdc);
+        buf.u8(&mut self.stat);
+        buf.u8(&mut self.scy);
+        buf.u8(&mut self.scx);
+        buf.u8(&mut self.ly);
+        buf.u8(&mut self.lyc);
+        buf.u8(&mut self.dma);
+        buf.u8(&mut self.bgp);
+        buf.u8(&mut self.obp0);
+        buf.u8(&mut self.obp1);
+        buf.u8(&mut self.wy);
+        buf.u8(&mut self.wx);
+        buf.bool(&mut self.irq_vblank);
+        buf.bool(&mut self.irq_lcdc);
+        buf.u16(&mut self.counter);
+        buf.bytes(&mut self.back_buffer);
+        buf.bytes(&mut self.front_buffer);
+
+        if buf.is_loading() {
+            // VRAM and OAM were replaced behind the caches
+            self.tile_cache_valid = [false; NUM_TILE_ROWS];
+            self.oam_generation = self.oam_generation.wrapping_add(1);
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::{Duration, Instant};
+
+/// Part of the system that host time is charged to.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Subsystem {
+    Cpu,
+    Ppu,
+    Apu,
+    /// Catridge, timer, serial port, joypad and interrupts
+    Other,
+}
+
+/// All subsystems, in the order they are reported.
+pub const SUBSYSTEMS: [Subsystem; 4] = [
+    Subsystem::Cpu,
+    Subsystem::Ppu,
+    Subsystem::Apu,
+    Subsystem::Other,
+];
+
+impl Subsystem {
+    /// Returns the name used in reports.
+    pub fn name(self) -> &'static str {
+        match self {
+            Subsystem::Cpu => "cpu",
+            Subsystem::Ppu => "ppu",
+            Subsystem::Apu => "apu",
+            Subsystem::Other => "other",
+        }
+    }
+}
+
+/// Host time spent in each subsystem, measured like a stopwatch: every lap
+/// charges the time since the previous lap to a subsystem. Measuring is not
+/// free, so it is only done when enabled.
+#[derive(Clone)]
+pub struct Profile {
+    /// Time charged to each subsystem
+    times: [Duration; 4],
+    /// End of the last lap
+    last_lap: Instant,
+}
+
+impl Profile {
+    /// Creates a new `Profile` starting now.
+    pub fn new() -> Self {
+        Profile {
+            times: [Duration::new(0, 0); 4],
+            last_lap: Instant::now(),
+        }
+    }
+
+    /// Charges the time since the last lap to a subsystem.
+    pub fn lap(&mut self, subsystem: Subsystem) {
+        let now = Instant::now();
+
+        self.times[subsystem as usize] += now - self.last_lap;
+        self.last_lap = now;
+    }
+
+    /// Returns the time charged to a subsystem.
+    pub fn time(&self, subsystem: Subsystem) -> Duration {
+        self.times[subsystem as usize]
+    }
+}
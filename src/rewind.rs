@@ -0,0 +1,46 @@
+use std::collections::VecDeque;
+
+use cpu::CPU;
+
+/// Ring buffer of emulator snapshots for stepping backwards in time.
+pub struct Rewind {
+    /// Snapshots, oldest first
+    snapshots: VecDeque<CPU>,
+    /// Maximum number of snapshots kept
+    capacity: usize,
+}
+
+impl Rewind {
+    /// Creates a new `Rewind` keeping up to `capacity` snapshots.
+    pub fn new(capacity: usize) -> Self {
+        Rewind {
+            snapshots: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Takes a snapshot, dropping the oldest one if the buffer is full.
+    pub fn push(&mut self, cpu: &CPU) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.snapshots.len() >= self.capacity {
+            self.snapshots.pop_front();
+        }
+
+        self.snapshots.push_back(cpu.clone());
+    }
+
+    /// Restores the latest snapshot and removes it from the buffer. Returns
+    /// false if there are no snapshots left.
+    pub fn step_back(&mut self, cpu: &mut CPU) -> bool {
+        match self.snapshots.pop_back() {
+            Some(snapshot) => {
+                cpu.restore(snapshot);
+                true
+            }
+            None => false,
+        }
+    }
+}
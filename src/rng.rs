@@ -0,0 +1,44 @@
+use savestate::{Savestate, StateBuffer};
+
+/// Small deterministic pseudo-random number generator (xorshift64*) used to
+/// emulate power-on noise reproducibly from a seed.
+#[derive(Clone, Copy)]
+pub struct Rng {
+    /// Current state (never zero)
+    state: u64,
+}
+
+impl Rng {
+    /// Creates a new `Rng` from a seed.
+    pub fn new(seed: u64) -> Self {
+        // Mix the seed so that small seeds give unrelated sequences
+        let state = (seed ^ 0x9e37_79b9_7f4a_7c15).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+
+        Rng {
+            state: if state == 0 { 1 } else { state },
+        }
+    }
+
+    /// Returns the next 64 random bits.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Fills a buffer with random bytes.
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+impl Savestate for Rng {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.u64(&mut self.state);
+    }
+}
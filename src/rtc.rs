@@ -0,0 +1,231 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use savestate::{Savestate, StateBuffer};
+
+/// Number of clocks per second.
+const CLOCKS_PER_SEC: u32 = 4_194_304;
+/// Size of the RTC footer appended to save files.
+pub const RTC_FOOTER_SIZE: usize = 48;
+/// Size of the RTC footer with a 32-bit timestamp, written by older VBA.
+pub const RTC_FOOTER_SIZE_32: usize = 44;
+
+/// Real Time Clock of MBC3 catridges, also keeping the time of HuC3 ones.
+#[derive(Clone)]
+pub struct Rtc {
+    /// Seconds, minutes, hours, day counter (lower 8 bits) and day counter
+    /// (upper 1 bit) with halt and carry flags
+    regs: [u8; 5],
+    /// Latched copy of `regs`
+    latched: [u8; 5],
+    /// Last value written to the latch register
+    latch_prev: u8,
+    /// Elapsed clocks in current second
+    counter: u32,
+}
+
+impl Rtc {
+    /// Creates a new `Rtc`.
+    pub fn new() -> Self {
+        Rtc {
+            regs: [0; 5],
+            latched: [0; 5],
+            latch_prev: 0xff,
+            counter: 0,
+        }
+    }
+
+    /// Returns true if the clock is halted.
+    fn halted(&self) -> bool {
+        self.regs[4] & 0x40 > 0
+    }
+
+    /// Handles a write to the latch register. Writing 0x00 then 0x01 latches
+    /// the current time.
+    pub fn latch(&mut self, val: u8) {
+        if self.latch_prev == 0x00 && val == 0x01 {
+            self.latched = self.regs;
+        }
+
+        self.latch_prev = val;
+    }
+
+    /// Reads a latched RTC register (0x08-0x0c).
+    pub fn read(&self, reg: u8) -> u8 {
+        match reg {
+            0x08..=0x0c => self.latched[(reg - 0x08) as usize],
+            _ => 0xff,
+        }
+    }
+
+    /// Writes an RTC register (0x08-0x0c).
+    pub fn write(&mut self, reg: u8, val: u8) {
+        match reg {
+            0x08 => {
+                self.regs[0] = val & 0x3f;
+                self.counter = 0;
+            }
+            0x09 => self.regs[1] = val & 0x3f,
+            0x0a => self.regs[2] = val & 0x1f,
+            0x0b => self.regs[3] = val,
+            0x0c => self.regs[4] = val & 0xc1,
+            _ => (),
+        }
+    }
+
+    /// Returns the minutes elapsed in the current day and the day counter,
+    /// the time as kept by HuC3 catridges.
+    pub fn minutes_and_days(&self) -> (u16, u16) {
+        let minutes = self.regs[2] as u16 * 60 + self.regs[1] as u16;
+        let days = (self.regs[4] as u16 & 0x1) << 8 | self.regs[3] as u16;
+
+        (minutes, days)
+    }
+
+    /// Sets the time from the minutes elapsed in the current day and the day
+    /// counter. Seconds restart from zero.
+    pub fn set_minutes_and_days(&mut self, minutes: u16, days: u16) {
+        let days = days + minutes / (24 * 60);
+        let minutes = minutes % (24 * 60);
+
+        self.regs[0] = 0;
+        self.regs[1] = (minutes % 60) as u8;
+        self.regs[2] = (minutes / 60) as u8;
+        self.regs[3] = days as u8;
+        self.regs[4] = (self.regs[4] & 0xfe) | (days >> 8) as u8 & 0x1;
+        self.counter = 0;
+
+        // Day counter overflow
+        if days > 0x1ff {
+            self.regs[4] |= 0x80;
+        }
+    }
+
+    /// Advances the clock by one second.
+    fn tick_second(&mut self) {
+        self.regs[0] = (self.regs[0] + 1) & 0x3f;
+        if self.regs[0] != 60 {
+            return;
+        }
+        self.regs[0] = 0;
+
+        self.regs[1] = (self.regs[1] + 1) & 0x3f;
+        if self.regs[1] != 60 {
+            return;
+        }
+        self.regs[1] = 0;
+
+        self.regs[2] = (self.regs[2] + 1) & 0x1f;
+        if self.regs[2] != 24 {
+            return;
+        }
+        self.regs[2] = 0;
+
+        let day = ((self.regs[4] as u16 & 0x1) << 8 | self.regs[3] as u16) + 1;
+        self.regs[3] = day as u8;
+        self.regs[4] = (self.regs[4] & 0xfe) | (day >> 8) as u8 & 0x1;
+
+        // Day counter overflow
+        if day > 0x1ff {
+            self.regs[4] |= 0x80;
+        }
+    }
+
+    /// Advances the clock by a given number of seconds.
+    pub fn advance_secs(&mut self, secs: u64) {
+        if self.halted() {
+            return;
+        }
+
+        let day = (self.regs[4] as u64 & 0x1) << 8 | self.regs[3] as u64;
+        let total = secs
+            + self.regs[0] as u64
+            + self.regs[1] as u64 * 60
+            + self.regs[2] as u64 * 3600
+            + day * 86400;
+        let day = total / 86400;
+
+        self.regs[0] = (total % 60) as u8;
+        self.regs[1] = (total / 60 % 60) as u8;
+        self.regs[2] = (total / 3600 % 24) as u8;
+        self.regs[3] = day as u8;
+        self.regs[4] = (self.regs[4] & 0xfe) | (day >> 8) as u8 & 0x1;
+
+        // Day counter overflow
+        if day > 0x1ff {
+            self.regs[4] |= 0x80;
+        }
+    }
+
+    /// Progresses the clock for a given number of ticks.
+    pub fn update(&mut self, tick: u8) {
+        if self.halted() {
+            return;
+        }
+
+        self.counter += tick as u32;
+
+        if self.counter >= CLOCKS_PER_SEC {
+            self.counter -= CLOCKS_PER_SEC;
+            self.tick_second();
+        }
+    }
+
+    /// Returns the current host time in seconds since the UNIX epoch.
+    pub fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+
+    /// Serializes the clock into the save file footer used by other
+    /// emulators: the current and latched registers as 32-bit little endian
+    /// words followed by a 64-bit UNIX timestamp.
+    pub fn save(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(RTC_FOOTER_SIZE);
+
+        for &reg in self.regs.iter().chain(self.latched.iter()) {
+            buf.extend_from_slice(&[reg, 0, 0, 0]);
+        }
+
+        let timestamp = Self::now();
+        for i in 0..8 {
+            buf.push((timestamp >> (i * 8)) as u8);
+        }
+
+        buf
+    }
+
+    /// Restores the clock from a save file footer with either a 64-bit or a
+    /// 32-bit timestamp. If `catch_up` is true, the host time elapsed since
+    /// the footer was written is added.
+    pub fn load(&mut self, buf: &[u8], catch_up: bool) {
+        if buf.len() < RTC_FOOTER_SIZE_32 {
+            warn!(target: "gbr::mbc", "RTC footer too short, ignoring");
+            return;
+        }
+
+        for i in 0..5 {
+            self.regs[i] = buf[i * 4];
+            self.latched[i] = buf[(i + 5) * 4];
+        }
+
+        let mut timestamp = 0;
+        for i in 0..(buf.len() - 40).min(8) {
+            timestamp |= (buf[40 + i] as u64) << (i * 8);
+        }
+
+        if catch_up {
+            self.advance_secs(Self::now().saturating_sub(timestamp));
+        }
+    }
+}
+
+impl Savestate for Rtc {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.bytes(&mut self.regs);
+        buf.bytes(&mut self.latched);
+        buf.u8(&mut self.latch_prev);
+        buf.u32(&mut self.counter);
+    }
+}
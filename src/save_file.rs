@@ -0,0 +1,58 @@
+use rtc::{RTC_FOOTER_SIZE, RTC_FOOTER_SIZE_32};
+
+/// Size of the built-in RAM of MBC2 catridges (512 4-bit cells).
+pub const MBC2_RAM_SIZE: usize = 512;
+
+/// Battery save converted to the layout gbr uses.
+pub struct SaveData {
+    /// Catridge RAM, exactly as large as the catridge RAM
+    pub ram: Vec<u8>,
+    /// RTC footer, if the save has one
+    pub rtc: Option<Vec<u8>>,
+}
+
+/// Splits a save file into RAM and RTC footer, and normalizes saves written
+/// by other emulators and flash carts:
+///
+/// * RTC footers with a 32-bit timestamp (older VBA) as well as 64-bit ones
+/// * MBC2 RAM stored as one nibble per byte with any upper nibble
+/// * RAM padded or truncated to a different size
+pub fn normalize(mut data: Vec<u8>, ram_size: usize, has_rtc: bool, mbc2: bool) -> SaveData {
+    let mut rtc = None;
+
+    // RAM sizes are multiples of 512 bytes, so a remainder is a footer
+    if has_rtc {
+        let footer_size = data.len() % MBC2_RAM_SIZE;
+
+        if footer_size == RTC_FOOTER_SIZE || footer_size == RTC_FOOTER_SIZE_32 {
+            rtc = Some(data.split_off(data.len() - footer_size));
+        } else if footer_size != 0 {
+            warn!(target: "gbr::mbc", "Unknown RTC footer of {} bytes, ignoring", footer_size);
+        }
+    }
+
+    // Smaller saves (e.g. the MBC7 EEPROM) have no footer
+    let footer_size = data.len() % MBC2_RAM_SIZE;
+    if footer_size != 0 && data.len() != ram_size {
+        data.truncate(data.len() - footer_size);
+    }
+
+    if data.len() != ram_size {
+        info!(
+            target: "gbr::mbc",
+            "Resizing save data from {} to {} bytes",
+            data.len(),
+            ram_size
+        );
+        data.resize(ram_size, 0xff);
+    }
+
+    // Only the lower nibble is stored, the upper one reads as 1s
+    if mbc2 {
+        for b in data.iter_mut() {
+            *b |= 0xf0;
+        }
+    }
+
+    SaveData { ram: data, rtc }
+}
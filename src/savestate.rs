@@ -0,0 +1,210 @@
+/// Magic at the start of a resume state.
+const MAGIC: &[u8; 4] = b"GBRS";
+/// Version of the layout, bumped whenever a field is added or removed.
+const VERSION: u8 = 3;
+
+/// Emulated state kept in a resume state.
+pub trait Savestate {
+    /// Writes the state to, or reads it from, a buffer. Fields go through
+    /// the same calls in the same order in both directions, so the layout
+    /// cannot get out of sync. Settings and host resources (e.g. the link
+    /// cable or the output volume) are not part of the state.
+    fn savestate(&mut self, buf: &mut StateBuffer);
+}
+
+/// Little-endian buffer a resume state is written to or read from.
+pub struct StateBuffer {
+    /// Encoded state
+    data: Vec<u8>,
+    /// Offset of the next byte read
+    pos: usize,
+    /// Fields are read instead of written
+    loading: bool,
+    /// Why the state could not be read, if it could not
+    error: Option<String>,
+}
+
+impl StateBuffer {
+    /// Creates a buffer to write a state to.
+    fn saving() -> Self {
+        let mut buf = StateBuffer {
+            data: Vec::new(),
+            pos: 0,
+            loading: false,
+            error: None,
+        };
+        buf.data.extend_from_slice(MAGIC);
+        buf.data.push(VERSION);
+
+        buf
+    }
+
+    /// Creates a buffer to read a state from.
+    fn loading(data: &[u8]) -> Result<Self, String> {
+        if !data.starts_with(MAGIC) {
+            return Err("not a resume state".to_string());
+        }
+        if data.get(MAGIC.len()) != Some(&VERSION) {
+            return Err("resume state of another version".to_string());
+        }
+
+        Ok(StateBuffer {
+            data: data.to_vec(),
+            pos: MAGIC.len() + 1,
+            loading: true,
+            error: None,
+        })
+    }
+
+    /// Returns true if fields are read instead of written.
+    pub fn is_loading(&self) -> bool {
+        self.loading
+    }
+
+    /// Fails reading, e.g. on a state of another ROM. The first error is
+    /// kept.
+    pub fn fail(&mut self, reason: &str) {
+        if self.error.is_none() {
+            self.error = Some(reason.to_string());
+        }
+    }
+
+    /// Writes or reads a run of bytes.
+    pub fn bytes(&mut self, v: &mut [u8]) {
+        if !self.loading {
+            self.data.extend_from_slice(v);
+            return;
+        }
+
+        match self.data.get(self.pos..self.pos + v.len()) {
+            Some(data) => v.copy_from_slice(data),
+            None => self.fail("resume state is truncated"),
+        }
+        self.pos += v.len();
+    }
+
+    /// Writes or reads a byte vector, prefixed with its length.
+    pub fn vec(&mut self, v: &mut Vec<u8>) {
+        let mut len = v.len() as u32;
+        self.u32(&mut len);
+
+        if self.loading {
+            if len as usize > self.data.len() - self.pos.min(self.data.len()) {
+                self.fail("resume state is truncated");
+                return;
+            }
+            v.resize(len as usize, 0);
+        }
+        self.bytes(v);
+    }
+
+    /// Writes or reads a byte.
+    pub fn u8(&mut self, v: &mut u8) {
+        let mut b = [*v];
+        self.bytes(&mut b);
+        *v = b[0];
+    }
+
+    /// Writes or reads a bool.
+    pub fn bool(&mut self, v: &mut bool) {
+        let mut b = *v as u8;
+        self.u8(&mut b);
+        *v = b > 0;
+    }
+
+    /// Writes or reads a `u16`.
+    pub fn u16(&mut self, v: &mut u16) {
+        let mut b = v.to_le_bytes();
+        self.bytes(&mut b);
+        *v = u16::from_le_bytes(b);
+    }
+
+    /// Writes or reads a `u32`.
+    pub fn u32(&mut self, v: &mut u32) {
+        let mut b = v.to_le_bytes();
+        self.bytes(&mut b);
+        *v = u32::from_le_bytes(b);
+    }
+
+    /// Writes or reads a `u64`.
+    pub fn u64(&mut self, v: &mut u64) {
+        let mut b = v.to_le_bytes();
+        self.bytes(&mut b);
+        *v = u64::from_le_bytes(b);
+    }
+}
+
+/// Writes the state of a system to bytes.
+pub fn save<T: Savestate + Clone>(system: &T) -> Vec<u8> {
+    let mut buf = StateBuffer::saving();
+    system.clone().savestate(&mut buf);
+
+    buf.data
+}
+
+/// Reads a state written by `save` into a copy of a system, leaving the
+/// system untouched if the state cannot be read.
+pub fn load<T: Savestate + Clone>(system: &T, data: &[u8]) -> Result<T, String> {
+    let mut buf = StateBuffer::loading(data)?;
+    let mut copy = system.clone();
+    copy.savestate(&mut buf);
+
+    if let Some(error) = buf.error {
+        return Err(error);
+    }
+    if buf.pos != buf.data.len() {
+        return Err("resume state has trailing data".to_string());
+    }
+
+    Ok(copy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpu::{CPU, CYCLES_PER_FRAME};
+    use emulator::EmulatorBuilder;
+
+    /// Returns a powered-on system looping in an MBC1 catridge with RAM,
+    /// whose ROM differs by a byte.
+    fn cpu(id: u8) -> CPU {
+        let mut rom = vec![0; 0x8000];
+        // JR -2
+        rom[0x0100] = 0x18;
+        rom[0x0101] = 0xfe;
+        rom[0x0147] = 0x03;
+        rom[0x0149] = 0x02;
+        rom[0x014e] = id;
+
+        EmulatorBuilder::new(rom).seed(1).build().unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut cpu1 = cpu(0);
+        cpu1.mmu.write(0x0000, 0x0a);
+        cpu1.mmu.write(0xa123, 0x45);
+        cpu1.mmu.write(0xc000, 0x67);
+        cpu1.run_for_cycles(CYCLES_PER_FRAME * 3 / 2);
+        let state = save(&cpu1);
+
+        let mut cpu2 = load(&cpu(0), &state).unwrap();
+        assert_eq!(save(&cpu2), state);
+        assert_eq!(cpu2.mmu.read(0xa123), 0x45);
+        assert_eq!(cpu2.mmu.read(0xc000), 0x67);
+
+        cpu1.run_for_cycles(CYCLES_PER_FRAME);
+        cpu2.run_for_cycles(CYCLES_PER_FRAME);
+        assert_eq!(save(&cpu1), save(&cpu2));
+    }
+
+    #[test]
+    fn invalid() {
+        let state = save(&cpu(0));
+
+        assert!(load(&cpu(1), &state).is_err());
+        assert!(load(&cpu(0), &state[..state.len() - 1]).is_err());
+        assert!(load(&cpu(0), &[state.as_slice(), &[0]].concat()).is_err());
+        assert!(load(&cpu(0), b"PATCH").is_err());
+    }
+}
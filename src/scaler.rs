@@ -0,0 +1,144 @@
+/// Software scaler applied to frames before they are uploaded to the screen.
+pub trait Scaler {
+    /// Returns the scaling factor.
+    fn factor(&self) -> usize;
+
+    /// Scales a `w`x`h` frame into `dst`, which holds `w * factor()` x
+    /// `h * factor()` pixels.
+    fn scale(&self, src: &[u8], w: usize, h: usize, dst: &mut [u8]);
+}
+
+/// Returns a scaler by its name.
+pub fn from_name(name: &str) -> Option<Box<dyn Scaler>> {
+    match name {
+        "none" => Some(Box::new(Identity)),
+        "scale2x" => Some(Box::new(Scale2x)),
+        "scale3x" => Some(Box::new(Scale3x)),
+        _ => None,
+    }
+}
+
+/// Returns the pixel at (x + dx, y + dy), clamping coordinates to the frame.
+fn pixel(src: &[u8], w: usize, h: usize, x: usize, y: usize, dx: isize, dy: isize) -> u8 {
+    let x = (x as isize + dx).max(0).min(w as isize - 1) as usize;
+    let y = (y as isize + dy).max(0).min(h as isize - 1) as usize;
+
+    src[y * w + x]
+}
+
+/// Passes the frame through unmodified.
+pub struct Identity;
+
+impl Scaler for Identity {
+    fn factor(&self) -> usize {
+        1
+    }
+
+    fn scale(&self, src: &[u8], _w: usize, _h: usize, dst: &mut [u8]) {
+        dst.copy_from_slice(src);
+    }
+}
+
+/// Scale2x (AdvMAME2x) pixel art scaler.
+pub struct Scale2x;
+
+impl Scaler for Scale2x {
+    fn factor(&self) -> usize {
+        2
+    }
+
+    fn scale(&self, src: &[u8], w: usize, h: usize, dst: &mut [u8]) {
+        let dw = w * 2;
+
+        for y in 0..h {
+            for x in 0..w {
+                let b = pixel(src, w, h, x, y, 0, -1);
+                let d = pixel(src, w, h, x, y, -1, 0);
+                let e = src[y * w + x];
+                let f = pixel(src, w, h, x, y, 1, 0);
+                let h_ = pixel(src, w, h, x, y, 0, 1);
+
+                let (e0, e1, e2, e3) = if b != h_ && d != f {
+                    (
+                        if d == b { d } else { e },
+                        if b == f { f } else { e },
+                        if d == h_ { d } else { e },
+                        if h_ == f { f } else { e },
+                    )
+                } else {
+                    (e, e, e, e)
+                };
+
+                let base = y * 2 * dw + x * 2;
+                dst[base] = e0;
+                dst[base + 1] = e1;
+                dst[base + dw] = e2;
+                dst[base + dw + 1] = e3;
+            }
+        }
+    }
+}
+
+/// Scale3x (AdvMAME3x) pixel art scaler.
+pub struct Scale3x;
+
+impl Scaler for Scale3x {
+    fn factor(&self) -> usize {
+        3
+    }
+
+    fn scale(&self, src: &[u8], w: usize, h: usize, dst: &mut [u8]) {
+        let dw = w * 3;
+
+        for y in 0..h {
+            for x in 0..w {
+                let a = pixel(src, w, h, x, y, -1, -1);
+                let b = pixel(src, w, h, x, y, 0, -1);
+                let c = pixel(src, w, h, x, y, 1, -1);
+                let d = pixel(src, w, h, x, y, -1, 0);
+                let e = src[y * w + x];
+                let f = pixel(src, w, h, x, y, 1, 0);
+                let g = pixel(src, w, h, x, y, -1, 1);
+                let h_ = pixel(src, w, h, x, y, 0, 1);
+                let i = pixel(src, w, h, x, y, 1, 1);
+
+                let out = if b != h_ && d != f {
+                    [
+                        if d == b { d } else { e },
+                        if (d == b && e != c) || (b == f && e != a) {
+                            b
+                        } else {
+                            e
+                        },
+                        if b == f { f } else { e },
+                        if (d == b && e != g) || (d == h_ && e != a) {
+                            d
+                        } else {
+                            e
+                        },
+                        e,
+                        if (b == f && e != i) || (h_ == f && e != c) {
+                            f
+                        } else {
+                            e
+                        },
+                        if d == h_ { d } else { e },
+                        if (d == h_ && e != i) || (h_ == f && e != g) {
+                            h_
+                        } else {
+                            e
+                        },
+                        if h_ == f { f } else { e },
+                    ]
+                } else {
+                    [e; 9]
+                };
+
+                let base = y * 3 * dw + x * 3;
+                for (j, &color) in out.iter().enumerate() {
+                    dst[base + (j / 3) * dw + j % 3] = color;
+                }
+            }
+        }
+    }
+}
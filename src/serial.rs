@@ -0,0 +1,215 @@
+use std::sync::{Arc, Mutex};
+
+use io_device::IODevice;
+use savestate::{Savestate, StateBuffer};
+
+/// Clocks per transferred byte with the internal clock (8192 Hz).
+const CLOCKS_PER_BYTE: u32 = 512 * 8;
+
+/// Device on the other end of the link cable.
+pub trait Link: Send {
+    /// Exchanges a byte when a transfer clocked by this Game Boy completes,
+    /// returning the byte received from the other end.
+    fn exchange(&mut self, out: u8) -> u8;
+}
+
+/// Link that records every byte sent by the Game Boy, as if printed by a
+/// serial terminal. Test ROMs report their results this way. Nothing is
+/// connected on the other end, so all ones are received.
+pub struct Capture {
+    /// Bytes sent so far
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Capture {
+    /// Creates a new `Capture` and returns it with a handle to its output.
+    pub fn new() -> (Self, Arc<Mutex<Vec<u8>>>) {
+        let output = Arc::new(Mutex::new(Vec::new()));
+
+        (
+            Capture {
+                output: output.clone(),
+            },
+            output,
+        )
+    }
+}
+
+impl Link for Capture {
+    fn exchange(&mut self, out: u8) -> u8 {
+        self.output.lock().unwrap().push(out);
+        0xff
+    }
+}
+
+/// Serial port.
+pub struct Serial {
+    /// Serial transfer data
+    sb: u8,
+    /// Serial transfer control
+    sc: u8,
+    /// Elapsed clocks in current transfer
+    counter: u32,
+    /// Device connected to the link port
+    link: Option<Box<dyn Link>>,
+    /// Extra clocks added to every transfer to emulate cable latency
+    latency: u16,
+    /// Number of bytes after which the cable is disconnected
+    disconnect_after: Option<u64>,
+    /// Number of bytes transferred
+    transferred: u64,
+    /// Interrupt request
+    pub irq: bool,
+}
+
+impl Serial {
+    /// Creates a new `Serial`.
+    pub fn new() -> Self {
+        Serial {
+            sb: 0,
+            sc: 0x7e,
+            counter: 0,
+            link: None,
+            latency: 0,
+            disconnect_after: None,
+            transferred: 0,
+            irq: false,
+        }
+    }
+
+    /// Connects a device to the link port.
+    pub fn connect(&mut self, link: Box<dyn Link>) {
+        self.link = Some(link);
+    }
+
+    /// Disconnects the device from the link port and returns it.
+    pub fn disconnect(&mut self) -> Option<Box<dyn Link>> {
+        self.link.take()
+    }
+
+    /// Delays every transfer clocked by this Game Boy by a number of clocks.
+    pub fn set_latency(&mut self, clocks: u16) {
+        self.latency = clocks;
+    }
+
+    /// Disconnects the cable after a number of bytes have been transferred,
+    /// as if it was pulled mid-session.
+    pub fn set_disconnect_after(&mut self, bytes: Option<u64>) {
+        self.disconnect_after = bytes;
+    }
+
+    /// Returns true if the cable has been (virtually) pulled.
+    fn disconnected(&self) -> bool {
+        match self.disconnect_after {
+            Some(bytes) => self.transferred >= bytes,
+            None => false,
+        }
+    }
+
+    /// Returns true if a transfer is in progress.
+    fn transferring(&self) -> bool {
+        self.sc & 0x80 > 0
+    }
+
+    /// Returns true if this Game Boy provides the clock.
+    fn internal_clock(&self) -> bool {
+        self.sc & 0x01 > 0
+    }
+
+    /// Completes a transfer, storing the received byte.
+    fn complete(&mut self, received: u8) {
+        self.transferred += 1;
+        self.sb = received;
+        self.sc &= 0x7f;
+        self.irq = true;
+    }
+
+    /// Clocks a byte in from a device driving the clock (e.g. another Game
+    /// Boy or a four player adapter). Returns the byte shifted out, or `None`
+    /// if no transfer with the external clock is pending.
+    pub fn external_clock(&mut self, received: u8) -> Option<u8> {
+        // When disconnected, the transfer never completes
+        if !self.transferring() || self.internal_clock() || self.disconnected() {
+            return None;
+        }
+
+        let sent = self.sb;
+        self.complete(received);
+
+        Some(sent)
+    }
+}
+
+impl Clone for Serial {
+    /// Clones the port state. The device connected to the link port cannot be
+    /// cloned and is left disconnected in the clone.
+    fn clone(&self) -> Self {
+        Serial {
+            sb: self.sb,
+            sc: self.sc,
+            counter: self.counter,
+            link: None,
+            latency: self.latency,
+            disconnect_after: self.disconnect_after,
+            transferred: self.transferred,
+            irq: self.irq,
+        }
+    }
+}
+
+impl IODevice for Serial {
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            // SB
+            0xff01 => self.sb = val,
+            // SC
+            0xff02 => {
+                self.sc = val | 0x7e;
+                self.counter = 0;
+            }
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            // SB
+            0xff01 => self.sb,
+            // SC
+            0xff02 => self.sc,
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn update(&mut self, tick: u8) {
+        if !self.transferring() || !self.internal_clock() {
+            return;
+        }
+
+        self.counter += tick as u32;
+
+        if self.counter >= CLOCKS_PER_BYTE + self.latency as u32 {
+            self.counter = 0;
+
+            // Without a cable, all ones are shifted in
+            let sb = self.sb;
+            let disconnected = self.disconnected();
+            let received = match self.link {
+                Some(ref mut link) if !disconnected => link.exchange(sb),
+                _ => 0xff,
+            };
+
+            self.complete(received);
+        }
+    }
+}
+
+impl Savestate for Serial {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.u8(&mut self.sb);
+        buf.u8(&mut self.sc);
+        buf.u32(&mut self.counter);
+        buf.u64(&mut self.transferred);
+        buf.bool(&mut self.irq);
+    }
+}
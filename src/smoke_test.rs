@@ -0,0 +1,241 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use catridge_error::CatridgeError;
+use cpu::CYCLES_PER_FRAME;
+use emulator::EmulatorBuilder;
+use input_script::InputScript;
+use input_source::InputSource;
+use pool::Pool;
+use serial::Capture;
+
+/// End-to-end test of a ROM described by a script:
+///
+/// ```text
+/// rom tetris.gb           # ROM image, relative to the script
+/// seed 42                 # Seed power-on RAM (see `CPU::seed`)
+/// input 120 start         # Hold keys from a frame on (see `InputScript`)
+/// input 125 -
+/// expect 600 0123456789abcdef  # Expected frame hash after 600 frames
+/// serial 3000 Passed      # Expected serial output within 3000 frames
+/// serial-hex 600 03 05 08 # Same, given as hex bytes
+/// ```
+struct SmokeTest {
+    /// ROM filename
+    rom_fname: PathBuf,
+    /// Seed for power-on RAM contents
+    seed: Option<u64>,
+    /// Joypad input
+    input: InputScript,
+    /// Frame numbers and expected frame hashes
+    expects: Vec<(u64, u64)>,
+    /// Frame number by which the serial output must contain a byte string
+    serial: Option<(u64, Vec<u8>)>,
+}
+
+/// Result of a test.
+enum Outcome {
+    Pass,
+    Fail(String),
+    Skip(String),
+}
+
+impl SmokeTest {
+    /// Reads a test script.
+    fn new(fname: &str) -> Result<Self, String> {
+        let file = File::open(fname).map_err(|e| e.to_string())?;
+        let dir = Path::new(fname).parent().unwrap_or_else(|| Path::new(""));
+
+        let mut rom_fname = None;
+        let mut seed = None;
+        let mut input = InputScript::empty();
+        let mut expects = Vec::new();
+        let mut serial = None;
+
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (cmd, rest) = match line.find(char::is_whitespace) {
+                Some(pos) => (&line[..pos], line[pos..].trim()),
+                None => (line, ""),
+            };
+            let at_line = |e: String| format!("{} at line {}", e, i + 1);
+
+            match cmd {
+                "rom" => rom_fname = Some(dir.join(rest)),
+                "seed" => match rest.parse() {
+                    Ok(n) => seed = Some(n),
+                    Err(_) => return Err(at_line(String::from("Invalid seed"))),
+                },
+                "input" => {
+                    let (frame, keys) = InputScript::parse_line(rest).map_err(at_line)?;
+                    input.push(frame, keys).map_err(at_line)?;
+                }
+                "expect" => {
+                    let mut fields = rest.split_whitespace();
+                    let frame = fields.next().and_then(|f| f.parse().ok());
+                    let hash = fields.next().and_then(|h| u64::from_str_radix(h, 16).ok());
+
+                    match (frame, hash) {
+                        (Some(frame), Some(hash)) => expects.push((frame, hash)),
+                        _ => return Err(at_line(String::from("Invalid expectation"))),
+                    }
+                }
+                "serial" | "serial-hex" => {
+                    let (frame, text) = match rest.find(char::is_whitespace) {
+                        Some(pos) => (rest[..pos].parse().ok(), rest[pos..].trim()),
+                        None => (None, ""),
+                    };
+                    let bytes = if cmd == "serial" {
+                        Some(text.as_bytes().to_vec())
+                    } else {
+                        text.split_whitespace()
+                            .map(|b| u8::from_str_radix(b, 16).ok())
+                            .collect()
+                    };
+
+                    match (frame, bytes) {
+                        (Some(frame), Some(ref bytes)) if !bytes.is_empty() => {
+                            serial = Some((frame, bytes.clone()))
+                        }
+                        _ => return Err(at_line(String::from("Invalid serial expectation"))),
+                    }
+                }
+                _ => return Err(at_line(format!("Unknown command {}", cmd))),
+            }
+        }
+
+        Ok(SmokeTest {
+            rom_fname: rom_fname.ok_or_else(|| String::from("No ROM specified"))?,
+            seed,
+            input,
+            expects,
+            serial,
+        })
+    }
+
+    /// Runs the test.
+    fn run(mut self) -> Outcome {
+        if !self.rom_fname.exists() {
+            return Outcome::Skip(format!("{} not found", self.rom_fname.display()));
+        }
+
+        let seed = self.seed;
+        let cpu = fs::read(&self.rom_fname)
+            .map_err(CatridgeError::Read)
+            .and_then(|rom| {
+                let mut builder = EmulatorBuilder::new(rom);
+                if let Some(seed) = seed {
+                    builder = builder.seed(seed);
+                }
+                builder.build()
+            });
+        let mut cpu = match cpu {
+            Ok(cpu) => cpu,
+            Err(e) => return Outcome::Fail(e.to_string()),
+        };
+        let (capture, output) = Capture::new();
+        cpu.mmu.serial.connect(Box::new(capture));
+
+        let last_expect = self.expects.iter().map(|&(f, _)| f).max().unwrap_or(0);
+        let serial_frame = self.serial.as_ref().map(|&(f, _)| f).unwrap_or(0);
+        let last_frame = last_expect.max(serial_frame);
+        let mut serial_found = false;
+
+        for frame in 0..last_frame {
+            cpu.mmu.joypad.set_input(&self.input.poll(frame));
+            cpu.run_for_cycles(CYCLES_PER_FRAME);
+
+            let hash = cpu.mmu.ppu.frame_hash();
+
+            for &(f, expected) in self.expects.iter() {
+                if f == frame + 1 && hash != expected {
+                    return Outcome::Fail(format!(
+                        "frame {}: expected {:016x}, got {:016x}",
+                        f, expected, hash
+                    ));
+                }
+            }
+
+            if let Some((f, ref expected)) = self.serial {
+                if !serial_found {
+                    let output = output.lock().unwrap();
+                    serial_found = output.windows(expected.len()).any(|w| w == &expected[..]);
+
+                    if !serial_found && frame + 1 >= f {
+                        return Outcome::Fail(format!(
+                            "frame {}: serial output {:?} does not contain {:?}",
+                            f,
+                            String::from_utf8_lossy(&output),
+                            String::from_utf8_lossy(expected)
+                        ));
+                    }
+                }
+
+                // Test ROMs may stop responding after reporting their result
+                if serial_found && frame + 1 >= last_expect {
+                    break;
+                }
+            }
+        }
+
+        Outcome::Pass
+    }
+}
+
+/// Number of tests run in parallel by default.
+const DEFAULT_JOBS: usize = 4;
+
+/// Runs a test script.
+fn run_script(fname: String) -> Outcome {
+    match SmokeTest::new(&fname) {
+        Ok(test) => test.run(),
+        Err(e) => Outcome::Fail(e),
+    }
+}
+
+/// Runs test scripts (`[-j <jobs>] <script>...`) and returns the exit code.
+pub fn main(args: &[String]) -> i32 {
+    let mut jobs = DEFAULT_JOBS;
+    let mut fnames = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-j" => match args.next().and_then(|j| j.parse().ok()) {
+                Some(j) => jobs = j,
+                None => {
+                    eprintln!("Invalid number of jobs");
+                    return 1;
+                }
+            },
+            _ => fnames.push(arg.clone()),
+        }
+    }
+
+    let outcomes = Pool::new(jobs).run(fnames.clone(), run_script);
+    let mut failed = 0;
+
+    for (fname, outcome) in fnames.iter().zip(outcomes) {
+        match outcome {
+            Outcome::Pass => println!("{} ... ok", fname),
+            Outcome::Skip(reason) => println!("{} ... skipped ({})", fname, reason),
+            Outcome::Fail(reason) => {
+                println!("{} ... FAILED ({})", fname, reason);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        1
+    } else {
+        0
+    }
+}
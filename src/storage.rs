@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+/// Where battery saves and resume states are kept. The core takes ROM images as bytes and
+/// keeps saves only through a backend supplied by the frontend, so that it
+/// never touches the file system itself. Each frontend supplies a backend
+/// suiting its platform (e.g. files, browser storage or the save directory
+/// of a libretro frontend).
+pub trait StorageBackend {
+    /// Loads the data stored under a key. Returns an error of kind
+    /// `NotFound` if nothing is stored.
+    fn load(&self, key: &str) -> io::Result<Vec<u8>>;
+
+    /// Stores data under a key, replacing any previous data.
+    fn store(&mut self, key: &str, data: &[u8]) -> io::Result<()>;
+
+    /// Removes the data stored under a key. Returns an error of kind
+    /// `NotFound` if nothing is stored.
+    fn remove(&mut self, key: &str) -> io::Result<()>;
+}
+
+/// Keeps data in files, using keys as paths.
+pub struct FileStorage;
+
+impl StorageBackend for FileStorage {
+    fn load(&self, key: &str) -> io::Result<Vec<u8>> {
+        fs::read(key)
+    }
+
+    fn store(&mut self, key: &str, data: &[u8]) -> io::Result<()> {
+        fs::write(key, data)
+    }
+
+    fn remove(&mut self, key: &str) -> io::Result<()> {
+        fs::remove_file(key)
+    }
+}
+
+/// Keeps data in memory, e.g. for headless runs that must not leave saves
+/// behind.
+pub struct MemoryStorage {
+    /// Data stored under each key
+    data: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryStorage {
+    /// Creates a new, empty `MemoryStorage`.
+    pub fn new() -> Self {
+        MemoryStorage {
+            data: HashMap::new(),
+        }
+    }
+}
+
+impl StorageBackend for MemoryStorage {
+    fn load(&self, key: &str) -> io::Result<Vec<u8>> {
+        self.data
+            .get(key)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, key.to_string()))
+    }
+
+    fn store(&mut self, key: &str, data: &[u8]) -> io::Result<()> {
+        self.data.insert(key.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> io::Result<()> {
+        self.data
+            .remove(key)
+            .map(|_| ())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, key.to_string()))
+    }
+}
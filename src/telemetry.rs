@@ -0,0 +1,90 @@
+use cpu::CYCLES_PER_FRAME;
+use timeline::LINE_CLOCKS;
+
+/// Work done by the core in a frame.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Counters {
+    /// Instructions executed
+    pub instructions: u32,
+    /// Scanlines rendered
+    pub scanlines: u32,
+    /// OAM DMA transfers started
+    pub dma_transfers: u32,
+    /// Switchable ROM bank changes between instructions
+    pub bank_switches: u32,
+    /// Stereo audio samples produced
+    pub samples: u32,
+}
+
+/// Per-frame counters of the work done by the core, for finding out why a
+/// game runs slow.
+#[derive(Clone)]
+pub struct Telemetry {
+    /// Clocks since the start of the current frame
+    clock: u32,
+    /// Switchable ROM bank when the last instruction was executed
+    last_bank: usize,
+    /// Counters of the current frame
+    current: Counters,
+    /// Counters of the last complete frame
+    last_frame: Counters,
+}
+
+impl Telemetry {
+    /// Creates a new `Telemetry` with all counters zeroed.
+    pub fn new() -> Self {
+        Telemetry {
+            clock: 0,
+            last_bank: 1,
+            current: Counters::default(),
+            last_frame: Counters::default(),
+        }
+    }
+
+    /// Counts an instruction executed with `bank` mapped to 0x4000-0x7fff.
+    pub fn count_instruction(&mut self, bank: usize) {
+        self.current.instructions += 1;
+
+        if bank != self.last_bank {
+            self.current.bank_switches += 1;
+            self.last_bank = bank;
+        }
+    }
+
+    /// Counts a rendered scanline.
+    pub fn count_scanline(&mut self) {
+        self.current.scanlines += 1;
+    }
+
+    /// Counts an OAM DMA transfer.
+    pub fn count_dma(&mut self) {
+        self.current.dma_transfers += 1;
+    }
+
+    /// Counts produced stereo audio samples.
+    pub fn count_samples(&mut self, samples: usize) {
+        self.current.samples += samples as u32;
+    }
+
+    /// Starts a new frame. Called when the PPU enters OAM Search on line 0.
+    pub fn start_frame(&mut self) {
+        self.last_frame = self.current;
+        self.current = Counters::default();
+        self.clock = 0;
+    }
+
+    /// Progresses the clock for a given number of ticks.
+    pub fn update(&mut self, tick: u8) {
+        self.clock += tick as u32;
+
+        // The LCD is off, so split frames by time
+        if self.clock >= CYCLES_PER_FRAME + LINE_CLOCKS {
+            self.start_frame();
+        }
+    }
+
+    /// Returns the counters of the last complete frame.
+    pub fn last_frame(&self) -> Counters {
+        self.last_frame
+    }
+}
@@ -0,0 +1,105 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Directory test ROMs are cached in by default, where the smoke test
+/// scripts look for them.
+const DEFAULT_DIR: &str = "tests/smoke/roms";
+
+/// Test ROM archives as (directory name, URL). Zip archives are extracted
+/// flat, tarballs without their top-level directory.
+const ARCHIVES: [(&str, &str); 4] = [
+    (
+        "cpu_instrs",
+        "https://gbdev.gg8.se/files/roms/blargg-gb-tests/cpu_instrs.zip",
+    ),
+    (
+        "instr_timing",
+        "https://gbdev.gg8.se/files/roms/blargg-gb-tests/instr_timing.zip",
+    ),
+    (
+        "mem_timing",
+        "https://gbdev.gg8.se/files/roms/blargg-gb-tests/mem_timing.zip",
+    ),
+    (
+        "mooneye",
+        "https://gekkio.fi/files/mooneye-test-suite/mts-20240926-1737-443f6e1/mts-20240926-1737-443f6e1.tar.xz",
+    ),
+];
+
+/// Runs an external command, returning an error message if it fails.
+fn run(cmd: &mut Command) -> Result<(), String> {
+    match cmd.status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("{:?} exited with {}", cmd, status)),
+        Err(e) => Err(format!("Failed to run {:?}: {}", cmd, e)),
+    }
+}
+
+/// Downloads and extracts an archive into `dir/name`.
+fn fetch(dir: &Path, name: &str, url: &str) -> Result<(), String> {
+    let dest = dir.join(name);
+    let archive = dir.join(url.rsplit('/').next().unwrap());
+
+    run(Command::new("curl")
+        .arg("-fsSL")
+        .arg("-o")
+        .arg(&archive)
+        .arg(url))?;
+    fs::create_dir_all(&dest).map_err(|e| e.to_string())?;
+
+    if url.ends_with(".zip") {
+        run(Command::new("unzip")
+            .arg("-q")
+            .arg("-o")
+            .arg("-j")
+            .arg(&archive)
+            .arg("-d")
+            .arg(&dest))?;
+    } else {
+        run(Command::new("tar")
+            .arg("-xJf")
+            .arg(&archive)
+            .arg("--strip-components=1")
+            .arg("-C")
+            .arg(&dest))?;
+    }
+
+    fs::remove_file(&archive).map_err(|e| e.to_string())
+}
+
+/// Downloads test ROMs into a cache directory (`[<dir>]`), skipping those
+/// already present. Returns the exit code.
+pub fn main(args: &[String]) -> i32 {
+    let dir = Path::new(args.first().map(|s| s.as_str()).unwrap_or(DEFAULT_DIR));
+
+    if let Err(e) = fs::create_dir_all(dir) {
+        eprintln!("Failed to create {}: {}", dir.display(), e);
+        return 1;
+    }
+
+    let mut failed = 0;
+
+    for &(name, url) in ARCHIVES.iter() {
+        if dir.join(name).exists() {
+            println!("{} ... cached", name);
+            continue;
+        }
+
+        match fetch(dir, name, url) {
+            Ok(()) => println!("{} ... ok", name),
+            Err(e) => {
+                println!("{} ... FAILED ({})", name, e);
+                // Leave no partial directory behind so that a retry fetches it
+                let _ = fs::remove_dir_all(dir.join(name));
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        1
+    } else {
+        0
+    }
+}
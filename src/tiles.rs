@@ -0,0 +1,133 @@
+use std::fs::File;
+use std::io::{Read, Write};
+
+use png;
+use ppu::PPU;
+
+/// Number of tiles per row in images written by `tiles2png`.
+const TILES_PER_ROW: usize = 16;
+/// Brightness of color numbers 0-3 with the identity palette.
+const SHADES: [u8; 4] = [0xff, 0xaa, 0x55, 0x00];
+
+/// Prints usage of `gbr png2tiles` and `gbr tiles2png`.
+fn usage() -> i32 {
+    eprintln!("Usage: gbr png2tiles <image.png> -o <tiles.2bpp>");
+    eprintln!("       gbr tiles2png <tiles.2bpp> -o <image.png>");
+    1
+}
+
+/// Parses `<input> -o <output>`.
+fn parse_args(args: &[String]) -> Option<(&str, &str)> {
+    let mut input = None;
+    let mut output = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(args.next()?.as_str()),
+            _ if arg.starts_with('-') => return None,
+            _ => input = Some(arg.as_str()),
+        }
+    }
+
+    Some((input?, output?))
+}
+
+/// Returns the color number closest to a brightness.
+fn color_no(shade: u8) -> u8 {
+    ((0xff - shade as u16 + 0x2a) / 0x55) as u8
+}
+
+/// Converts a grayscale PNG into 2bpp tile data. Tiles are read left to
+/// right, top to bottom. Returns the exit code.
+pub fn png2tiles(args: &[String]) -> i32 {
+    let (input, output) = match parse_args(args) {
+        Some(files) => files,
+        None => return usage(),
+    };
+
+    let (pixels, width, height) = match png::load_gray(input) {
+        Ok(image) => image,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", input, e);
+            return 1;
+        }
+    };
+
+    if width % 8 != 0 || height % 8 != 0 {
+        eprintln!("Image size must be a multiple of 8 ({}x{})", width, height);
+        return 1;
+    }
+
+    let mut tiles = Vec::with_capacity(width * height / 4);
+
+    for tile_y in 0..height / 8 {
+        for tile_x in 0..width / 8 {
+            for y in 0..8 {
+                let start = (tile_y * 8 + y) * width + tile_x * 8;
+                let color_nos: Vec<u8> = pixels[start..start + 8]
+                    .iter()
+                    .map(|&shade| color_no(shade))
+                    .collect();
+
+                let (lo, hi) = PPU::encode_tile_row(&color_nos);
+                tiles.push(lo);
+                tiles.push(hi);
+            }
+        }
+    }
+
+    match File::create(output).and_then(|mut file| file.write_all(&tiles)) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Failed to write {}: {}", output, e);
+            1
+        }
+    }
+}
+
+/// Converts 2bpp tile data into a grayscale PNG, `TILES_PER_ROW` tiles wide.
+/// Returns the exit code.
+pub fn tiles2png(args: &[String]) -> i32 {
+    let (input, output) = match parse_args(args) {
+        Some(files) => files,
+        None => return usage(),
+    };
+
+    let mut tiles = Vec::new();
+    if let Err(e) = File::open(input).and_then(|mut file| file.read_to_end(&mut tiles)) {
+        eprintln!("Failed to read {}: {}", input, e);
+        return 1;
+    }
+
+    if tiles.is_empty() || tiles.len() % 16 != 0 {
+        eprintln!("Tile data must be a non-empty multiple of 16 bytes");
+        return 1;
+    }
+
+    let num_tiles = tiles.len() / 16;
+    let width = TILES_PER_ROW.min(num_tiles) * 8;
+    let height = ((num_tiles - 1) / TILES_PER_ROW + 1) * 8;
+    let mut pixels = vec![SHADES[0]; width * height];
+
+    for (i, tile) in tiles.chunks(16).enumerate() {
+        let (tile_x, tile_y) = (i % TILES_PER_ROW, i / TILES_PER_ROW);
+
+        for y in 0..8 {
+            let row = PPU::decode_tile_row((tile[y * 2], tile[y * 2 + 1]));
+
+            for x in 0..8 {
+                let ix = (tile_y * 8 + y) * width + tile_x * 8 + x;
+                pixels[ix] = SHADES[PPU::get_color_no(row, x as u8) as usize];
+            }
+        }
+    }
+
+    match png::save_gray(output, &pixels, width, height) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Failed to write {}: {}", output, e);
+            1
+        }
+    }
+}
@@ -0,0 +1,132 @@
+/// Length of a grain in seconds.
+const GRAIN_SECS: f32 = 0.02;
+/// Length of the crossfade between grains in seconds.
+const FADE_SECS: f32 = 0.005;
+
+/// Changes the tempo of the audio output without changing its pitch, so
+/// that audio stays listenable when the emulation runs faster or slower.
+///
+/// Resampling would shift the pitch by the speed. Instead, short grains of
+/// the input are played at their original rate, and the input between the
+/// starts of two grains is skipped (faster) or partly repeated (slower).
+/// Each grain is crossfaded with the continuation of the previous one to
+/// avoid clicks.
+pub struct TimeStretch {
+    /// Output frames per grain
+    grain: usize,
+    /// Frames crossfaded between grains
+    fade: usize,
+    /// Interleaved stereo input not consumed yet
+    input: Vec<f32>,
+    /// Position of the next grain in the input in frames
+    pos: f32,
+    /// Continuation of the last grain, faded out under the next one
+    tail: Vec<f32>,
+}
+
+impl TimeStretch {
+    /// Creates a new `TimeStretch` for a sample rate in Hz.
+    pub fn new(sample_rate: u32) -> Self {
+        TimeStretch {
+            grain: (sample_rate as f32 * GRAIN_SECS) as usize,
+            fade: (sample_rate as f32 * FADE_SECS) as usize,
+            input: Vec::new(),
+            pos: 0.0,
+            tail: Vec::new(),
+        }
+    }
+
+    /// Takes interleaved stereo samples generated at `speed` times the
+    /// normal speed and returns the samples to play at the normal rate.
+    /// Samples pass through unchanged at the normal speed.
+    pub fn process(&mut self, samples: &[f32], speed: f32) -> Vec<f32> {
+        if speed == 1.0 {
+            // Flush what is left from the last change of speed
+            let mut output = self.input.split_off(0);
+            output.extend_from_slice(samples);
+            self.pos = 0.0;
+            self.tail.clear();
+
+            return output;
+        }
+
+        self.input.extend_from_slice(samples);
+
+        let mut output = Vec::new();
+        let len = self.grain + self.fade;
+
+        while (self.pos as usize + len) * 2 <= self.input.len() {
+            let start = self.pos as usize * 2;
+            let grain = &self.input[start..start + len * 2];
+
+            for (i, &sample) in grain[..self.fade * 2].iter().enumerate() {
+                let w = (i / 2) as f32 / self.fade as f32;
+                let prev = self.tail.get(i).copied().unwrap_or(sample);
+                output.push(prev * (1.0 - w) + sample * w);
+            }
+            output.extend_from_slice(&grain[self.fade * 2..self.grain * 2]);
+            self.tail = grain[self.grain * 2..].to_vec();
+
+            self.pos += self.grain as f32 * speed;
+        }
+
+        // Drop the input before the next grain
+        let consumed = (self.pos as usize).min(self.input.len() / 2);
+        self.input.drain(..consumed * 2);
+        self.pos -= consumed as f32;
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns `frames` stereo frames of a 1 kHz sine at 48 kHz.
+    fn sine(frames: usize) -> Vec<f32> {
+        (0..frames * 2)
+            .map(|i| ((i / 2) as f32 * 2.0 * std::f32::consts::PI / 48.0).sin())
+            .collect()
+    }
+
+    #[test]
+    fn pass_through() {
+        let mut stretch = TimeStretch::new(48_000);
+        let input = sine(800);
+
+        assert_eq!(stretch.process(&input, 1.0), input);
+    }
+
+    #[test]
+    fn tempo() {
+        for &speed in [0.5, 2.0, 4.0].iter() {
+            let mut stretch = TimeStretch::new(48_000);
+            let mut output = Vec::new();
+
+            // One second of audio generated 800 frames at a time
+            for _ in 0..60 {
+                output.extend(stretch.process(&sine(800), speed));
+            }
+
+            let expected = 48_000.0 / speed;
+            let frames = (output.len() / 2) as f32;
+            assert!((frames - expected).abs() < 2_000.0, "{} {}", speed, frames);
+        }
+    }
+
+    #[test]
+    fn pitch() {
+        let mut stretch = TimeStretch::new(48_000);
+        let output = stretch.process(&sine(48_000), 2.0);
+
+        // A 1 kHz sine crosses zero upwards once per 48 frames
+        let crossings = output
+            .chunks(2)
+            .zip(output.chunks(2).skip(1))
+            .filter(|&(a, b)| a[0] < 0.0 && b[0] >= 0.0)
+            .count();
+        let expected = output.len() / 2 / 48;
+        assert!((crossings as isize - expected as isize).abs() < 20);
+    }
+}
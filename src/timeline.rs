@@ -0,0 +1,96 @@
+use std::mem;
+
+use cpu::CYCLES_PER_FRAME;
+use interrupt::Interrupt;
+
+/// Clocks in a scanline.
+pub const LINE_CLOCKS: u32 = 456;
+/// Clocks an OAM DMA transfer takes (160 M-cycles).
+pub const DMA_CLOCKS: u32 = 640;
+
+/// Event recorded on the timeline.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Event {
+    /// PPU entered a mode
+    Mode(u8),
+    /// Device requested an interrupt
+    Requested(Interrupt),
+    /// CPU called an interrupt service routine
+    Serviced(Interrupt),
+    /// OAM DMA transfer started
+    Dma,
+}
+
+/// Per-frame timeline of PPU modes, interrupts and DMA transfers, for
+/// finding STAT and IRQ timing problems.
+#[derive(Clone)]
+pub struct Timeline {
+    /// Events are recorded
+    enabled: bool,
+    /// Current PPU mode, to know the mode at the start of a frame
+    mode: u8,
+    /// Clocks since the start of the current frame
+    clock: u32,
+    /// Events of the current frame
+    events: Vec<(u32, Event)>,
+    /// Events of the last complete frame
+    last_frame: Vec<(u32, Event)>,
+}
+
+impl Timeline {
+    /// Creates a new, disabled `Timeline`.
+    pub fn new() -> Self {
+        Timeline {
+            enabled: false,
+            mode: 0,
+            clock: 0,
+            events: Vec::new(),
+            last_frame: Vec::new(),
+        }
+    }
+
+    /// Enables or disables recording.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if self.enabled != enabled {
+            self.enabled = enabled;
+            self.events.clear();
+            self.last_frame.clear();
+        }
+    }
+
+    /// Records an event at the current clock.
+    pub fn record(&mut self, event: Event) {
+        if let Event::Mode(mode) = event {
+            self.mode = mode;
+        }
+
+        if self.enabled {
+            self.events.push((self.clock, event));
+        }
+    }
+
+    /// Starts a new frame. Called when the PPU enters OAM Search on line 0.
+    pub fn start_frame(&mut self) {
+        self.last_frame = mem::take(&mut self.events);
+        self.clock = 0;
+
+        let mode = self.mode;
+        self.record(Event::Mode(mode));
+    }
+
+    /// Progresses the clock for a given number of ticks.
+    pub fn update(&mut self, tick: u8) {
+        self.clock += tick as u32;
+
+        // The LCD is off, so split frames by time
+        if self.clock >= CYCLES_PER_FRAME + LINE_CLOCKS {
+            self.start_frame();
+        }
+    }
+
+    /// Returns the events of the last complete frame with their clocks since
+    /// the start of the frame.
+    pub fn last_frame(&self) -> &[(u32, Event)] {
+        &self.last_frame
+    }
+}
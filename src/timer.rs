@@ -1,5 +1,10 @@
 use io_device::IODevice;
+use savestate::{Savestate, StateBuffer};
 
+/// Bit of the internal counter clocking the APU frame sequencer (DIV bit 4).
+const DIV_APU_BIT: u16 = 0x1000;
+
+#[derive(Clone)]
 pub struct Timer {
     /// Timer counter
     tima: u8,
@@ -11,6 +16,8 @@ pub struct Timer {
     counter: u16,
     /// Interrupt request
     pub irq: bool,
+    /// DIV bit 4 fell, which steps the APU frame sequencer
+    pub div_apu: bool,
 }
 
 impl Timer {
@@ -22,6 +29,7 @@ impl Timer {
             tac: 0,
             counter: 0,
             irq: false,
+            div_apu: false,
         }
     }
 }
@@ -30,7 +38,13 @@ impl IODevice for Timer {
     fn write(&mut self, addr: u16, val: u8) {
         match addr {
             // DIV
-            0xff04 => self.counter = 0,
+            0xff04 => {
+                // Resetting the counter is also a falling edge
+                if self.counter & DIV_APU_BIT > 0 {
+                    self.div_apu = true;
+                }
+                self.counter = 0;
+            }
             // TIMA
             0xff05 => self.tima = val,
             // TMA
@@ -60,6 +74,10 @@ impl IODevice for Timer {
 
         self.counter = self.counter.wrapping_add(tick as u16);
 
+        if counter_prev & DIV_APU_BIT > 0 && self.counter & DIV_APU_BIT == 0 {
+            self.div_apu = true;
+        }
+
         if self.tac & 4 > 0 {
             let divider = match self.tac & 3 {
                 0 => 10,
@@ -86,3 +104,14 @@ impl IODevice for Timer {
         }
     }
 }
+
+impl Savestate for Timer {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.u8(&mut self.tima);
+        buf.u8(&mut self.tma);
+        buf.u8(&mut self.tac);
+        buf.u16(&mut self.counter);
+        buf.bool(&mut self.irq);
+        buf.bool(&mut self.div_apu);
+    }
+}
@@ -0,0 +1,121 @@
+/// Registers that can be referenced in tracepoint messages.
+const REGS: [&str; 14] = [
+    "a", "f", "b", "c", "d", "e", "h", "l", "af", "bc", "de", "hl", "sp", "pc",
+];
+
+/// Value referenced in a tracepoint message.
+#[derive(Clone)]
+enum Operand {
+    /// Register (e.g. `{a}`, `{hl}`)
+    Reg(&'static str),
+    /// Byte at a fixed address (e.g. `{[c0a0]}`)
+    Mem(u16),
+    /// Byte pointed to by a 16-bit register (e.g. `{[hl]}`)
+    MemReg(&'static str),
+}
+
+/// Part of a tracepoint message.
+#[derive(Clone)]
+enum Segment {
+    Text(String),
+    Value(Operand),
+}
+
+/// Address that logs a formatted message when executed, without stopping
+/// emulation.
+#[derive(Clone)]
+pub struct Tracepoint {
+    /// Address of the traced instruction
+    pub addr: u16,
+    /// Message
+    segments: Vec<Segment>,
+}
+
+/// Looks up a register name.
+fn reg_name(name: &str) -> Option<&'static str> {
+    REGS.iter().cloned().find(|&r| r == name)
+}
+
+/// Parses a `{...}` expression.
+fn parse_operand(expr: &str) -> Result<Operand, String> {
+    let expr = expr.trim().to_lowercase();
+
+    if expr.starts_with('[') && expr.ends_with(']') {
+        let inner = &expr[1..expr.len() - 1];
+
+        return match reg_name(inner) {
+            Some(reg) if reg.len() == 2 => Ok(Operand::MemReg(reg)),
+            _ => u16::from_str_radix(inner, 16)
+                .map(Operand::Mem)
+                .map_err(|_| format!("Invalid address: {}", inner)),
+        };
+    }
+
+    reg_name(&expr)
+        .map(Operand::Reg)
+        .ok_or_else(|| format!("Unknown register: {}", expr))
+}
+
+impl Tracepoint {
+    /// Parses a tracepoint given as `<addr>:<message>`, e.g.
+    /// `4a2f:HP={[c0a0]} A={a} HL={hl}`. Registers in braces are replaced
+    /// by their values and `[...]` reads a byte from an address or from the
+    /// address in a 16-bit register.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let pos = spec
+            .find(':')
+            .ok_or_else(|| String::from("Expected <addr>:<message>"))?;
+        let addr = u16::from_str_radix(&spec[..pos], 16)
+            .map_err(|_| format!("Invalid address: {}", &spec[..pos]))?;
+
+        let mut segments = Vec::new();
+        let mut rest = &spec[pos + 1..];
+
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| String::from("Unclosed {"))?;
+
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_string()));
+            }
+            segments.push(Segment::Value(parse_operand(
+                &rest[start + 1..start + end],
+            )?));
+
+            rest = &rest[start + end + 1..];
+        }
+
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_string()));
+        }
+
+        Ok(Tracepoint { addr, segments })
+    }
+
+    /// Formats the message. `reg` returns the value of a register and `read`
+    /// reads a byte without side effects.
+    pub fn format<R, M>(&self, reg: R, read: M) -> String
+    where
+        R: Fn(&str) -> u16,
+        M: Fn(u16) -> u8,
+    {
+        let mut msg = String::new();
+
+        for segment in self.segments.iter() {
+            match *segment {
+                Segment::Text(ref text) => msg.push_str(text),
+                Segment::Value(Operand::Reg(name)) if name.len() == 1 => {
+                    msg.push_str(&format!("{:02x}", reg(name)))
+                }
+                Segment::Value(Operand::Reg(name)) => msg.push_str(&format!("{:04x}", reg(name))),
+                Segment::Value(Operand::Mem(addr)) => msg.push_str(&format!("{:02x}", read(addr))),
+                Segment::Value(Operand::MemReg(name)) => {
+                    msg.push_str(&format!("{:02x}", read(reg(name))))
+                }
+            }
+        }
+
+        msg
+    }
+}
@@ -0,0 +1,129 @@
+use std::fmt;
+
+use mmu::MMU;
+
+/// Comparison of a byte of memory against a value.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    /// Any of the bits of the value set
+    And,
+}
+
+/// Operators in the order they are tried when parsing, longer ones first.
+const OPS: [(&str, Op); 5] = [
+    ("==", Op::Eq),
+    ("!=", Op::Ne),
+    ("<", Op::Lt),
+    (">", Op::Gt),
+    ("&", Op::And),
+];
+
+/// Condition on a byte of memory, e.g. an in-game flag set when saving.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemCondition {
+    /// Address of the byte
+    addr: u16,
+    /// Comparison
+    op: Op,
+    /// Value compared with
+    value: u8,
+}
+
+impl MemCondition {
+    /// Parses a condition given as `<addr><op><value>` in hex, where `<op>`
+    /// is `==`, `!=`, `<`, `>` or `&` (any of the bits set), e.g. `c0a0==01`
+    /// or `d123&80`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (pos, token, op) = OPS
+            .iter()
+            .filter_map(|&(token, op)| spec.find(token).map(|pos| (pos, token, op)))
+            .min_by_key(|&(pos, _, _)| pos)
+            .ok_or_else(|| String::from("Expected <addr><op><value> (e.g. c0a0==01)"))?;
+
+        let addr = spec[..pos].trim().trim_start_matches("0x");
+        let addr =
+            u16::from_str_radix(addr, 16).map_err(|_| format!("Invalid address: {}", addr))?;
+        let value = spec[pos + token.len()..].trim().trim_start_matches("0x");
+        let value =
+            u8::from_str_radix(value, 16).map_err(|_| format!("Invalid value: {}", value))?;
+
+        Ok(MemCondition { addr, op, value })
+    }
+
+    /// Returns true if the condition holds.
+    pub fn is_met(&self, mmu: &MMU) -> bool {
+        let byte = mmu.peek(self.addr);
+
+        match self.op {
+            Op::Eq => byte == self.value,
+            Op::Ne => byte != self.value,
+            Op::Lt => byte < self.value,
+            Op::Gt => byte > self.value,
+            Op::And => byte & self.value > 0,
+        }
+    }
+}
+
+impl fmt::Display for MemCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let token = OPS.iter().find(|&&(_, op)| op == self.op).unwrap().0;
+
+        write!(f, "{:04x}{}{:02x}", self.addr, token, self.value)
+    }
+}
+
+/// Fast forwards until a memory condition becomes true, e.g. to grind until
+/// the next save point. Only a change from false to true counts, so a flag
+/// that is already set when arming does not stop it right away.
+pub struct TurboUntil {
+    /// Condition that stops fast forwarding
+    condition: MemCondition,
+    /// Waiting for the condition
+    armed: bool,
+    /// The condition held when last checked
+    was_met: bool,
+}
+
+impl TurboUntil {
+    /// Creates a new, disarmed `TurboUntil`.
+    pub fn new(condition: MemCondition) -> Self {
+        TurboUntil {
+            condition,
+            armed: false,
+            was_met: false,
+        }
+    }
+
+    /// Returns the condition.
+    pub fn condition(&self) -> &MemCondition {
+        &self.condition
+    }
+
+    /// Starts waiting for the condition to become true.
+    pub fn arm(&mut self, mmu: &MMU) {
+        self.armed = true;
+        self.was_met = self.condition.is_met(mmu);
+    }
+
+    /// Checks the condition, once per frame. Returns true, disarming, when
+    /// it has become true since the last check.
+    pub fn check(&mut self, mmu: &MMU) -> bool {
+        if !self.armed {
+            return false;
+        }
+
+        let met = self.condition.is_met(mmu);
+        let became_true = met && !self.was_met;
+
+        self.was_met = met;
+        if became_true {
+            self.armed = false;
+        }
+
+        became_true
+    }
+}
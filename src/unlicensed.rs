@@ -0,0 +1,92 @@
+use catridge_info::CatridgeInfo;
+use mbc::{Mbc, Memory};
+use savestate::{Savestate, StateBuffer};
+
+/// Unlicensed mappers, which do not declare themselves in the header.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Unlicensed {
+    /// Wisdom Tree: writing to 0x0000-0x3fff maps the 32KB bank given by
+    /// the lower 8 bits of the address to 0x0000-0x7fff
+    WisdomTree,
+}
+
+/// Returns true if a ROM uses a mapper.
+type Rule = fn(&[u8], &CatridgeInfo) -> bool;
+
+/// Rules detecting unlicensed mappers from a ROM, checked in order.
+const RULES: [(Unlicensed, Rule); 1] = [(Unlicensed::WisdomTree, is_wisdom_tree)];
+
+/// Returns true if a ROM looks like a Wisdom Tree game: a ROM only header
+/// on a ROM larger than 32KB, with the publisher name in the first bank.
+fn is_wisdom_tree(rom: &[u8], info: &CatridgeInfo) -> bool {
+    let rom_only = info.mbc_type == 0x00 || info.mbc_type == 0xc0;
+    let bank0 = &rom[..rom.len().min(0x4000)];
+
+    rom_only
+        && rom.len() > 0x8000
+        && (contains(bank0, b"WISDOM TREE") || contains(bank0, b"WISDOM\x00TREE"))
+}
+
+/// Returns true if `haystack` contains `needle`.
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+/// Detects an unlicensed mapper.
+pub fn detect(rom: &[u8], info: &CatridgeInfo) -> Option<Unlicensed> {
+    RULES
+        .iter()
+        .find(|&&(_, rule)| rule(rom, info))
+        .map(|&(mapper, _)| mapper)
+}
+
+/// Wisdom Tree mapper.
+#[derive(Clone)]
+pub struct WisdomTree {
+    /// 32KB ROM bank number
+    bank_no: u8,
+}
+
+impl WisdomTree {
+    /// Creates a new `WisdomTree`.
+    pub fn new() -> Self {
+        WisdomTree { bank_no: 0 }
+    }
+}
+
+impl Mbc for WisdomTree {
+    fn write(&mut self, _mem: &mut Memory, addr: u16, _val: u8) {
+        // The bank is selected by the address, not the value
+        if let 0x0000..=0x3fff = addr {
+            self.bank_no = addr as u8;
+        }
+    }
+
+    fn read(&self, mem: &Memory, addr: u16) -> Option<u8> {
+        match addr {
+            // 32KB ROM bank
+            0x0000..=0x3fff => Some(mem.read_rom(self.bank_no as usize * 2, addr)),
+            0x4000..=0x7fff => Some(mem.read_rom(self.rom_bank_no(), addr)),
+            // No RAM
+            _ => None,
+        }
+    }
+
+    fn rom_bank_no(&self) -> usize {
+        self.bank_no as usize * 2 + 1
+    }
+
+    fn reset(&mut self) {
+        self.bank_no = 0;
+    }
+
+    fn box_clone(&self) -> Box<dyn Mbc> {
+        Box::new(self.clone())
+    }
+}
+
+impl Savestate for WisdomTree {
+    fn savestate(&mut self, buf: &mut StateBuffer) {
+        buf.u8(&mut self.bank_no);
+    }
+}
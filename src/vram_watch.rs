@@ -0,0 +1,135 @@
+use std::fmt;
+
+/// Shades used to draw color numbers 0-3 as text.
+const SHADES: [char; 4] = ['.', '+', '*', '#'];
+
+/// A write to a watched VRAM address, with the affected tile before and
+/// after the write.
+#[derive(Clone)]
+pub struct VramWrite {
+    /// Address written to
+    pub addr: u16,
+    /// Previous value
+    pub old: u8,
+    /// New value
+    pub new: u8,
+    /// Color numbers of the affected tile before the write
+    pub old_tile: [u8; 64],
+    /// Color numbers of the affected tile after the write
+    pub new_tile: [u8; 64],
+    /// Tile map cells (map address, x, y) displaying the tile
+    pub cells: Vec<(u16, u8, u8)>,
+}
+
+/// Returns the tile number (0-383) a tile map entry refers to.
+fn map_entry_tile(tile_no: u8, lcdc: u8) -> u16 {
+    if lcdc & 0x10 > 0 {
+        tile_no as u16
+    } else {
+        (256 + tile_no as i8 as i16) as u16
+    }
+}
+
+/// Returns the color numbers of a tile.
+fn tile_pixels(vram: &[u8], tile: u16) -> [u8; 64] {
+    let mut pixels = [0; 64];
+
+    for y in 0..8 {
+        let lo = vram[(tile as usize) * 16 + y * 2];
+        let hi = vram[(tile as usize) * 16 + y * 2 + 1];
+
+        for x in 0..8 {
+            pixels[y * 8 + x] = (hi >> (7 - x) & 1) << 1 | (lo >> (7 - x) & 1);
+        }
+    }
+
+    pixels
+}
+
+/// Returns the tile map cells displaying a tile.
+fn tile_cells(vram: &[u8], tile: u16, lcdc: u8) -> Vec<(u16, u8, u8)> {
+    let mut cells = Vec::new();
+
+    for &map_base in &[0x1800, 0x1c00] {
+        for i in 0..0x400 {
+            if map_entry_tile(vram[map_base + i], lcdc) == tile {
+                cells.push((0x8000 + map_base as u16, (i & 0x1f) as u8, (i >> 5) as u8));
+            }
+        }
+    }
+
+    cells
+}
+
+impl VramWrite {
+    /// Describes a write of `new` to `addr`. `vram` holds the contents before
+    /// the write.
+    pub fn new(vram: &[u8], lcdc: u8, addr: u16, new: u8) -> Self {
+        let offset = (addr & 0x1fff) as usize;
+        let old = vram[offset];
+
+        let mut written = vram.to_vec();
+        written[offset] = new;
+
+        let (old_tile, new_tile, cells) = if offset < 0x1800 {
+            // Tile data
+            let tile = (offset >> 4) as u16;
+            (
+                tile_pixels(vram, tile),
+                tile_pixels(&written, tile),
+                tile_cells(vram, tile, lcdc),
+            )
+        } else {
+            // Tile map
+            let map_base = (offset & 0x1c00) as u16;
+            let i = offset & 0x3ff;
+            (
+                tile_pixels(vram, map_entry_tile(old, lcdc)),
+                tile_pixels(vram, map_entry_tile(new, lcdc)),
+                vec![(0x8000 + map_base, (i & 0x1f) as u8, (i >> 5) as u8)],
+            )
+        };
+
+        VramWrite {
+            addr,
+            old,
+            new,
+            old_tile,
+            new_tile,
+            cells,
+        }
+    }
+}
+
+impl fmt::Display for VramWrite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "VRAM write 0x{:04x}: 0x{:02x} -> 0x{:02x}",
+            self.addr, self.old, self.new
+        )?;
+        writeln!(f, "  old       new")?;
+
+        for y in 0..8 {
+            let old: String = self.old_tile[y * 8..y * 8 + 8]
+                .iter()
+                .map(|&c| SHADES[c as usize])
+                .collect();
+            let new: String = self.new_tile[y * 8..y * 8 + 8]
+                .iter()
+                .map(|&c| SHADES[c as usize])
+                .collect();
+            writeln!(f, "  {}  {}", old, new)?;
+        }
+
+        write!(f, "  cells:")?;
+        if self.cells.is_empty() {
+            write!(f, " none")?;
+        }
+        for &(map, x, y) in &self.cells {
+            write!(f, " 0x{:04x}({},{})", map, x, y)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+
+/// Size of the RIFF and fmt headers before the sample data.
+const HEADER_SIZE: u32 = 44;
+
+/// Writes 16-bit PCM samples to a WAV file as they are produced. The sizes in
+/// the header are filled in by `finish`.
+pub struct WavWriter {
+    /// Output file
+    file: BufWriter<File>,
+    /// Name of the output file
+    fname: String,
+    /// Bytes of sample data written
+    data_size: u32,
+}
+
+impl WavWriter {
+    /// Creates a WAV file of interleaved samples.
+    pub fn create(fname: &str, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(fname)?);
+
+        let block_align = channels * 2;
+
+        file.write_all(b"RIFF")?;
+        // Size of the rest of the file, filled in by `finish`
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(b"WAVE")?;
+
+        file.write_all(b"fmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        // PCM
+        file.write_all(&1u16.to_le_bytes())?;
+        file.write_all(&channels.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&16u16.to_le_bytes())?;
+
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter {
+            file,
+            fname: fname.to_string(),
+            data_size: 0,
+        })
+    }
+
+    /// Returns the name of the output file.
+    pub fn fname(&self) -> &str {
+        &self.fname
+    }
+
+    /// Appends samples in the range -1.0 to 1.0.
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        for &sample in samples {
+            // Casts saturate, so out of range samples are clipped
+            let sample = (sample * i16::MAX as f32) as i16;
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+
+        self.data_size += samples.len() as u32 * 2;
+
+        Ok(())
+    }
+
+    /// Fills in the sizes in the header and closes the file.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file
+            .write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(HEADER_SIZE as u64 - 4))?;
+        self.file.write_all(&self.data_size.to_le_bytes())?;
+
+        self.file.flush()
+    }
+}